@@ -0,0 +1,190 @@
+//! Manual NAT traversal test tool.
+//!
+//! Run one instance on each side (exactly one of them with `--controlling`), then copy the
+//! description printed by each instance into the other one. Once connected, lines typed into
+//! either instance are sent to the other one, which echoes them back.
+//!
+//! ```text
+//! ice-punch [--controlling] [--stun <ip:port>] [--turn <ip:port> <username> <password>]
+//! ```
+use futures::{SinkExt, StreamExt};
+use glib::{MainContext, MainLoop};
+use libnice::ice::{Agent, Candidate, ComponentState, RelayServer, RelayType};
+use libnice::sdp::IceAttribute;
+use std::ffi::CString;
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const ECHO_PREFIX: &str = "echo: ";
+
+struct Args {
+    controlling: bool,
+    stun: Option<SocketAddr>,
+    turn: Option<RelayServer>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        controlling: false,
+        stun: None,
+        turn: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    let parse_addr = |value: Option<String>| -> Result<SocketAddr, String> {
+        let value = value.ok_or("missing address")?;
+        value.parse().map_err(|_| format!("invalid address: {}", value))
+    };
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--controlling" => args.controlling = true,
+            "--stun" => args.stun = Some(parse_addr(iter.next())?),
+            "--turn" => {
+                let addr = parse_addr(iter.next())?;
+                let username = iter.next().ok_or("missing TURN username")?;
+                let password = iter.next().ok_or("missing TURN password")?;
+                args.turn = Some(RelayServer::new(addr, username, password, RelayType::Udp));
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok(args)
+}
+
+/// Encodes the local credentials and candidates into a single copy-pasteable line.
+fn encode_description(ufrag: &str, pwd: &str, candidates: &[Candidate]) -> String {
+    let mut description = format!("{} {}\n", ufrag, pwd);
+    for candidate in candidates {
+        description += &format!("{}\n", IceAttribute::Candidate(candidate.clone()));
+    }
+    base64::encode(description)
+}
+
+fn decode_description(line: &str) -> Result<(String, String, Vec<Candidate>), String> {
+    let description = base64::decode(line.trim()).map_err(|err| err.to_string())?;
+    let description = String::from_utf8(description).map_err(|err| err.to_string())?;
+    let mut lines = description.lines();
+    let mut credentials = lines.next().ok_or("empty description")?.split(' ');
+    let ufrag = credentials.next().ok_or("missing ufrag")?.to_owned();
+    let pwd = credentials.next().ok_or("missing pwd")?.to_owned();
+    let candidates = lines
+        .filter_map(|line| match IceAttribute::parse(line) {
+            Some(IceAttribute::Candidate(candidate)) => Some(candidate),
+            _ => None,
+        })
+        .collect();
+    Ok((ufrag, pwd, candidates))
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: ice-punch [--controlling] [--stun <ip:port>] [--turn <ip:port> <username> <password>]");
+            std::process::exit(1);
+        }
+    };
+
+    let ctx = MainContext::new();
+    let main_loop = MainLoop::new(Some(&ctx), false);
+    let main_loop_clone = main_loop.clone();
+    std::thread::spawn(move || {
+        if !main_loop_clone.get_context().acquire() {
+            panic!("failed to acquire main loop");
+        }
+        main_loop_clone.run();
+    });
+
+    // Read stdin on a separate thread, it is used for both the description and the data
+    let (mut stdin_sender, mut stdin) = futures::channel::mpsc::channel::<String>(16);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let line = line.expect("failed to read stdin");
+            if futures::executor::block_on(stdin_sender.send(line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async move {
+        let agent = Agent::new_rfc5245(main_loop.get_context());
+        agent.set_controlling_mode(args.controlling);
+        if let Some(stun) = args.stun {
+            agent.set_stun_server(Some(stun)).expect("failed to set STUN server");
+        }
+
+        let mut builder = agent.stream_builder(1);
+        if let Some(turn) = args.turn {
+            builder.add_relay_server(turn);
+        }
+        let mut stream = builder.build().expect("failed to create stream");
+        tokio::spawn(async move {
+            if let Err(err) = agent.await {
+                eprintln!("Agent stopped: {}", err);
+            }
+        });
+
+        eprintln!("Gathering candidates...");
+        let candidates = stream.by_ref().collect::<Vec<_>>().await;
+        let description = encode_description(stream.get_local_ufrag(), stream.get_local_pwd(), &candidates);
+        eprintln!("Local description (paste into the other instance):");
+        println!("{}", description);
+
+        eprintln!("Paste the remote description:");
+        let (ufrag, pwd, remote_candidates) = loop {
+            let line = stdin.next().await.expect("stdin closed");
+            match decode_description(&line) {
+                Ok(remote) => break remote,
+                Err(err) => eprintln!("Invalid description ({}), try again:", err),
+            }
+        };
+        stream
+            .set_remote_credentials(CString::new(ufrag).unwrap(), CString::new(pwd).unwrap())
+            .unwrap();
+        for candidate in remote_candidates {
+            stream.add_remote_candidate(candidate).unwrap();
+        }
+        stream.set_remote_gathering_done().unwrap();
+
+        eprintln!("Connecting...");
+        let component = stream.into_components().remove(0);
+        let ready = component.wait_for_state(ComponentState::Ready);
+        let mut component = match tokio::time::timeout(Duration::from_secs(30), ready).await {
+            Ok(Some(component)) => component,
+            _ => {
+                eprintln!("Failed to connect");
+                std::process::exit(1);
+            }
+        };
+        eprintln!("Connected");
+
+        eprintln!("Type lines to send them, received lines are printed and echoed back.");
+        let mut writer = component.writer();
+        loop {
+            tokio::select! {
+                packet = component.next() => {
+                    let packet = match packet {
+                        Some(packet) => packet,
+                        None => break,
+                    };
+                    let text = String::from_utf8_lossy(&packet);
+                    println!("< {}", text);
+                    if !text.starts_with(ECHO_PREFIX) {
+                        let _ = std::io::Write::write(&mut writer, format!("{}{}", ECHO_PREFIX, text).as_bytes());
+                    }
+                }
+                line = stdin.next() => {
+                    let line = match line {
+                        Some(line) => line,
+                        None => break,
+                    };
+                    if std::io::Write::write(&mut writer, line.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
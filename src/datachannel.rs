@@ -0,0 +1,395 @@
+//! WebRTC data channels ([RFC 8831]) on top of an SCTP association.
+//!
+//! This crate implements neither DTLS nor SCTP. Instead, an SCTP implementation is plugged in via
+//! the [SctpAssociation] trait, usually running over a DTLS layer which itself runs over a
+//! connected component (see [DatagramTransport](crate::transport::DatagramTransport)).
+//! This module implements the Data Channel Establishment Protocol ([RFC 8832]) and the mapping of
+//! channels to SCTP streams.
+//!
+//! [RFC 8831]: https://tools.ietf.org/html/rfc8831
+//! [RFC 8832]: https://tools.ietf.org/html/rfc8832
+use std::collections::HashMap;
+use std::io;
+use std::task::{Context, Poll};
+
+const PPID_DCEP: u32 = 50;
+const PPID_STRING: u32 = 51;
+const PPID_BINARY: u32 = 53;
+const PPID_STRING_EMPTY: u32 = 56;
+const PPID_BINARY_EMPTY: u32 = 57;
+
+const DCEP_ACK: u8 = 0x02;
+const DCEP_OPEN: u8 = 0x03;
+
+const CHANNEL_UNORDERED: u8 = 0x80;
+const CHANNEL_RELIABLE: u8 = 0x00;
+const CHANNEL_PARTIAL_RELIABLE_REXMIT: u8 = 0x01;
+const CHANNEL_PARTIAL_RELIABLE_TIMED: u8 = 0x02;
+
+/// The reliability of a data channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reliability {
+    /// Messages are retransmitted until they have been received.
+    Reliable,
+    /// Messages are retransmitted at most the given number of times.
+    MaxRetransmits(u32),
+    /// Messages are retransmitted for at most the given number of milliseconds.
+    MaxPacketLifetime(u32),
+}
+
+/// The delivery properties of a data channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelOptions {
+    /// Whether messages are delivered in order.
+    pub ordered: bool,
+    /// How hard the association tries to deliver messages.
+    pub reliability: Reliability,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        Self {
+            ordered: true,
+            reliability: Reliability::Reliable,
+        }
+    }
+}
+
+/// A DCEP message, see [RFC 8832, section 5](https://tools.ietf.org/html/rfc8832#section-5).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DcepMessage {
+    /// DATA_CHANNEL_OPEN
+    Open {
+        /// The delivery properties of the channel.
+        options: ChannelOptions,
+        /// The priority of the channel.
+        priority: u16,
+        /// The label of the channel.
+        label: String,
+        /// The sub-protocol of the channel.
+        protocol: String,
+    },
+    /// DATA_CHANNEL_ACK
+    Ack,
+}
+
+impl DcepMessage {
+    /// Encodes this message.
+    ///
+    /// The label and protocol of an open message must not be longer than 65535 bytes, the
+    /// length fields would wrap otherwise.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            DcepMessage::Ack => vec![DCEP_ACK],
+            DcepMessage::Open { options, priority, label, protocol } => {
+                let (channel_type, parameter) = match options.reliability {
+                    Reliability::Reliable => (CHANNEL_RELIABLE, 0),
+                    Reliability::MaxRetransmits(n) => (CHANNEL_PARTIAL_RELIABLE_REXMIT, n),
+                    Reliability::MaxPacketLifetime(ms) => (CHANNEL_PARTIAL_RELIABLE_TIMED, ms),
+                };
+                let channel_type = if options.ordered { channel_type } else { channel_type | CHANNEL_UNORDERED };
+                let mut buf = Vec::with_capacity(12 + label.len() + protocol.len());
+                buf.push(DCEP_OPEN);
+                buf.push(channel_type);
+                buf.extend_from_slice(&priority.to_be_bytes());
+                buf.extend_from_slice(&parameter.to_be_bytes());
+                buf.extend_from_slice(&(label.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&(protocol.len() as u16).to_be_bytes());
+                buf.extend_from_slice(label.as_bytes());
+                buf.extend_from_slice(protocol.as_bytes());
+                buf
+            }
+        }
+    }
+
+    /// Parses a message, returning `None` if it is malformed or of an unknown type.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        match *buf.first()? {
+            DCEP_ACK => Some(DcepMessage::Ack),
+            DCEP_OPEN if buf.len() >= 12 => {
+                let u16_at = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+                let parameter = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+                let reliability = match buf[1] & !CHANNEL_UNORDERED {
+                    CHANNEL_RELIABLE => Reliability::Reliable,
+                    CHANNEL_PARTIAL_RELIABLE_REXMIT => Reliability::MaxRetransmits(parameter),
+                    CHANNEL_PARTIAL_RELIABLE_TIMED => Reliability::MaxPacketLifetime(parameter),
+                    _ => return None,
+                };
+                let label_end = 12 + u16_at(8) as usize;
+                let protocol_end = label_end + u16_at(10) as usize;
+                if buf.len() < protocol_end {
+                    return None;
+                }
+                Some(DcepMessage::Open {
+                    options: ChannelOptions {
+                        ordered: buf[1] & CHANNEL_UNORDERED == 0,
+                        reliability,
+                    },
+                    priority: u16_at(2),
+                    label: String::from_utf8(buf[12..label_end].to_vec()).ok()?,
+                    protocol: String::from_utf8(buf[label_end..protocol_end].to_vec()).ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A user message received on an SCTP stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SctpMessage {
+    /// The SCTP stream identifier.
+    pub stream_id: u16,
+    /// The payload protocol identifier.
+    pub ppid: u32,
+    /// The payload.
+    pub data: Vec<u8>,
+}
+
+/// An established SCTP association, implemented by an external SCTP stack.
+pub trait SctpAssociation: Send {
+    /// Sends a user message on the given stream.
+    fn send(&mut self, stream_id: u16, ppid: u32, data: &[u8], options: ChannelOptions) -> io::Result<()>;
+
+    /// Receives the next user message, returning `None` once the association has been closed.
+    fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<SctpMessage>>;
+}
+
+/// A message of a data channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataChannelMessage {
+    /// A text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+}
+
+/// An event of [DataChannels::poll_event].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DataChannelEvent {
+    /// A channel has been opened, either by the remote peer or by an acknowledgement of a channel
+    /// opened via [DataChannels::open].
+    Opened {
+        /// The id (SCTP stream identifier) of the channel.
+        id: u16,
+        /// The label of the channel.
+        label: String,
+        /// The sub-protocol of the channel.
+        protocol: String,
+    },
+    /// A message has been received on a channel.
+    Message {
+        /// The id of the channel.
+        id: u16,
+        /// The message.
+        message: DataChannelMessage,
+    },
+}
+
+struct Channel {
+    label: String,
+    protocol: String,
+    options: ChannelOptions,
+    acked: bool,
+}
+
+/// The data channels of an SCTP association.
+pub struct DataChannels<A> {
+    association: A,
+    channels: HashMap<u16, Channel>,
+    next_id: u16,
+}
+
+impl<A: SctpAssociation> DataChannels<A> {
+    /// Creates the data channels over an established association.
+    ///
+    /// `dtls_client` selects which half of the stream identifiers is used for locally opened
+    /// channels (even ones for the DTLS client, odd ones for the DTLS server).
+    pub fn new(association: A, dtls_client: bool) -> Self {
+        Self {
+            association,
+            channels: HashMap::new(),
+            next_id: if dtls_client { 0 } else { 1 },
+        }
+    }
+
+    /// Returns a reference to the underlying association.
+    pub fn association(&self) -> &A {
+        &self.association
+    }
+
+    /// Opens a new channel, returning its id.
+    ///
+    /// Messages may be sent right away, [DataChannelEvent::Opened] is emitted once the remote
+    /// peer has acknowledged the channel.
+    ///
+    /// Fails with [io::ErrorKind::InvalidInput] if the label or protocol is longer than 65535
+    /// bytes, which DCEP cannot encode.
+    pub fn open(&mut self, label: &str, protocol: &str, options: ChannelOptions) -> io::Result<u16> {
+        if label.len() > usize::from(u16::MAX) || protocol.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "label or protocol too long"));
+        }
+        let id = self.next_id;
+        if self.channels.contains_key(&id) || id == u16::MAX {
+            return Err(io::Error::new(io::ErrorKind::Other, "no more stream identifiers available"));
+        }
+        let open = DcepMessage::Open {
+            options,
+            priority: 0,
+            label: label.to_owned(),
+            protocol: protocol.to_owned(),
+        };
+        // DCEP messages are always sent reliably and in order
+        self.association.send(id, PPID_DCEP, &open.encode(), ChannelOptions::default())?;
+        self.channels.insert(id, Channel {
+            label: label.to_owned(),
+            protocol: protocol.to_owned(),
+            options,
+            acked: false,
+        });
+        self.next_id = id.saturating_add(2);
+        Ok(id)
+    }
+
+    /// Sends a message on a channel.
+    pub fn send(&mut self, id: u16, message: &DataChannelMessage) -> io::Result<()> {
+        let channel = self
+            .channels
+            .get(&id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown channel"))?;
+        // Empty messages are sent as a single zero byte with a dedicated PPID
+        let (ppid, data) = match message {
+            DataChannelMessage::Text(text) if text.is_empty() => (PPID_STRING_EMPTY, &[0u8][..]),
+            DataChannelMessage::Text(text) => (PPID_STRING, text.as_bytes()),
+            DataChannelMessage::Binary(data) if data.is_empty() => (PPID_BINARY_EMPTY, &[0u8][..]),
+            DataChannelMessage::Binary(data) => (PPID_BINARY, &data[..]),
+        };
+        self.association.send(id, ppid, data, channel.options)
+    }
+
+    /// Receives the next event, returning `None` once the association has been closed.
+    pub fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<DataChannelEvent>> {
+        loop {
+            let msg = match self.association.poll_recv(cx) {
+                Poll::Ready(Some(msg)) => msg,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Some(event) = self.handle_message(msg) {
+                return Poll::Ready(Some(event));
+            }
+        }
+    }
+
+    fn handle_message(&mut self, msg: SctpMessage) -> Option<DataChannelEvent> {
+        let id = msg.stream_id;
+        let message = match msg.ppid {
+            PPID_DCEP => return self.handle_dcep(id, &msg.data),
+            PPID_STRING => DataChannelMessage::Text(String::from_utf8(msg.data).ok()?),
+            PPID_BINARY => DataChannelMessage::Binary(msg.data),
+            PPID_STRING_EMPTY => DataChannelMessage::Text(String::new()),
+            PPID_BINARY_EMPTY => DataChannelMessage::Binary(Vec::new()),
+            _ => return None,
+        };
+        if !self.channels.contains_key(&id) {
+            return None;
+        }
+        Some(DataChannelEvent::Message { id, message })
+    }
+
+    fn handle_dcep(&mut self, id: u16, data: &[u8]) -> Option<DataChannelEvent> {
+        match DcepMessage::parse(data)? {
+            DcepMessage::Open { options, label, protocol, .. } => {
+                self.association
+                    .send(id, PPID_DCEP, &DcepMessage::Ack.encode(), ChannelOptions::default())
+                    .ok()?;
+                self.channels.insert(id, Channel {
+                    label: label.clone(),
+                    protocol: protocol.clone(),
+                    options,
+                    acked: true,
+                });
+                Some(DataChannelEvent::Opened { id, label, protocol })
+            }
+            DcepMessage::Ack => {
+                let channel = self.channels.get_mut(&id)?;
+                if channel.acked {
+                    return None;
+                }
+                channel.acked = true;
+                Some(DataChannelEvent::Opened {
+                    id,
+                    label: channel.label.clone(),
+                    protocol: channel.protocol.clone(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+    use futures::future::poll_fn;
+    use futures::StreamExt;
+
+    /// An "association" delivering messages to its peer immediately.
+    struct Loopback(mpsc::UnboundedSender<SctpMessage>, mpsc::UnboundedReceiver<SctpMessage>);
+
+    impl SctpAssociation for Loopback {
+        fn send(&mut self, stream_id: u16, ppid: u32, data: &[u8], _: ChannelOptions) -> io::Result<()> {
+            let _ = self.0.unbounded_send(SctpMessage { stream_id, ppid, data: data.to_vec() });
+            Ok(())
+        }
+
+        fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<SctpMessage>> {
+            self.1.poll_next_unpin(cx)
+        }
+    }
+
+    #[test]
+    fn dcep_open_round_trip() {
+        let open = DcepMessage::Open {
+            options: ChannelOptions {
+                ordered: false,
+                reliability: Reliability::MaxRetransmits(3),
+            },
+            priority: 256,
+            label: "chat".to_owned(),
+            protocol: "".to_owned(),
+        };
+        assert_eq!(DcepMessage::parse(&open.encode()), Some(open));
+        assert_eq!(DcepMessage::parse(&[DCEP_OPEN, 0, 0]), None);
+    }
+
+    #[test]
+    fn open_and_send() {
+        let (a_sender, b_receiver) = mpsc::unbounded();
+        let (b_sender, a_receiver) = mpsc::unbounded();
+        let mut client = DataChannels::new(Loopback(a_sender, a_receiver), true);
+        let mut server = DataChannels::new(Loopback(b_sender, b_receiver), false);
+
+        let id = client.open("chat", "", ChannelOptions::default()).unwrap();
+        assert_eq!(id, 0);
+        client.send(id, &DataChannelMessage::Text(String::new())).unwrap();
+
+        let opened = DataChannelEvent::Opened { id, label: "chat".to_owned(), protocol: "".to_owned() };
+        assert_eq!(block_on(poll_fn(|cx| server.poll_event(cx))), Some(opened.clone()));
+        let message = DataChannelEvent::Message { id, message: DataChannelMessage::Text(String::new()) };
+        assert_eq!(block_on(poll_fn(|cx| server.poll_event(cx))), Some(message));
+        assert_eq!(block_on(poll_fn(|cx| client.poll_event(cx))), Some(opened));
+    }
+
+    #[test]
+    fn rejects_overlong_label() {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut channels = DataChannels::new(Loopback(sender, receiver), true);
+        let label = "a".repeat(usize::from(u16::MAX) + 1);
+        let err = channels.open(&label, "", ChannelOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(channels.open("chat", "", ChannelOptions::default()).unwrap(), 0);
+    }
+}
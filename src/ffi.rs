@@ -16,7 +16,9 @@ use std::net::Ipv6Addr;
 use std::net::SocketAddr;
 use std::ops::DerefMut;
 use std::os::raw::c_char;
+use std::os::raw::c_int;
 use std::os::raw::c_uint;
+use std::sync::{Arc, Mutex};
 use webrtc_sdp::address::Address;
 use webrtc_sdp::attribute_type::SdpAttributeCandidate;
 use webrtc_sdp::attribute_type::SdpAttributeCandidateTcpType;
@@ -28,6 +30,9 @@ use glib::translate::*;
 use std::ptr;
 
 use libnice_sys as sys;
+
+mod error;
+pub use error::NiceError;
 use crate::platform as platform;
 use libnice_sys::{NiceAgentOption, NiceNominationMode};
 
@@ -75,7 +80,7 @@ pub enum NiceAgentProperty {
     StunInitialTimeout(u32),
     /// Read / Construct
     StunMaxRetransmissions(u32),
-    /// Read / Construct
+    /// Read / write
     StunPacingTimer(u32),
     /// Read / Construct
     StunReliableTimeout(u32),
@@ -155,6 +160,9 @@ glib_wrapper! {
     }
 }
 
+// All public functions of NiceAgent are thread-safe (they synchronize on the agent's mutex).
+// Callbacks are invoked on the agent's (or the attached) MainContext, which is why all callbacks
+// registered via this wrapper must be Send.
 unsafe impl Send for NiceAgent {}
 unsafe impl Sync for NiceAgent {}
 
@@ -223,6 +231,23 @@ impl NiceAgent {
         })
     }
 
+    /// Attaches a callback function to changes of the `controlling-mode` property, which libnice
+    /// also changes itself when resolving role conflicts.
+    pub fn on_controlling_mode_changed<F: Fn(bool) + Send + Sync + 'static>(
+        &mut self,
+        f: F,
+    ) -> BoolResult<SignalHandlerId> {
+        self.connect("notify::controlling-mode", false, move |values| {
+            let agent: NiceAgent = values[0].get().unwrap().unwrap();
+            if let Ok(NiceAgentProperty::ControllingMode(controlling)) =
+                agent.get_nice_property(NiceAgentPropertyType::ControllingMode)
+            {
+                f(controlling);
+            }
+            None
+        })
+    }
+
     /// Attaches a callback function to the `component-state-changed` signal.
     pub fn on_component_state_changed<F>(&mut self, f: F) -> BoolResult<SignalHandlerId>
     where
@@ -244,18 +269,42 @@ impl NiceAgent {
         unsafe { sys::nice_agent_set_software(self.to_glib_none().0, name.borrow().as_ptr()) }
     }
 
+    /// Adds a local address from which candidates will be gathered.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-add-local-address
+    pub fn add_local_address(&self, addr: impl Borrow<SocketAddr>) -> BoolResult<()> {
+        let mut raw: sys::NiceAddress = unsafe { std::mem::zeroed() };
+        to_nice_addr(addr.borrow(), &mut raw);
+        glib_result_from_gboolean!(
+            unsafe { sys::nice_agent_add_local_address(self.to_glib_none().0, &mut raw) },
+            "add_local_address failed",
+        )
+    }
+
     /// Adds a new stream to this agent and returns its id.
     /// [libnice] documentation.
     ///
     /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-add-stream
-    pub fn add_stream(&self, components: c_uint) -> BoolResult<c_uint> {
+    pub fn add_stream(&self, components: c_uint) -> Result<c_uint, NiceError> {
+        if components == 0 {
+            return Err(NiceError::InvalidArgument {
+                operation: "add_stream",
+                reason: "a stream needs at least one component",
+            });
+        }
         let id = unsafe { sys::nice_agent_add_stream(self.to_glib_none().0, components) };
         if id == 0 {
-            return Err(glib_bool_error!("add_stream failed"));
+            return Err(NiceError::Failed("add_stream"));
         }
         Ok(id)
     }
 
+    /// Returns whether this agent has a stream with the given id.
+    pub fn has_stream(&self, stream_id: c_uint) -> bool {
+        self.get_local_credentials(stream_id).is_ok()
+    }
+
     /// Removes a stream from this agent
     /// [libnice] documentation.
     ///
@@ -268,10 +317,26 @@ impl NiceAgent {
     /// [libnice] documentation.
     ///
     /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-gather-candidates
-    pub fn gather_candidates(&self, stream_id: c_uint) -> BoolResult<()> {
+    ///
+    /// Fails with [NiceError::NoHostCandidates] if the port range of a component is exhausted.
+    pub fn gather_candidates(&self, stream_id: c_uint) -> Result<(), NiceError> {
+        if unsafe { sys::nice_agent_gather_candidates(self.to_glib_none().0, stream_id) } != 0 {
+            return Ok(());
+        }
+        if !self.has_stream(stream_id) {
+            return Err(NiceError::UnknownStream(stream_id));
+        }
+        Err(NiceError::NoHostCandidates(stream_id))
+    }
+
+    /// Notifies the agent that the remote peer has finished gathering candidates for a stream.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-peer-candidate-gathering-done
+    pub fn peer_candidate_gathering_done(&self, stream_id: c_uint) -> BoolResult<()> {
         glib_result_from_gboolean!(
-            unsafe { sys::nice_agent_gather_candidates(self.to_glib_none().0, stream_id) },
-            "gather_candidates failed",
+            unsafe { sys::nice_agent_peer_candidate_gathering_done(self.to_glib_none().0, stream_id) },
+            "peer_candidate_gathering_done failed",
         )
     }
 
@@ -325,6 +390,52 @@ impl NiceAgent {
         Ok((ufrag, pwd))
     }
 
+    /// Sets the local ICE credentials of a stream, instead of the randomly generated ones.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-set-local-credentials
+    pub fn set_local_credentials(&self, stream_id: c_uint, ufrag: &CStr, pwd: &CStr) -> BoolResult<()> {
+        glib_result_from_gboolean!(
+            unsafe {
+                sys::nice_agent_set_local_credentials(
+                    self.to_glib_none().0,
+                    stream_id,
+                    ufrag.as_ptr(),
+                    pwd.as_ptr(),
+                )
+            },
+            "set_local_credentials failed",
+        )
+    }
+
+    /// Returns the local candidates of a stream component gathered so far.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-get-local-candidates
+    pub fn get_local_candidates(&self, stream_id: c_uint, component_id: c_uint) -> Vec<NiceCandidate> {
+        unsafe {
+            take_candidate_list(sys::nice_agent_get_local_candidates(
+                self.to_glib_none().0,
+                stream_id,
+                component_id,
+            ) as *mut glib::glib_sys::GSList)
+        }
+    }
+
+    /// Returns the remote candidates of a stream component, including peer reflexive ones.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-get-remote-candidates
+    pub fn get_remote_candidates(&self, stream_id: c_uint, component_id: c_uint) -> Vec<NiceCandidate> {
+        unsafe {
+            take_candidate_list(sys::nice_agent_get_remote_candidates(
+                self.to_glib_none().0,
+                stream_id,
+                component_id,
+            ) as *mut glib::glib_sys::GSList)
+        }
+    }
+
     /// Adds a remote ICE candidate for a particular stream component.
     /// [libnice] documentation.
     ///
@@ -382,6 +493,244 @@ impl NiceAgent {
         }
     }
 
+    /// Sets the TURN relay server used to gather relayed candidates for a stream component.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-set-relay-info
+    ///
+    /// May be called multiple times to add multiple relay servers. If the stream already started
+    /// gathering, libnice will immediately allocate a relayed candidate on the new server.
+    pub fn set_relay_info(
+        &self,
+        stream_id: c_uint,
+        component_id: c_uint,
+        server_ip: &CStr,
+        server_port: u16,
+        username: &CStr,
+        password: &CStr,
+        relay_type: NiceRelayType,
+    ) -> Result<(), NiceError> {
+        let server_addr = server_ip.to_string_lossy();
+        if server_addr.parse::<std::net::IpAddr>().is_err() {
+            return Err(NiceError::UnresolvedRelay(server_addr.into_owned()));
+        }
+        let res = unsafe {
+            sys::nice_agent_set_relay_info(
+                self.to_glib_none().0,
+                stream_id,
+                component_id,
+                server_ip.as_ptr(),
+                server_port.into(),
+                username.as_ptr(),
+                password.as_ptr(),
+                relay_type as i32,
+            )
+        };
+        if res != 0 {
+            return Ok(());
+        }
+        if !self.has_stream(stream_id) {
+            return Err(NiceError::UnknownStream(stream_id));
+        }
+        Err(NiceError::Failed("set_relay_info"))
+    }
+
+    /// Forgets all relay servers of a stream component.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-forget-relays
+    pub fn forget_relays(&self, stream_id: c_uint, component_id: c_uint) -> BoolResult<()> {
+        glib_result_from_gboolean!(
+            unsafe { sys::nice_agent_forget_relays(self.to_glib_none().0, stream_id, component_id) },
+            "forget_relays failed",
+        )
+    }
+
+    /// Returns the selected pair of a stream component as `(local, remote)` candidates.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-get-selected-pair
+    pub fn get_selected_pair(
+        &self,
+        stream_id: c_uint,
+        component_id: c_uint,
+    ) -> Option<(NiceCandidate, NiceCandidate)> {
+        let mut local: *mut sys::NiceCandidate = ptr::null_mut();
+        let mut remote: *mut sys::NiceCandidate = ptr::null_mut();
+        let res = unsafe {
+            sys::nice_agent_get_selected_pair(
+                self.to_glib_none().0,
+                stream_id,
+                component_id,
+                &mut local,
+                &mut remote,
+            )
+        };
+        if res == 0 {
+            return None;
+        }
+        unsafe { Some((NiceCandidate::from_glib_none(local), NiceCandidate::from_glib_none(remote))) }
+    }
+
+    /// Sets the selected pair of a stream component, identified by the foundations of its local
+    /// and remote candidate. This disables any further ICE processing for the component.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-set-selected-pair
+    pub fn set_selected_pair(
+        &self,
+        stream_id: c_uint,
+        component_id: c_uint,
+        local_foundation: &CStr,
+        remote_foundation: &CStr,
+    ) -> BoolResult<()> {
+        glib_result_from_gboolean!(
+            unsafe {
+                sys::nice_agent_set_selected_pair(
+                    self.to_glib_none().0,
+                    stream_id,
+                    component_id,
+                    local_foundation.as_ptr(),
+                    remote_foundation.as_ptr(),
+                )
+            },
+            "set_selected_pair failed",
+        )
+    }
+
+    /// Restarts ICE processing for all streams, generating new local credentials.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-restart
+    pub fn restart(&self) -> BoolResult<()> {
+        glib_result_from_gboolean!(
+            unsafe { sys::nice_agent_restart(self.to_glib_none().0) },
+            "restart failed",
+        )
+    }
+
+    /// Generates an SDP string with the credentials and candidates of all streams.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-generate-local-sdp
+    pub fn generate_local_sdp(&self) -> BoolResult<String> {
+        unsafe { take_gstring(sys::nice_agent_generate_local_sdp(self.to_glib_none().0), "generate_local_sdp failed") }
+    }
+
+    /// Generates an SDP string with the credentials and candidates of a stream, including the
+    /// `m=` and `c=` lines if `include_non_ice` is set.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-generate-local-stream-sdp
+    pub fn generate_local_stream_sdp(&self, stream_id: c_uint, include_non_ice: bool) -> BoolResult<String> {
+        unsafe {
+            take_gstring(
+                sys::nice_agent_generate_local_stream_sdp(self.to_glib_none().0, stream_id, include_non_ice.to_glib()),
+                "generate_local_stream_sdp failed",
+            )
+        }
+    }
+
+    /// Generates the SDP line of a candidate (`a=candidate:...`).
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-generate-local-candidate-sdp
+    pub fn generate_local_candidate_sdp(&self, candidate: &NiceCandidate) -> BoolResult<String> {
+        unsafe {
+            take_gstring(
+                sys::nice_agent_generate_local_candidate_sdp(self.to_glib_none().0, mut_override(candidate.to_glib_none().0)),
+                "generate_local_candidate_sdp failed",
+            )
+        }
+    }
+
+    /// Applies the credentials and candidates of an SDP string to the streams of this agent,
+    /// returning the number of candidates added.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-parse-remote-sdp
+    pub fn parse_remote_sdp(&self, sdp: &CStr) -> BoolResult<usize> {
+        let res = unsafe { sys::nice_agent_parse_remote_sdp(self.to_glib_none().0, sdp.as_ptr()) };
+        if res < 0 {
+            return Err(glib_bool_error!("parse_remote_sdp failed"));
+        }
+        Ok(res as usize)
+    }
+
+    /// Parses the SDP string of a single stream without applying it, returning its candidates
+    /// and its credentials as `(ufrag, pwd)`.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-parse-remote-stream-sdp
+    pub fn parse_remote_stream_sdp(
+        &self,
+        stream_id: c_uint,
+        sdp: &CStr,
+    ) -> BoolResult<(Vec<NiceCandidate>, CString, CString)> {
+        let mut ufrag_ptr: *mut c_char = ptr::null_mut();
+        let mut pwd_ptr: *mut c_char = ptr::null_mut();
+        let candidates = unsafe {
+            take_candidate_list(sys::nice_agent_parse_remote_stream_sdp(
+                self.to_glib_none().0,
+                stream_id,
+                sdp.as_ptr(),
+                &mut ufrag_ptr,
+                &mut pwd_ptr,
+            ) as *mut glib::glib_sys::GSList)
+        };
+        let ufrag = unsafe { take_gstring(ufrag_ptr, "parse_remote_stream_sdp failed") };
+        let pwd = unsafe { take_gstring(pwd_ptr, "parse_remote_stream_sdp failed") };
+        let to_cstring = |s: String| CString::new(s).expect("no null bytes in C string");
+        Ok((candidates, to_cstring(ufrag?), to_cstring(pwd?)))
+    }
+
+    /// Parses the SDP line of a single candidate of a stream, without adding it.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-parse-remote-candidate-sdp
+    pub fn parse_remote_candidate_sdp(&self, stream_id: c_uint, sdp: &CStr) -> Option<NiceCandidate> {
+        let raw = unsafe { sys::nice_agent_parse_remote_candidate_sdp(self.to_glib_none().0, stream_id, sdp.as_ptr()) };
+        if raw.is_null() {
+            return None;
+        }
+        Some(unsafe { NiceCandidate::from_glib_full(raw) })
+    }
+
+    /// Returns the native handles (file descriptors on Unix, `SOCKET`s on Windows) of the sockets
+    /// of a stream component, e.g. to set socket options such as the DSCP value.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-get-sockets
+    ///
+    /// The sockets are owned by libnice and must neither be closed nor used beyond the lifetime
+    /// of the stream.
+    pub fn get_sockets(&self, stream_id: c_uint, component_id: c_uint) -> Vec<c_int> {
+        unsafe {
+            let array = sys::nice_agent_get_sockets(self.to_glib_none().0, stream_id, component_id)
+                as *mut glib::glib_sys::GPtrArray;
+            if array.is_null() {
+                return Vec::new();
+            }
+            let sockets = std::slice::from_raw_parts((*array).pdata, (*array).len as usize)
+                .iter()
+                .map(|socket| g_socket_get_fd(*socket))
+                .collect();
+            glib::glib_sys::g_ptr_array_unref(array);
+            sockets
+        }
+    }
+
+    /// Restarts ICE processing for a single stream, generating new local credentials.
+    /// [libnice] documentation.
+    ///
+    /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-restart-stream
+    pub fn restart_stream(&self, stream_id: c_uint) -> BoolResult<()> {
+        glib_result_from_gboolean!(
+            unsafe { sys::nice_agent_restart_stream(self.to_glib_none().0, stream_id) },
+            "restart_stream failed",
+        )
+    }
+
     /// Sends data via the specified stream component.
     /// [libnice] documentation.
     ///
@@ -415,7 +764,7 @@ impl NiceAgent {
     /// Therefore, if you wish to replace the current callback, you must first drop the old handle
     /// and only then attach a new callback.
     pub fn attach_recv<F: FnMut(&[u8]) + Send + 'static>(
-        &mut self,
+        &self,
         stream_id: c_uint,
         component_id: c_uint,
         ctx: &MainContext,
@@ -448,13 +797,18 @@ impl NiceAgent {
         if res < 0 {
             return Err(glib_bool_error!("attach_recv failed"));
         }
-        Ok(AttachRecvHandle(
-            self.clone(),
+        let data = boxed_f.deref_mut() as *mut F as usize;
+        Ok(AttachRecvHandle {
+            agent: self.clone(),
             stream_id,
             component_id,
-            ctx.clone(),
-            boxed_f,
-        ))
+            attachment: Arc::new(Mutex::new(RecvAttachment {
+                ctx: Some(ctx.clone()),
+                func: Some(wrapper::<F>),
+                data,
+                callback: Some(boxed_f),
+            })),
+        })
     }
 
     /// Detaches a callback attached with [NiceAgent::attach_recv].
@@ -465,7 +819,7 @@ impl NiceAgent {
     /// Note that the callback itself is only dropped once the handle returned from
     /// [NiceAgent::attach_recv] is dropped.
     pub fn detach_recv(
-        &mut self,
+        &self,
         stream_id: c_uint,
         component_id: c_uint,
         ctx: &MainContext,
@@ -521,7 +875,7 @@ impl NiceAgent {
         )
     }
 
-    pub fn set_nice_property(&mut self, property: NiceAgentProperty) -> BoolResult<()> {
+    pub fn set_nice_property(&self, property: NiceAgentProperty) -> BoolResult<()> {
         nice_write_properties!(self, property,
             (ControllingMode, "controlling-mode"),
             (ForceRelay, "force-relay"),
@@ -551,20 +905,97 @@ impl NiceAgent {
 /// Handle keeping a callback alive which was previously attached with [NiceAgent::attach_recv].
 ///
 /// Dropping this handle will detach **any** currently attached callback for the same component.
+/// The handle may be dropped on any thread, the callback itself is then dropped on the
+/// MainContext it was attached to, so it cannot be dropped while it is being called.
 #[must_use = "when an AttachRecvHandle is dropped, it detaches the callback"]
-pub struct AttachRecvHandle(
-    NiceAgent,
-    c_uint,
-    c_uint,
-    MainContext,
-    Box<dyn std::any::Any + Send>,
-);
+pub struct AttachRecvHandle {
+    agent: NiceAgent,
+    stream_id: c_uint,
+    component_id: c_uint,
+    attachment: Arc<Mutex<RecvAttachment>>,
+}
+
+/// The state of an attached callback, shared with pending migrations.
+struct RecvAttachment {
+    /// The context the callback is currently attached to, `None` once the handle is dropped.
+    ctx: Option<MainContext>,
+    func: sys::NiceAgentRecvFunc,
+    /// The pointer passed to `func`, pointing into `callback`.
+    data: usize,
+    callback: Option<Box<dyn std::any::Any + Send>>,
+}
+
+impl AttachRecvHandle {
+    /// Returns the context the callback is attached to, or about to be migrated away from.
+    pub fn get_ctx(&self) -> MainContext {
+        let attachment = self.attachment.lock().unwrap();
+        attachment.ctx.clone().expect("attached until dropped")
+    }
+
+    /// Moves the callback to `ctx`, so it is called from the thread iterating `ctx` from now on.
+    ///
+    /// The callback is detached and re-attached on the current context, so no packet is
+    /// processed on both contexts at the same time. This happens right away if the current
+    /// context can be acquired by this thread and otherwise once its thread gets to it, packets
+    /// arriving in between may be lost. Migrating a handle which is dropped before that happens
+    /// has no effect.
+    pub fn migrate(&self, ctx: &MainContext) {
+        migrate_recv(self.agent.clone(), self.stream_id, self.component_id, self.attachment.clone(), ctx.clone());
+    }
+}
+
+/// Migrates an attached callback to `target`, from the thread of the context it is attached to.
+fn migrate_recv(
+    agent: NiceAgent,
+    stream_id: c_uint,
+    component_id: c_uint,
+    attachment: Arc<Mutex<RecvAttachment>>,
+    target: MainContext,
+) {
+    let current = match &attachment.lock().unwrap().ctx {
+        Some(ctx) => ctx.clone(),
+        None => return,
+    };
+    current.clone().invoke(move || {
+        let mut state = attachment.lock().unwrap();
+        match state.ctx.clone() {
+            Some(ctx) if ctx == current => {}
+            // Dropped, or migrated by an earlier request in the meantime
+            Some(_) => {
+                drop(state);
+                return migrate_recv(agent, stream_id, component_id, attachment, target);
+            }
+            None => return,
+        }
+        // No call is in progress, as this runs on the thread of the current context
+        let res = unsafe {
+            sys::nice_agent_attach_recv(
+                agent.to_glib_none().0,
+                stream_id,
+                component_id,
+                target.to_glib_none().0,
+                state.func,
+                state.data as glib::glib_sys::gpointer,
+            )
+        };
+        if res >= 0 {
+            state.ctx = Some(target);
+        }
+    });
+}
 
 impl Drop for AttachRecvHandle {
     fn drop(&mut self) {
-        self.0
-            .detach_recv(self.1, self.2, &self.3)
+        let (ctx, callback) = {
+            let mut attachment = self.attachment.lock().unwrap();
+            (attachment.ctx.take().expect("attached until dropped"), attachment.callback.take())
+        };
+        self.agent
+            .detach_recv(self.stream_id, self.component_id, &ctx)
             .expect("cannot continue safely when detach failed");
+        // libnice won't start new calls after detaching, but one may still be in progress on
+        // the context's thread. Calls directly if this thread owns (or can acquire) the context.
+        ctx.invoke(move || drop(callback));
     }
 }
 
@@ -584,6 +1015,10 @@ glib_wrapper! {
     }
 }
 
+// A NiceCandidate is plain data exclusively owned by the wrapper (copied when cloned).
+unsafe impl Send for NiceCandidate {}
+unsafe impl Sync for NiceCandidate {}
+
 impl NiceCandidate {
     /// Creates a new NiceCandidate.
     pub fn new(type_: NiceCandidateType) -> Self {
@@ -641,12 +1076,24 @@ impl NiceCandidate {
         raw.stream_id
     }
 
+    /// Sets the `stream_id` field.
+    pub fn set_stream_id(&mut self, stream_id: c_uint) {
+        let raw = unsafe { &mut *self.to_glib_none_mut().0 };
+        raw.stream_id = stream_id;
+    }
+
     /// Returns the `type` field.
     pub fn type_(&self) -> NiceCandidateType {
         let raw = unsafe { &*self.to_glib_none().0 };
         raw.type_.into()
     }
 
+    /// Sets the `type` field.
+    pub fn set_type(&mut self, type_: NiceCandidateType) {
+        let raw = unsafe { &mut *self.to_glib_none_mut().0 };
+        raw.type_ = type_ as i32;
+    }
+
     /// Returns the `transport` field.
     pub fn transport(&self) -> NiceCandidateTransport {
         let raw = unsafe { &*self.to_glib_none().0 };
@@ -677,6 +1124,15 @@ impl NiceCandidate {
         to_nice_addr(addr.borrow(), &mut raw.addr);
     }
 
+    /// Sets the `base_addr` field, `None` to clear it.
+    pub fn set_base_addr(&mut self, addr: Option<SocketAddr>) {
+        let raw = unsafe { &mut *self.to_glib_none_mut().0 };
+        match addr {
+            Some(addr) => to_nice_addr(&addr, &mut raw.base_addr),
+            None => unsafe { sys::nice_address_init(&mut raw.base_addr) },
+        }
+    }
+
     /// Returns the `priority` field.
     pub fn priority(&self) -> u32 {
         let raw = unsafe { &*self.to_glib_none().0 };
@@ -721,6 +1177,53 @@ impl NiceCandidate {
         }
     }
 
+    /// Returns the `username` field.
+    ///
+    /// Only used with the MSN and OC2007 compatibility modes, where each candidate has its own
+    /// credentials.
+    pub fn username(&self) -> Option<&CStr> {
+        let raw = unsafe { &*self.to_glib_none().0 };
+        if raw.username.is_null() {
+            return None;
+        }
+        Some(unsafe { CStr::from_ptr(raw.username) })
+    }
+
+    /// Sets the `username` field, see [NiceCandidate::username].
+    pub fn set_username(&mut self, username: Option<&CStr>) {
+        let raw = unsafe { &mut *self.to_glib_none_mut().0 };
+        unsafe { replace_gstring(&mut raw.username, username) };
+    }
+
+    /// Returns the `password` field, see [NiceCandidate::username].
+    pub fn password(&self) -> Option<&CStr> {
+        let raw = unsafe { &*self.to_glib_none().0 };
+        if raw.password.is_null() {
+            return None;
+        }
+        Some(unsafe { CStr::from_ptr(raw.password) })
+    }
+
+    /// Sets the `password` field, see [NiceCandidate::username].
+    pub fn set_password(&mut self, password: Option<&CStr>) {
+        let raw = unsafe { &mut *self.to_glib_none_mut().0 };
+        unsafe { replace_gstring(&mut raw.password, password) };
+    }
+
+    /// Returns the address and type of the `turn` field, i.e. the relay server which allocated a
+    /// relayed local candidate.
+    ///
+    /// There is no setter, as libnice only allows creating the relay server internally (see
+    /// [NiceAgent::set_relay_info]).
+    pub fn turn_server(&self) -> Option<(SocketAddr, NiceRelayType)> {
+        let raw = unsafe { &*self.to_glib_none().0 };
+        if raw.turn.is_null() {
+            return None;
+        }
+        let turn = unsafe { &*raw.turn };
+        Some((from_nice_addr(&turn.server)?, turn.type_.into()))
+    }
+
     /// Converts this candidate into an [SdpAttributeCandidate].
     pub fn to_sdp(&self) -> SdpAttributeCandidate {
         let address = self.addr();
@@ -739,12 +1242,7 @@ impl NiceCandidate {
             priority: u64::from(self.priority()),
             address: Address::Ip(address.ip()),
             port: u32::from(address.port()),
-            c_type: match self.type_() {
-                NiceCandidateType::Host => SdpAttributeCandidateType::Host,
-                NiceCandidateType::ServerReflexive => SdpAttributeCandidateType::Srflx,
-                NiceCandidateType::PeerReflexive => SdpAttributeCandidateType::Prflx,
-                NiceCandidateType::Relayed => SdpAttributeCandidateType::Relay,
-            },
+            c_type: self.type_().into(),
             raddr: base_addr.clone().map(|addr| Address::Ip(addr.ip().clone())),
             rport: base_addr.map(|addr| u32::from(addr.port())),
             tcp_type: match self.transport() {
@@ -774,6 +1272,17 @@ pub enum NiceCandidateType {
     Relayed = sys::NiceCandidateType_NICE_CANDIDATE_TYPE_RELAYED as isize,
 }
 
+impl From<NiceCandidateType> for SdpAttributeCandidateType {
+    fn from(c_type: NiceCandidateType) -> Self {
+        match c_type {
+            NiceCandidateType::Host => SdpAttributeCandidateType::Host,
+            NiceCandidateType::ServerReflexive => SdpAttributeCandidateType::Srflx,
+            NiceCandidateType::PeerReflexive => SdpAttributeCandidateType::Prflx,
+            NiceCandidateType::Relayed => SdpAttributeCandidateType::Relay,
+        }
+    }
+}
+
 impl From<sys::NiceCandidateType> for NiceCandidateType {
     fn from(raw: sys::NiceCandidateType) -> Self {
         match raw {
@@ -895,6 +1404,43 @@ pub enum NiceCompatibility {
     OC2007R2 = sys::NiceCompatibility_NICE_COMPATIBILITY_OC2007R2 as isize,
 }
 
+#[link(name = "gio-2.0")]
+extern "C" {
+    // Not part of glib-sys, the sockets are only used via their native handle
+    fn g_socket_get_fd(socket: glib::glib_sys::gpointer) -> c_int;
+}
+
+/// Converts a string allocated by libnice (transfer full) into a [String], failing if it is null.
+unsafe fn take_gstring(raw: *mut c_char, error: &str) -> BoolResult<String> {
+    if raw.is_null() {
+        return Err(glib_bool_error!("{}", error));
+    }
+    let string = CStr::from_ptr(raw).to_string_lossy().into_owned();
+    glib::glib_sys::g_free(raw as glib::glib_sys::gpointer);
+    Ok(string)
+}
+
+/// Takes ownership of a list of candidates returned by libnice (transfer full).
+unsafe fn take_candidate_list(list: *mut glib::glib_sys::GSList) -> Vec<NiceCandidate> {
+    let mut candidates = Vec::new();
+    let mut item = list;
+    while !item.is_null() {
+        candidates.push(NiceCandidate::from_glib_full((*item).data as *mut sys::NiceCandidate));
+        item = (*item).next;
+    }
+    glib::glib_sys::g_slist_free(list);
+    candidates
+}
+
+/// Replaces a string owned by a libnice struct (freed via `g_free`) with a copy of `value`.
+unsafe fn replace_gstring(field: &mut *mut c_char, value: Option<&CStr>) {
+    glib::glib_sys::g_free(*field as glib::glib_sys::gpointer);
+    *field = match value {
+        Some(value) => glib::glib_sys::g_strdup(value.as_ptr()),
+        None => ptr::null_mut(),
+    };
+}
+
 fn from_nice_addr(raw: &sys::NiceAddress) -> Option<SocketAddr> {
     unsafe {
         match i32::from(raw.s.addr.as_ref().sa_family) {
@@ -930,13 +1476,17 @@ fn to_nice_addr(addr: &SocketAddr, raw: &mut sys::NiceAddress) {
 
 #[cfg(test)]
 mod test {
-    use crate::ffi::{NiceAgent, NiceCompatibility, NiceAgentProperty, NiceAgentPropertyType};
+    use crate::ffi::{
+        NiceAgent, NiceAgentProperty, NiceAgentPropertyType, NiceCandidate, NiceCandidateType, NiceCompatibility, NiceError,
+        NiceRelayType,
+    };
     use glib::MainContext;
+    use std::ffi::CString;
 
     #[test]
     fn test_property_assign() {
         let ctx = MainContext::new();
-        let mut agent = NiceAgent::new(&ctx, NiceCompatibility::RFC5245);
+        let agent = NiceAgent::new(&ctx, NiceCompatibility::RFC5245);
 
         assert_eq!(agent.get_nice_property(NiceAgentPropertyType::ProxyUsername).unwrap(), NiceAgentProperty::ProxyUsername(None));
         //println!("Username: {:?}", agent.get_nice_property(NiceAgentPropertyType::ProxyUsername).unwrap());
@@ -944,4 +1494,48 @@ mod test {
         assert_eq!(agent.get_nice_property(NiceAgentPropertyType::ProxyUsername).unwrap(), NiceAgentProperty::ProxyUsername(Some(String::from("WolverinDEV"))));
         //println!("Username: {:?}", agent.get_nice_property(NiceAgentPropertyType::ProxyUsername).unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn credentials_and_sdp_roundtrip() {
+        let ctx = MainContext::new();
+        let agent = NiceAgent::new(&ctx, NiceCompatibility::RFC5245);
+        let stream_id = agent.add_stream(1).unwrap();
+        let ufrag = CString::new("abcd").unwrap();
+        let pwd = CString::new("0123456789abcdefghijkl").unwrap();
+        agent.set_local_credentials(stream_id, &ufrag, &pwd).unwrap();
+        assert_eq!(agent.get_local_credentials(stream_id).unwrap(), (ufrag.clone(), pwd.clone()));
+
+        let sdp = agent.generate_local_stream_sdp(stream_id, false).unwrap();
+        assert!(sdp.contains("a=ice-ufrag:abcd"));
+        let (candidates, parsed_ufrag, parsed_pwd) =
+            agent.parse_remote_stream_sdp(stream_id, &CString::new(sdp).unwrap()).unwrap();
+        assert!(candidates.is_empty());
+        assert_eq!((parsed_ufrag, parsed_pwd), (ufrag, pwd));
+
+        let mut candidate = NiceCandidate::new(NiceCandidateType::Host);
+        candidate.set_stream_id(stream_id);
+        candidate.set_component_id(1);
+        candidate.set_addr("192.168.1.2:5000".parse::<std::net::SocketAddr>().unwrap());
+        candidate.set_priority(2_130_706_431);
+        candidate.set_foundation(&CString::new("1").unwrap());
+        let line = agent.generate_local_candidate_sdp(&candidate).unwrap();
+        let parsed = agent.parse_remote_candidate_sdp(stream_id, &CString::new(line).unwrap()).unwrap();
+        assert_eq!(parsed.addr(), candidate.addr());
+        assert_eq!(parsed.priority(), candidate.priority());
+        assert!(agent.get_local_candidates(stream_id, 1).is_empty());
+    }
+
+    #[test]
+    fn reports_typed_errors() {
+        let ctx = MainContext::new();
+        let agent = NiceAgent::new(&ctx, NiceCompatibility::RFC5245);
+        assert!(matches!(agent.add_stream(0), Err(NiceError::InvalidArgument { .. })));
+        assert_eq!(agent.gather_candidates(42), Err(NiceError::UnknownStream(42)));
+        let stream_id = agent.add_stream(1).unwrap();
+        let credential = CString::new("turn").unwrap();
+        assert_eq!(
+            agent.set_relay_info(stream_id, 1, &CString::new("turn.example.com").unwrap(), 3478, &credential, &credential, NiceRelayType::TurnUdp),
+            Err(NiceError::UnresolvedRelay("turn.example.com".to_string()))
+        );
+    }
+}
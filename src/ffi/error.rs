@@ -0,0 +1,52 @@
+//! The error of the [NiceAgent](crate::ffi::NiceAgent) functions which libnice only reports as
+//! a boolean failure.
+use std::error::Error;
+use std::fmt;
+use std::os::raw::c_uint;
+
+/// Why a libnice function failed.
+///
+/// libnice neither returns a `GError` nor sets `errno` for these functions, so the cause is
+/// determined by checking the arguments and the state of the agent after the failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NiceError {
+    /// The stream does not exist (anymore).
+    UnknownStream(c_uint),
+    /// An argument is invalid, e.g. a stream without components.
+    InvalidArgument {
+        /// The libnice function.
+        operation: &'static str,
+        /// What is wrong with the argument.
+        reason: &'static str,
+    },
+    /// The address of a relay server is not an IP address, libnice does not resolve host names.
+    UnresolvedRelay(String),
+    /// No host candidate could be created for some component of the stream, because all ports
+    /// of its port range are in use or no local address could be bound.
+    NoHostCandidates(c_uint),
+    /// libnice failed for a reason which could not be determined.
+    Failed(&'static str),
+}
+
+impl fmt::Display for NiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NiceError::UnknownStream(stream_id) => write!(f, "unknown stream {}", stream_id),
+            NiceError::InvalidArgument { operation, reason } => write!(f, "{} failed: {}", operation, reason),
+            NiceError::UnresolvedRelay(addr) => write!(f, "relay server address {:?} is not an IP address", addr),
+            NiceError::NoHostCandidates(stream_id) => {
+                write!(f, "no host candidate could be created for stream {}", stream_id)
+            }
+            NiceError::Failed(operation) => write!(f, "{} failed", operation),
+        }
+    }
+}
+
+impl Error for NiceError {}
+
+impl From<NiceError> for glib::BoolError {
+    fn from(err: NiceError) -> Self {
+        glib_bool_error!("{}", err)
+    }
+}
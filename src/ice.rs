@@ -1,50 +1,223 @@
 //! See `test::connects_and_transmits_data` for a usage example.
 use crate::ffi;
 use futures::channel::mpsc;
-use futures::executor::block_on;
-use futures::io::{AsyncRead, AsyncWrite, ErrorKind};
+use futures::io::{AsyncRead, AsyncWrite};
 use futures::pin_mut;
 use futures::ready;
-use futures::sink::SinkExt;
 use futures::task::Poll;
 use futures::Sink;
 use futures::Stream as FuturesStream;
+use futures::StreamExt;
 use glib::MainContext;
-use std::collections::HashMap;
-use std::ffi::CString;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::ffi::{CStr, CString};
 use std::future::Future;
 use std::io;
 use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr};
 use std::ops::DerefMut;
-use std::os::raw::c_uint;
+use std::os::raw::{c_int, c_uint};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::task::Context;
+use std::time::{Duration, Instant, SystemTime};
+use webrtc_sdp::address::Address;
+
+mod agent_error;
+pub use agent_error::AgentError;
+mod agent_set;
+pub use agent_set::{AgentId, AgentSet, AgentSetEvent};
+
+mod arq;
+pub use arq::{ArqConfig, ReliableChannel};
+
+mod audit;
+pub use audit::{AuditOperation, AuditRecord, OperationError};
+
+mod build_error;
+pub use build_error::{StreamBuildError, StreamBuildPhase, StreamConfigError};
+
+mod candidate_validation;
+pub use candidate_validation::{CandidateError, CandidateValidation};
+
+mod candidates;
+pub use candidates::{AddressFamilyPolicy, CandidateSelector};
+use candidates::{CandidateChannel, CandidateConverter, CandidateEmitter, LocalCandidatePolicy};
+mod component_kind;
+pub use component_kind::{CandidateComponent, ComponentCandidate, ComponentKind, RTCP_COMPONENT_ID, RTP_COMPONENT_ID};
+mod context_health;
+use context_health::{ContextHealth, HEARTBEAT_PERIOD};
+
+#[cfg(any(test, feature = "testing"))]
+mod deterministic;
+#[cfg(any(test, feature = "testing"))]
+use deterministic::DeterministicIds;
+#[cfg(feature = "signaling")]
+mod connect;
+#[cfg(feature = "signaling")]
+pub use connect::connect_pair;
+mod diagnostics;
+pub use diagnostics::{
+    CandidateCounts, CandidateDelays, ComponentDiagnostics, GatheringReport, GatheringStatus, ReflexiveMapping,
+    SlowConsumerThreshold, StateTransition, TrafficStats,
+};
+use diagnostics::{CongestionDetector, GatheringProgress, SlowConsumerDetector, TrafficCounters, MAX_STATE_HISTORY};
+
+mod events;
+pub use events::{AgentEvent, RelayEvent, RoleChange, RoleChangeCause};
+use events::{identify_relay_server, EventSinks, RoleState};
+
+mod failure;
+pub use failure::FailureReason;
+use failure::FailureSignals;
+mod first_packet;
+mod gather_gate;
+mod health;
+pub use health::{AgentHealth, StreamStateCounts};
+use gather_gate::GatherGate;
+pub use first_packet::FirstPacketFuture;
+use first_packet::FirstPacketSignal;
+
+#[cfg(feature = "codec")]
+mod framed;
+#[cfg(feature = "codec")]
+pub use framed::ComponentFramed;
+
+mod memory;
+pub use memory::MemoryUsage;
+use memory::MemoryAccount;
+mod nat;
+pub use nat::{NatEstimate, NatMapping};
+mod network;
+pub use network::PlatformNetwork;
+mod outbound;
+use outbound::OutboundQueues;
+mod pacing;
+pub use pacing::{PacedWriter, PacingMode};
+mod reconnect;
+pub use reconnect::DisconnectPolicy;
+use reconnect::DisconnectWatch;
+mod renegotiation;
+pub use renegotiation::RemoteDescriptionUpdate;
+use renegotiation::DescriptionDiff;
+mod rtt;
+mod send_error;
+pub use send_error::SendError;
+mod socket_options;
+pub use socket_options::SocketOptions;
+mod state_queue;
+use state_queue::{StateReceiver, StateSender};
+mod transform;
+pub use transform::PacketTransform;
+mod user_data;
+pub use user_data::UserData;
+mod worker_pool;
+pub use worker_pool::WorkerPool;
+use user_data::UserDataSlot;
+pub use rtt::RttFuture;
+use rtt::RttProbes;
 
 pub use crate::ffi::BoolResult;
 pub use crate::ffi::NiceCompatibility;
 pub use crate::ffi::NiceComponentState as ComponentState;
+pub use crate::ffi::NiceRelayType as RelayType;
 pub use webrtc_sdp::attribute_type::SdpAttributeCandidate as Candidate;
-use crate::ffi::{NiceComponentState, NiceAgentProperty};
+pub use webrtc_sdp::attribute_type::SdpAttributeCandidateType as CandidateType;
+use crate::ffi::{NiceCandidateType, NiceAgentProperty, NiceAgentPropertyType};
+use crate::sdp::{IceAttribute, IceCredentials, IceOptions, SessionDescription};
+use crate::timer::{GlibTimerSource, Timer, TimerSource};
+use crate::transport::{check_datagram_size, DatagramTransport, TransportEvent};
+#[cfg(feature = "pcap")]
+use crate::pcap::{ComponentCapture, Direction};
 use libnice_sys::NiceAgentOption;
 
 type ComponentId = (c_uint, c_uint);
 
+/// Maximum number of packets sent per poll of the [Agent] before yielding to other tasks.
+const SEND_BUDGET: usize = 64;
+
+/// The SOFTWARE attribute sent by agents for which [Agent::set_software] has not been called.
+const DEFAULT_SOFTWARE: &str = concat!("rust-libnice/", env!("CARGO_PKG_VERSION"));
+
 /// A single, high-level ICE agent.
 ///
 /// **Note**: The agent implements [Future] and needs to be [`poll()`ed] for any of its [Stream]s
 ///           to make progress.
 ///
+/// # Threading
+///
+/// The agent is [Send] + [Sync] by construction: libnice itself is thread-safe and all state
+/// shared with the libnice callbacks (which run on the agent's [MainContext]) is synchronized.
+/// [Stream]s and [StreamComponent]s are [Send] and may be moved to other threads.
+/// Work which must happen on the agent's context (e.g. to be serialized with the callbacks) can be
+/// marshalled onto it via [Agent::invoke].
+///
+/// Streams can be built and options set via `&self`, so an agent can be shared (e.g. in an
+/// [Arc]) between tasks which build streams concurrently. A shared agent can be driven by
+/// polling `&Agent`, which also implements [Future].
+///
 /// [`poll()`ed]: Future::poll
 pub struct Agent {
     ctx: MainContext,
     agent: ffi::NiceAgent,
     msgs_sender: mpsc::UnboundedSender<ControlMsg>,
-    msgs: mpsc::UnboundedReceiver<ControlMsg>,
-
-    candidate_sinks: Arc<Mutex<HashMap<c_uint, mpsc::UnboundedSender<Candidate>>>>,
-    state_sinks: Arc<Mutex<HashMap<ComponentId, mpsc::Sender<ComponentState>>>>,
+    msgs: Mutex<mpsc::UnboundedReceiver<ControlMsg>>,
+    /// Outbound packets, separate from control messages so those are not delayed by a backlog.
+    data_sender: mpsc::UnboundedSender<DataMsg>,
+    data: Mutex<mpsc::UnboundedReceiver<DataMsg>>,
+
+    candidate_channels: Arc<Mutex<HashMap<c_uint, Arc<Mutex<CandidateChannel>>>>>,
+    state_sinks: Arc<Mutex<HashMap<ComponentId, StateSender>>>,
+
+    candidate_policies: Arc<Mutex<HashMap<c_uint, LocalCandidatePolicy>>>,
+    candidate_emitters: Arc<Mutex<HashMap<c_uint, CandidateEmitter>>>,
+    components: Arc<Mutex<HashMap<ComponentId, Arc<ComponentShared>>>>,
+
+    events: EventSinks,
+    /// Number of relay servers per component for which no allocation has been observed yet.
+    pending_relays: Arc<Mutex<HashMap<ComponentId, usize>>>,
+    gathering: Arc<Mutex<HashMap<c_uint, Arc<Mutex<GatheringProgress>>>>>,
+    gather_gate: Arc<Mutex<GatherGate>>,
+    /// Addresses of the relay servers configured per component.
+    relay_servers: Arc<Mutex<HashMap<ComponentId, Vec<SocketAddr>>>>,
+    /// The relay servers to fall back to once a component failed, see
+    /// [StreamBuilder::set_relay_fallback].
+    relay_fallbacks: Arc<Mutex<HashMap<c_uint, Vec<RelayServer>>>>,
+    /// The STUN servers left to probe per stream, see [StreamBuilder::set_stun_servers].
+    stun_selections: Mutex<HashMap<c_uint, StunSelection>>,
+    /// The streams probing a STUN server, mapped to the stream they probe for.
+    stun_probes: Arc<Mutex<HashMap<c_uint, c_uint>>>,
+    audit: EventSinks<AuditRecord>,
+    /// The error of the last operation which failed in libnice, see [Agent::health].
+    last_error: Mutex<Option<String>>,
+    timers: Mutex<Arc<dyn TimerSource>>,
+    workers: Mutex<Option<Arc<WorkerPool>>>,
+    /// The contexts of the streams whose packets are received on a context other than the
+    /// agent's, see [StreamBuilder::set_recv_context].
+    recv_contexts: Mutex<HashMap<c_uint, MainContext>>,
+    /// The options applied to the sockets of the streams, see [StreamBuilder::set_socket_options].
+    socket_options: Mutex<HashMap<c_uint, SocketOptions>>,
+    active_network: Mutex<Option<PlatformNetwork>>,
+    /// The addresses added via [Agent::add_local_address] and [Agent::set_active_network],
+    /// which libnice cannot remove.
+    local_addresses: Mutex<HashSet<IpAddr>>,
+    /// See [Agent::set_deterministic_seed].
+    #[cfg(any(test, feature = "testing"))]
+    deterministic: Mutex<Option<DeterministicIds>>,
+    stream_user_data: Mutex<HashMap<c_uint, Arc<UserDataSlot>>>,
+    role_changes: EventSinks<RoleChange>,
+    role_state: Arc<Mutex<RoleState>>,
+    outbound: Mutex<OutboundQueues>,
+    memory: Arc<MemoryAccount>,
+    context_health: Arc<ContextHealth>,
+    /// Set once the agent stopped operating, see [AgentError].
+    terminated: Mutex<Option<AgentError>>,
+    /// Keeps the heartbeat on the context running as long as the agent exists.
+    _heartbeat: Mutex<Timer>,
 }
 
 impl Agent {
@@ -66,61 +239,428 @@ impl Agent {
     }
 
     fn construct(ctx: MainContext, mut agent: ffi::NiceAgent) -> Self {
+        agent.set_software(CString::new(DEFAULT_SOFTWARE).expect("no null bytes"));
+
         // Channel for sending messages from streams to the agent
         let (msgs_sender, msgs) = mpsc::unbounded();
+        let (data_sender, data) = mpsc::unbounded();
+
+        let events = EventSinks::default();
+        let pending_relays: Arc<Mutex<HashMap<ComponentId, usize>>> = Default::default();
+        let relay_servers: Arc<Mutex<HashMap<ComponentId, Vec<SocketAddr>>>> = Default::default();
+        let gathering: Arc<Mutex<HashMap<c_uint, Arc<Mutex<GatheringProgress>>>>> = Default::default();
+        let gather_gate: Arc<Mutex<GatherGate>> = Default::default();
+        let stun_probes: Arc<Mutex<HashMap<c_uint, c_uint>>> = Default::default();
 
         // Channel for sending candidates to streams
-        let candidate_sinks: Arc<Mutex<HashMap<c_uint, mpsc::UnboundedSender<Candidate>>>> = Default::default();
-        let candidate_sinks_clone = Arc::clone(&candidate_sinks);
+        let candidate_channels: Arc<Mutex<HashMap<c_uint, Arc<Mutex<CandidateChannel>>>>> = Default::default();
+        let candidate_policies: Arc<Mutex<HashMap<c_uint, LocalCandidatePolicy>>> = Default::default();
+        let candidate_emitters: Arc<Mutex<HashMap<c_uint, CandidateEmitter>>> = Default::default();
+        let candidate_channels_clone = Arc::clone(&candidate_channels);
+        let events_clone = events.clone();
+        let pending_relays_clone = Arc::clone(&pending_relays);
+        let gathering_clone = Arc::clone(&gathering);
+        let stun_probes_clone = Arc::clone(&stun_probes);
+        let msgs_sender_clone = msgs_sender.clone();
         agent
             .on_new_candidate(move |candidate| {
-                let mut candidate_sinks = candidate_sinks_clone.lock().unwrap();
+                if candidate.type_() == NiceCandidateType::ServerReflexive {
+                    if let Some(stream_id) = stun_probes_clone.lock().unwrap().remove(&candidate.stream_id()) {
+                        let _ = msgs_sender_clone.unbounded_send(ControlMsg::StunProbed(stream_id, true));
+                    }
+                }
+                if candidate.type_() == NiceCandidateType::Relayed {
+                    let key = (candidate.stream_id(), candidate.component_id());
+                    if let Some(pending) = pending_relays_clone.lock().unwrap().get_mut(&key) {
+                        *pending = pending.saturating_sub(1);
+                    }
+                    events_clone.emit(AgentEvent::Relay {
+                        stream_id: key.0,
+                        component_id: key.1,
+                        event: RelayEvent::Allocated { relayed_addr: candidate.addr() },
+                    });
+                }
+
                 let stream_id = &candidate.stream_id();
-                let sink = candidate_sinks.get_mut(stream_id).expect(format!("received candidate for stream {} but it does not exists", stream_id).as_str());
-                if sink.unbounded_send(candidate.to_sdp()).is_err() {
-                    candidate_sinks.remove(stream_id);
+                if let Some(progress) = gathering_clone.lock().unwrap().get(stream_id) {
+                    progress.lock().unwrap().observe(
+                        candidate.component_id(),
+                        &candidate.type_().into(),
+                        candidate.addr(),
+                        candidate.base_addr(),
+                    );
+                }
+                if let Some(channel) = candidate_channels_clone.lock().unwrap().get(stream_id) {
+                    channel.lock().unwrap().send_raw(candidate);
                 }
             })
             .unwrap();
-        let candidate_sinks_clone = Arc::clone(&candidate_sinks);
+        let candidate_channels_clone = Arc::clone(&candidate_channels);
+        let events_clone = events.clone();
+        let pending_relays_clone = Arc::clone(&pending_relays);
+        let gathering_clone = Arc::clone(&gathering);
+        let gather_gate_clone = Arc::clone(&gather_gate);
+        let stun_probes_clone = Arc::clone(&stun_probes);
+        let msgs_sender_clone = msgs_sender.clone();
         agent
             .on_candidate_gathering_done(move |stream_id| {
                 /* TODO: Send a candidate gathering done event */
-                let mut candidate_sinks = candidate_sinks_clone.lock().unwrap();
-                candidate_sinks.remove(&stream_id).expect(format!("received candidate gathering done signal for stream {} but it does not exists", stream_id).as_str());
+                if let Some(probed_stream_id) = stun_probes_clone.lock().unwrap().remove(&stream_id) {
+                    let _ = msgs_sender_clone.unbounded_send(ControlMsg::StunProbed(probed_stream_id, false));
+                    return;
+                }
+                finish_candidates(&candidate_channels_clone, stream_id);
+                if let Some(progress) = gathering_clone.lock().unwrap().get(&stream_id) {
+                    progress.lock().unwrap().finish();
+                }
+                let dequeued = gather_gate_clone.lock().unwrap().finish(stream_id, Instant::now());
+                start_dequeued(&events_clone, &msgs_sender_clone, dequeued);
+
+                let mut pending_relays = pending_relays_clone.lock().unwrap();
+                for (&(relay_stream_id, component_id), pending) in pending_relays.iter_mut() {
+                    if relay_stream_id != stream_id || *pending == 0 {
+                        continue;
+                    }
+                    events_clone.emit(AgentEvent::Relay {
+                        stream_id,
+                        component_id,
+                        event: RelayEvent::AllocationFailed { missing: *pending },
+                    });
+                    *pending = 0;
+                }
+            })
+            .unwrap();
+        let components: Arc<Mutex<HashMap<ComponentId, Arc<ComponentShared>>>> = Default::default();
+        let components_clone = Arc::clone(&components);
+        let events_clone = events.clone();
+        let relay_servers_clone = Arc::clone(&relay_servers);
+        agent
+            .on_selected_pair(move |stream_id, component_id, local, remote| {
+                let local_relayed = local.type_() == NiceCandidateType::Relayed;
+                let remote_relayed = remote.type_() == NiceCandidateType::Relayed;
+                if let Some(shared) = components_clone.lock().unwrap().get(&(stream_id, component_id)) {
+                    shared.traffic.set_relayed(local_relayed || remote_relayed);
+                    #[cfg(feature = "pcap")]
+                    shared.capture.set_selected_pair(local.addr(), remote.addr());
+                }
+                if !local_relayed && !remote_relayed {
+                    return;
+                }
+                let relay_server = if local_relayed {
+                    relay_servers_clone
+                        .lock()
+                        .unwrap()
+                        .get(&(stream_id, component_id))
+                        .and_then(|servers| identify_relay_server(local.addr(), servers))
+                } else {
+                    None
+                };
+                events_clone.emit(AgentEvent::RelayedPairSelected {
+                    stream_id,
+                    component_id,
+                    local_relayed,
+                    remote_relayed,
+                    relay_server,
+                });
             })
             .unwrap();
 
         // Channel for sending state updates to components
-        let state_sinks: Arc<Mutex<HashMap<ComponentId, mpsc::Sender<ComponentState>>>> =
-            Default::default();
+        let state_sinks: Arc<Mutex<HashMap<ComponentId, StateSender>>> = Default::default();
         let state_sinks_clone = Arc::clone(&state_sinks);
+        let components_clone = Arc::clone(&components);
+        let events_clone = events.clone();
+        let relay_servers_clone = Arc::clone(&relay_servers);
+        let gathering_clone = Arc::clone(&gathering);
+        let relay_fallbacks: Arc<Mutex<HashMap<c_uint, Vec<RelayServer>>>> = Default::default();
+        let relay_fallbacks_clone = Arc::clone(&relay_fallbacks);
+        let msgs_sender_clone = msgs_sender.clone();
         agent
             .on_component_state_changed(move |stream_id, component_id, new_state| {
-                let mut state_sinks = state_sinks_clone.lock().unwrap();
                 let key = (stream_id, component_id);
-                let sink = state_sinks.get_mut(&key).expect(format!("received state change for stream {}.{} but it does not exists", stream_id, component_id).as_str());
-                if block_on(sink.send(new_state)).is_err() {
-                    state_sinks.remove(&key);
+                // Streams not created via a StreamBuilder (e.g. STUN probes) are not tracked
+                let shared = match components_clone.lock().unwrap().get(&key) {
+                    Some(shared) => shared.clone(),
+                    None => return,
+                };
+                let failure = if new_state == ComponentState::Failed {
+                    let mut signals = shared.failure_signals();
+                    if let Some(progress) = gathering_clone.lock().unwrap().get(&stream_id) {
+                        let progress = progress.lock().unwrap();
+                        // Before filtering, a candidate the stream did not emit is still paired
+                        let counts = progress.observed(component_id);
+                        signals.local_candidates =
+                            counts.host + counts.server_reflexive + counts.peer_reflexive + counts.relayed;
+                        signals.relayed_candidates = counts.relayed;
+                        signals.gathering_done = progress.status().done;
+                    }
+                    signals.relays_configured = relay_servers_clone.lock().unwrap().get(&key).map_or(0, Vec::len);
+                    Some(signals.classify())
+                } else {
+                    None
+                };
+                let transition = shared.record_state(new_state, failure);
+                events_clone.emit(AgentEvent::ComponentStateChanged { stream_id, component_id, transition });
+                deliver_transition(&state_sinks_clone, &shared, transition);
+
+                // Fall back to relaying once, unless relaying has been configured or used already
+                if new_state == ComponentState::Failed && !shared.traffic.is_relayed() {
+                    let relays_configured = relay_servers_clone
+                        .lock()
+                        .unwrap()
+                        .keys()
+                        .any(|(relay_stream_id, _)| *relay_stream_id == stream_id);
+                    let fallback = relay_fallbacks_clone.lock().unwrap().remove(&stream_id);
+                    if let (false, Some(servers)) = (relays_configured, fallback) {
+                        let _ = msgs_sender_clone.unbounded_send(ControlMsg::RelayFallback(stream_id, servers));
+                    }
+                }
+            })
+            .unwrap();
+
+        let role_changes: EventSinks<RoleChange> = EventSinks::default();
+        let role_state: Arc<Mutex<RoleState>> = Default::default();
+        let role_changes_clone = role_changes.clone();
+        let role_state_clone = Arc::clone(&role_state);
+        agent
+            .on_controlling_mode_changed(move |controlling| {
+                let mut state = role_state_clone.lock().unwrap();
+                // GObject notifies on every write of the property, even if it did not change
+                if state.controlling == controlling {
+                    return;
                 }
+                state.controlling = controlling;
+                let cause = match state.requested {
+                    Some(requested) if requested == controlling => RoleChangeCause::Api,
+                    _ => RoleChangeCause::Conflict,
+                };
+                role_changes_clone.emit(RoleChange { controlling, cause });
             })
             .unwrap();
 
+        let context_health = Arc::new(ContextHealth::new(Instant::now()));
+        let context_health_clone = context_health.clone();
+        let events_clone = events.clone();
+        // Always on the context itself, regardless of the configured timer source
+        let heartbeat = GlibTimerSource::new(ctx.clone()).start(
+            HEARTBEAT_PERIOD,
+            Box::new(move || {
+                if context_health_clone.on_iteration(Instant::now()) {
+                    events_clone.emit(AgentEvent::MainContextRecovered);
+                }
+                true
+            }),
+        );
+
         Agent {
+            context_health,
+            terminated: Mutex::new(None),
+            _heartbeat: Mutex::new(heartbeat),
+            role_changes,
+            role_state,
+            outbound: Mutex::new(OutboundQueues::default()),
+            memory: Arc::default(),
+            timers: Mutex::new(Arc::new(GlibTimerSource::new(ctx.clone()))),
+            workers: Mutex::new(None),
+            recv_contexts: Mutex::new(HashMap::new()),
+            socket_options: Mutex::new(HashMap::new()),
+            active_network: Mutex::new(None),
+            local_addresses: Mutex::new(HashSet::new()),
+            #[cfg(any(test, feature = "testing"))]
+            deterministic: Mutex::new(None),
+            stream_user_data: Mutex::new(HashMap::new()),
             ctx,
             agent,
             msgs_sender,
-            msgs,
-            candidate_sinks,
-            state_sinks
+            msgs: Mutex::new(msgs),
+            data_sender,
+            data: Mutex::new(data),
+            candidate_channels,
+            state_sinks,
+            candidate_policies,
+            candidate_emitters,
+            components,
+            events,
+            pending_relays,
+            relay_servers,
+            relay_fallbacks,
+            stun_selections: Mutex::new(HashMap::new()),
+            stun_probes,
+            gathering,
+            gather_gate,
+            audit: EventSinks::default(),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Returns a new subscription to the events of this agent.
+    ///
+    /// Events are only delivered to subscriptions which exist at the time they occur.
+    pub fn events(&self) -> mpsc::UnboundedReceiver<AgentEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a new subscription to the audit log of this agent, which records every operation
+    /// requested via its streams and components (and its result) once it reached libnice.
+    ///
+    /// Records are only delivered to subscriptions which exist at the time they occur.
+    /// As long as there are no subscriptions, no records are created.
+    pub fn audit_log(&self) -> mpsc::UnboundedReceiver<AuditRecord> {
+        self.audit.subscribe()
+    }
+
+    /// Returns the time since the [MainContext] of this agent was last iterated, with a
+    /// resolution of about a second.
+    pub fn since_main_context_iteration(&self) -> Duration {
+        self.context_health.since_last_iteration(Instant::now())
+    }
+
+    /// Sets after how long without an iteration of the [MainContext] an
+    /// [AgentEvent::MainContextStalled] event is emitted (default 5s).
+    ///
+    /// This is checked while the agent is `poll()`ed and has streams. The threshold should be
+    /// well above a second and above the longest time the context is expected to be blocked.
+    pub fn set_main_context_stall_threshold(&self, threshold: Duration) {
+        self.context_health.set_threshold(threshold);
+    }
+
+    /// Sets after how long without an iteration of the [MainContext] the agent considers it lost
+    /// and stops operating, or never if `None` (the default).
+    ///
+    /// The agent future then resolves with [AgentError::MainContextLost], an
+    /// [AgentEvent::Terminated] event is emitted and all streams are closed. This is checked
+    /// while the agent has streams, a watchdog thread wakes the agent task once the timeout
+    /// passed even if it is idle. The timeout should be well above the stall threshold (see
+    /// [Agent::set_main_context_stall_threshold]).
+    pub fn set_main_context_lost_timeout(&self, timeout: Option<Duration>) {
+        self.context_health.set_lost_timeout(timeout);
+    }
+
+    /// Returns why the agent stopped operating, if it did.
+    pub fn terminated(&self) -> Option<AgentError> {
+        self.terminated.lock().unwrap().clone()
+    }
+
+    /// Returns the approximate memory used by the packets queued by this agent, i.e. received
+    /// packets not yet read and sent packets not yet passed to libnice.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory.usage()
+    }
+
+    /// Returns a summary of the state of this agent, e.g. for a health endpoint.
+    ///
+    /// Only streams created via a [StreamBuilder] are counted.
+    pub fn health(&self) -> AgentHealth {
+        let mut stream_states: HashMap<c_uint, Vec<ComponentState>> = HashMap::new();
+        let mut health = AgentHealth::default();
+        for (&(stream_id, _), shared) in self.components.lock().unwrap().iter() {
+            let state = shared
+                .state_history
+                .lock()
+                .unwrap()
+                .last()
+                .map_or(ComponentState::Disconnected, |transition| transition.state);
+            stream_states.entry(stream_id).or_default().push(state);
+            health.dropped_packets += shared.traffic.dropped();
+            if shared.traffic.is_relayed() {
+                health.relayed_components += 1;
+            }
+        }
+        for (_, states) in stream_states {
+            health.streams.add(health::aggregate_state(states));
+        }
+        health.queued_bytes = self.memory.usage().total();
+        health.main_context_stalled = health.streams.total() > 0 && self.context_health.is_stalled(Instant::now());
+        health.last_error = self.last_error.lock().unwrap().clone();
+        health
+    }
+
+    /// Limits the memory used by queued packets (see [Agent::memory_usage]), e.g. to enforce
+    /// per-session limits on multi-tenant servers, or removes the limit if `None`.
+    ///
+    /// Once the limit is reached, inbound packets are dropped (and reported like those dropped due
+    /// to a full inbound buffer, see [AgentEvent::SlowConsumer]) and sending fails.
+    pub fn set_memory_limit(&self, limit: Option<usize>) {
+        self.memory.set_limit(limit);
+    }
+
+    /// Returns the limit set via [Agent::set_memory_limit].
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory.limit()
+    }
+
+    /// Determines why a libnice function which only reports a boolean failure failed for a
+    /// stream, like the [ffi] functions returning a [NiceError](ffi::NiceError) do.
+    ///
+    /// Checks the streams of this agent rather than asking libnice, which costs another call.
+    fn nice_error(&self, stream_id: c_uint, operation: &'static str) -> ffi::NiceError {
+        if self.candidate_policies.lock().unwrap().contains_key(&stream_id) {
+            ffi::NiceError::Failed(operation)
+        } else {
+            ffi::NiceError::UnknownStream(stream_id)
         }
     }
 
+    /// Records that a relay server has been configured for a component, so a missing allocation
+    /// can be reported once gathering is done.
+    fn add_pending_relay(&self, key: ComponentId, server: SocketAddr) {
+        *self.pending_relays.lock().unwrap().entry(key).or_insert(0) += 1;
+        self.relay_servers.lock().unwrap().entry(key).or_default().push(server);
+    }
+
+    /// Runs `f` with the low-level agent on the [MainContext] of this agent, i.e. serialized
+    /// with all libnice callbacks.
+    ///
+    /// `f` is called right away if the current thread owns (or can acquire) the context,
+    /// otherwise it is called the next time the context is iterated.
+    pub fn invoke<F: FnOnce(&ffi::NiceAgent) + Send + 'static>(&self, f: F) {
+        let agent = self.agent.clone();
+        self.ctx.invoke(move || f(&agent));
+    }
+
     /// Returns the context this agent is running on.
     pub fn get_ctx(&self) -> &MainContext {
         &self.ctx
     }
 
+    /// Sets the source of the timers used by the agent's internal features (timeouts,
+    /// keepalives, retransmissions), see the [timer](crate::timer) module.
+    ///
+    /// Defaults to GLib timeout sources on the agent's [MainContext]. Components pick up the
+    /// timer source when their stream is built, so this should be called before building streams.
+    pub fn set_timer_source(&self, timers: Arc<dyn TimerSource>) {
+        *self.timers.lock().unwrap() = timers;
+    }
+
+    /// Returns the source of the timers used by the agent's internal features.
+    pub fn get_timer_source(&self) -> Arc<dyn TimerSource> {
+        self.timers.lock().unwrap().clone()
+    }
+
+    /// Sets the pool on whose threads custom [PacketSink]s (including callbacks registered via
+    /// [StreamComponent::on_data]) are called, instead of on the thread iterating the agent's
+    /// [MainContext]. This keeps connectivity checks and timers responsive while the sinks do
+    /// heavy per-packet work. `None` (the default) calls them on the context.
+    ///
+    /// Like the timer source, components pick up the pool when their stream is built.
+    /// [PacketTransform]s and the default sink are unaffected, they still run on the context.
+    pub fn set_worker_pool(&self, workers: Option<Arc<WorkerPool>>) {
+        *self.workers.lock().unwrap() = workers;
+    }
+
+    /// Returns the pool set via [Agent::set_worker_pool].
+    pub fn get_worker_pool(&self) -> Option<Arc<WorkerPool>> {
+        self.workers.lock().unwrap().clone()
+    }
+
+    /// Returns the context on which the packets of a stream are received.
+    fn recv_ctx(&self, stream_id: c_uint) -> MainContext {
+        match self.recv_contexts.lock().unwrap().get(&stream_id) {
+            Some(ctx) => ctx.clone(),
+            None => self.ctx.clone(),
+        }
+    }
+
     /// Returns the low-level agent backing this Agent.
     pub fn get_ffi_agent(&mut self) -> &mut ffi::NiceAgent {
         &mut self.agent
@@ -129,114 +669,840 @@ impl Agent {
     /// See the [libnice] documentation for more info.
     ///
     /// [libnice]: https://nice.freedesktop.org/libnice/NiceAgent.html#nice-agent-set-software
-    pub fn set_software(&mut self, name: impl Into<String>) {
+    ///
+    /// Defaults to `rust-libnice/<version>` (libnice appends its own version).
+    pub fn set_software(&self, name: impl Into<String>) {
         let name = CString::new(name.into()).expect("name must not have have null bytes");
         self.agent.set_software(name);
     }
 
+    /// Builder-style variant of [Agent::set_software].
+    pub fn with_software(self, name: impl Into<String>) -> Self {
+        self.set_software(name);
+        self
+    }
+
     /// Changes whether this agent is in controlling mode (by default it is not).
-    pub fn set_controlling_mode(&mut self, controlling: bool) {
+    ///
+    /// Live streams are notified of the change via [Stream::role_changes].
+    ///
+    /// Role conflicts are resolved using the ICE tie-breaker, which libnice generates randomly
+    /// per agent and neither exposes nor allows to be set. For deterministic roles (e.g. in tests),
+    /// put the agents into opposite modes so no conflict arises; conflicts which do arise are
+    /// reported with [RoleChangeCause::Conflict].
+    pub fn set_controlling_mode(&self, controlling: bool) {
+        // The lock must not be held while setting, libnice notifies synchronously
+        self.role_state.lock().unwrap().requested = Some(controlling);
         self.agent.set_nice_property(NiceAgentProperty::ControllingMode(controlling))
             .expect("failed to toggle controlling mode");
+        self.role_state.lock().unwrap().requested = None;
+    }
+
+    /// Returns whether this agent is in controlling mode, see [Agent::set_controlling_mode].
+    pub fn controlling_mode(&self) -> BoolResult<bool> {
+        match self.agent.get_nice_property(NiceAgentPropertyType::ControllingMode)? {
+            NiceAgentProperty::ControllingMode(controlling) => Ok(controlling),
+            _ => Err(glib_bool_error!("unexpected property type")),
+        }
+    }
+
+    /// Returns the ICE options supported by this agent, suitable for the local description.
+    ///
+    /// See [IceOptions::to_sdp_line] for generating the `a=ice-options` line.
+    pub fn local_ice_options(&self) -> IceOptions {
+        let is_enabled = |property: NiceAgentPropertyType| match self.agent.get_nice_property(property) {
+            Ok(NiceAgentProperty::IceTrickle(enabled)) => enabled,
+            Ok(NiceAgentProperty::SupportRenomination(enabled)) => enabled,
+            _ => false,
+        };
+        IceOptions {
+            trickle: is_enabled(NiceAgentPropertyType::IceTrickle),
+            renomination: is_enabled(NiceAgentPropertyType::SupportRenomination),
+            other: Vec::new(),
+        }
+    }
+
+    /// Changes whether validated pairs are kept alive with connectivity checks instead of STUN
+    /// binding indications (by default they are not).
+    ///
+    /// Binding indications are never answered, so a peer which vanished (e.g. after a network
+    /// switch on a mobile device) is only noticed once the remote side stops sending. Keepalive
+    /// connectivity checks expect a response and fail the pair if none arrives, which detects
+    /// disconnects faster at the cost of additional traffic (and battery) on idle pairs.
+    ///
+    /// Note that libnice does not allow to change the keepalive interval itself.
+    pub fn set_keepalive_conncheck(&self, enabled: bool) {
+        self.agent.set_nice_property(NiceAgentProperty::KeepAliveConnCheck(enabled))
+            .expect("failed to toggle keepalive connectivity checks");
+    }
+
+    /// Builder-style variant of [Agent::set_keepalive_conncheck].
+    pub fn with_keepalive_conncheck(self, enabled: bool) -> Self {
+        self.set_keepalive_conncheck(enabled);
+        self
+    }
+
+    /// Returns whether validated pairs are kept alive with connectivity checks, see
+    /// [Agent::set_keepalive_conncheck].
+    pub fn keepalive_conncheck(&self) -> bool {
+        match self.agent.get_nice_property(NiceAgentPropertyType::KeepAliveConnCheck) {
+            Ok(NiceAgentProperty::KeepAliveConnCheck(enabled)) => enabled,
+            _ => false,
+        }
+    }
+
+    /// Sets the pacing timer `Ta` of RFC 5245, the interval between two STUN transactions of
+    /// candidate gathering and connectivity checks (libnice defaults to 20ms).
+    ///
+    /// A larger interval spreads the checks (and wakeups) over a longer time, a smaller one
+    /// completes the checks faster. The interval is rounded down to whole milliseconds, with a
+    /// minimum of one millisecond.
+    pub fn set_stun_pacing_timer(&self, interval: Duration) {
+        let millis = interval.as_millis().max(1).min(u128::from(u32::MAX)) as u32;
+        self.agent.set_nice_property(NiceAgentProperty::StunPacingTimer(millis))
+            .expect("failed to set the stun pacing timer");
+    }
+
+    /// Returns the pacing timer of STUN transactions, see [Agent::set_stun_pacing_timer].
+    pub fn stun_pacing_timer(&self) -> Duration {
+        match self.agent.get_nice_property(NiceAgentPropertyType::StunPacingTimer) {
+            Ok(NiceAgentProperty::StunPacingTimer(millis)) => Duration::from_millis(u64::from(millis)),
+            _ => Duration::from_millis(20),
+        }
+    }
+
+    /// Adds a local address from which host candidates will be gathered.
+    ///
+    /// If no local addresses are added, libnice gathers candidates for all local interfaces.
+    /// Note that libnice gathers host candidates only once per stream, so addresses added after
+    /// a stream started gathering only apply to streams created (or gathered) afterwards.
+    pub fn add_local_address(&self, addr: SocketAddr) -> BoolResult<()> {
+        self.agent.add_local_address(addr)?;
+        self.local_addresses.lock().unwrap().insert(addr.ip());
+        Ok(())
+    }
+
+    /// Restricts the candidates emitted by all streams to the network the platform considers
+    /// active, e.g. after a mobile device switched from Wi-Fi to cellular. `None` stops filtering
+    /// the emitted candidates.
+    ///
+    /// The addresses of the network are added via [Agent::add_local_address] (once each), so
+    /// streams created afterwards gather host candidates on them. Local candidates gathered on
+    /// other interfaces, including those of streams gathering already, are no longer emitted.
+    /// Since libnice gathers host candidates only once per stream, existing streams follow the
+    /// new network via [Stream::regather] only as far as server reflexive and relayed
+    /// candidates are concerned; otherwise replace them with new streams (and an ICE restart on
+    /// the remote side).
+    ///
+    /// This is no isolation: libnice cannot remove local addresses, so streams created after
+    /// switching back (or to `None`) still only gather on the addresses of all networks set so
+    /// far, and candidates which are not emitted are only hidden from the remote peer, libnice
+    /// still pairs them with the remote candidates. To really leave a network, create a new
+    /// [Agent] and move the streams over to it.
+    pub fn set_active_network(&self, network: Option<PlatformNetwork>) -> BoolResult<()> {
+        if let Some(network) = &network {
+            let mut added = self.local_addresses.lock().unwrap();
+            for ip in &network.addresses {
+                if !added.contains(ip) {
+                    self.agent.add_local_address(SocketAddr::new(*ip, 0))?;
+                    added.insert(*ip);
+                }
+            }
+        }
+        let addresses = network.as_ref().map(|network| network.addresses.clone());
+        for policy in self.candidate_policies.lock().unwrap().values_mut() {
+            policy.network = addresses.clone();
+        }
+        *self.active_network.lock().unwrap() = network;
+        Ok(())
+    }
+
+    /// Derives the local credentials and candidate foundations of streams created afterwards
+    /// from `seed` (test builds and the `testing` feature only), for snapshot tests of the
+    /// signaled descriptions.
+    ///
+    /// The ufrag and pwd of a stream are set via libnice and only depend on the seed and the
+    /// stream id. The foundations of the emitted local candidates are replaced by ones derived
+    /// from the seed, the candidate type and the addresses (without ports); libnice still uses
+    /// its own foundations internally, e.g. for [StreamComponent::pin_pair]. Ports and
+    /// priorities are not affected.
+    ///
+    /// Never use this outside of tests, the credentials are predictable.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn set_deterministic_seed(&self, seed: Option<u64>) {
+        *self.deterministic.lock().unwrap() = seed.map(DeterministicIds::new);
+    }
+
+    /// Returns the network set via [Agent::set_active_network].
+    pub fn active_network(&self) -> Option<PlatformNetwork> {
+        self.active_network.lock().unwrap().clone()
+    }
+
+    /// Sets the STUN server used to gather server reflexive candidates.
+    ///
+    /// libnice does not resolve host names, so an IP address must be given.
+    pub fn set_stun_server(&self, server: Option<SocketAddr>) -> BoolResult<()> {
+        set_stun_server(&self.agent, server)
+    }
+
+    /// Limits the number of streams gathering candidates at the same time, `None` (the default)
+    /// for no limit.
+    ///
+    /// Gathering of further streams (including [Stream::regather]) is queued until a stream
+    /// finished gathering, see [AgentEvent::GatheringQueued] and [AgentEvent::GatheringDequeued].
+    /// This avoids exhausting ephemeral ports and STUN/TURN servers when building many streams
+    /// at once. Like all requests, queued streams are started while the agent is `poll()`ed.
+    pub fn set_max_concurrent_gathering(&self, max: Option<usize>) {
+        let dequeued = self.gather_gate.lock().unwrap().set_limit(max, Instant::now());
+        start_dequeued(&self.events, &self.msgs_sender, dequeued);
+    }
+
+    /// Returns the number of streams waiting to gather candidates, see
+    /// [Agent::set_max_concurrent_gathering].
+    pub fn queued_gathering(&self) -> usize {
+        self.gather_gate.lock().unwrap().queued()
     }
 
     /// Add a new [Stream] with the specified amount of components to the agent.
-    pub fn stream_builder(&mut self, components: usize) -> StreamBuilder {
+    pub fn stream_builder(&self, components: usize) -> StreamBuilder {
         StreamBuilder::new(self, components)
     }
 
-    fn handle_msg(&mut self, msg: ControlMsg) {
-        match msg {
+    /// Add a new [Stream] with an RTP and an RTCP component to the agent, which can be accessed
+    /// via [Stream::rtp] and [Stream::rtcp].
+    pub fn rtp_rtcp_stream_builder(&self) -> StreamBuilder {
+        let mut builder = StreamBuilder::new(self, 2);
+        builder.rtp_rtcp = true;
+        builder
+    }
+
+    /// Returns the user data of the stream with the given id, if it has been set and is of
+    /// type `T`. See [Stream::set_user_data].
+    ///
+    /// Useful to recover the application context of [AgentEvent]s.
+    pub fn stream_user_data<T: Any + Send + Sync>(&self, stream_id: c_uint) -> Option<Arc<T>> {
+        let slot = self.stream_user_data.lock().unwrap().get(&stream_id).cloned()?;
+        slot.get()
+    }
+
+    /// Returns the user data of the given component, if it has been set and is of type `T`.
+    /// See [StreamComponent::set_user_data].
+    pub fn component_user_data<T: Any + Send + Sync>(
+        &self,
+        stream_id: c_uint,
+        component_id: c_uint,
+    ) -> Option<Arc<T>> {
+        let shared = self.components.lock().unwrap().get(&(stream_id, component_id)).cloned()?;
+        shared.user_data.get()
+    }
+
+    /// Forcibly removes the stream with the given id, e.g. one whose owning task is stuck or has
+    /// been leaked. Streams are otherwise removed once their handles have been dropped.
+    ///
+    /// The components of the stream observe the [ComponentState::Failed] state, after which
+    /// their streams of inbound packets end. Dropping the remaining handles afterwards is a no-op.
+    pub fn remove_stream(&self, stream_id: c_uint) {
+        self.deliver_final_state(Some(stream_id), ComponentState::Failed);
+        self.remove_stream_internal(stream_id);
+    }
+
+    /// Delivers a final state to the components of a stream (or of all streams), which libnice
+    /// does not report itself when streams are removed.
+    fn deliver_final_state(&self, stream_id: Option<c_uint>, state: ComponentState) {
+        let components = self.components.lock().unwrap();
+        for (key, sink) in self.state_sinks.lock().unwrap().iter_mut() {
+            if stream_id.map_or(false, |stream_id| stream_id != key.0) {
+                continue;
+            }
+            if let Some(shared) = components.get(key) {
+                let _ = sink.push(shared.record_state(state, None));
+            }
+        }
+    }
+
+    fn handle_msg(&self, msg: ControlMsg) {
+        let audit = !self.audit.is_empty();
+        let (operation, result) = match msg {
             ControlMsg::SetRemoteCredentials(stream_id, ufrag, pwd) => {
-                let _ = self.agent.set_remote_credentials(stream_id, &ufrag, &pwd);
+                let result = self
+                    .agent
+                    .set_remote_credentials(stream_id, &ufrag, &pwd)
+                    .map_err(|_| self.nice_error(stream_id, "set_remote_credentials"));
+                (AuditOperation::SetRemoteCredentials { stream_id }, result.map_err(OperationError::from))
             }
-            ControlMsg::AddRemoteCandidate((stream_id, component_id), candidate) => {
+            ControlMsg::AddRemoteCandidate((stream_id, component_id), candidate, credentials) => {
+                let excluded = match (&candidate.address, self.candidate_policies.lock().unwrap().get(&stream_id)) {
+                    (Address::Ip(ip), Some(policy)) if !policy.address_family.allows(*ip) => Some(*ip),
+                    _ => None,
+                };
                 // TODO resolve FQDN in candidate (if any)
-                let candidate = match ffi::NiceCandidate::from_sdp_without_fqdn(&candidate) {
-                    Ok(candidate) => candidate,
-                    Err(_) => return, // rfc mandates we MUST ignore unsupported lines
+                let parsed = match excluded {
+                    Some(ip) => Err(CandidateError::ExcludedAddressFamily(ip).to_string()),
+                    None => ffi::NiceCandidate::from_sdp_without_fqdn(&candidate).map_err(|err| err.to_string()),
                 };
-                let candidate_ref = &candidate;
-                let candidates = std::slice::from_ref(&candidate_ref);
-                let _ = self
+                let result = match parsed {
+                    Ok(mut nice_candidate) => {
+                        if let Some(credentials) = credentials {
+                            nice_candidate.set_username(Some(&credentials.username));
+                            nice_candidate.set_password(Some(&credentials.password));
+                        }
+                        let candidate_ref = &nice_candidate;
+                        let candidates = std::slice::from_ref(&candidate_ref);
+                        match self.agent.add_remote_candidates(stream_id, component_id, candidates) {
+                            Ok(added) => {
+                                if let Some(shared) = self.components.lock().unwrap().get(&(stream_id, component_id)) {
+                                    shared.remote_candidates.fetch_add(added as usize, Ordering::Relaxed);
+                                }
+                                Ok(())
+                            }
+                            Err(_) => Err(self.nice_error(stream_id, "set_remote_candidates").into()),
+                        }
+                    }
+                    // rfc mandates we MUST ignore unsupported lines
+                    Err(reason) => {
+                        self.events.emit(AgentEvent::CandidateRejected {
+                            stream_id,
+                            component_id,
+                            candidate: candidate.clone(),
+                            reason: reason.clone(),
+                        });
+                        Err(OperationError::RejectedCandidate(reason))
+                    }
+                };
+                let operation = AuditOperation::AddRemoteCandidate { stream_id, component_id, candidate };
+                (operation, result)
+            }
+            ControlMsg::RemoteGatheringDone(stream_id) => {
+                let result = self
                     .agent
-                    .add_remote_candidates(stream_id, component_id, candidates);
+                    .peer_candidate_gathering_done(stream_id)
+                    .map_err(|_| self.nice_error(stream_id, "peer_candidate_gathering_done"));
+                (AuditOperation::RemoteGatheringDone { stream_id }, result.map_err(OperationError::from))
             }
             ControlMsg::Send((stream_id, component_id), buf) => {
+                self.memory.release_outbound(buf.len());
                 // The libnice docs are very unclear on when this can fail with unreliable
-                // transports, so we'll just assume it only fails for WOULD_BLOCK.
-                let _ = self.agent.send(stream_id, component_id, &buf);
+                // transports, so we'll just assume it only fails for WOULD_BLOCK, i.e. congestion.
+                let sent = self.agent.send(stream_id, component_id, &buf);
+                let complete = sent == Some(buf.len());
+                if let Some(shared) = self.components.lock().unwrap().get(&(stream_id, component_id)) {
+                    // Without a selected pair libnice fails every send, which is not congestion
+                    if matches!(shared.state(), ComponentState::Connected | ComponentState::Ready) {
+                        shared.on_send_result(complete);
+                    }
+                }
+                let result = match sent {
+                    Some(sent) if !complete => Err(OperationError::ShortSend { sent, len: buf.len() }),
+                    Some(_) => Ok(()),
+                    None => Err(self.nice_error(stream_id, "send").into()),
+                };
+                (AuditOperation::Send { stream_id, component_id, len: buf.len() }, result)
+            }
+            ControlMsg::StartGathering(stream_id) => {
+                let result = self.start_gathering(stream_id);
+                (AuditOperation::StartGathering { stream_id }, result.map_err(OperationError::from))
+            }
+            ControlMsg::Regather(stream_id) => {
+                // libnice ignores this for streams which already started gathering but will
+                // still report candidates of relays added afterwards, see start_gathering.
+                let result = self.start_gathering(stream_id);
+                (AuditOperation::Regather { stream_id }, result.map_err(OperationError::from))
+            }
+            ControlMsg::SetPortRange((stream_id, component_id), min_port, max_port) => {
+                self.agent.set_port_range(stream_id, component_id, min_port, max_port);
+                let operation = AuditOperation::SetPortRange { stream_id, component_id, min_port, max_port };
+                (operation, Ok(()))
+            }
+            ControlMsg::AddRelayServer((stream_id, component_id), relay) => {
+                let result = relay.apply(&self.agent, stream_id, component_id);
+                if result.is_ok() {
+                    self.add_pending_relay((stream_id, component_id), relay.addr);
+                }
+                let operation = AuditOperation::AddRelayServer { stream_id, component_id, addr: relay.addr };
+                (operation, result.map_err(OperationError::from))
+            }
+            ControlMsg::RelayFallback(stream_id, servers) => {
+                if !self.stream_failed(stream_id) {
+                    // Recovered meanwhile (e.g. via DisconnectPolicy::Recheck), keep the fallback
+                    self.relay_fallbacks.lock().unwrap().insert(stream_id, servers);
+                    return;
+                }
+                if let Some(policy) = self.candidate_policies.lock().unwrap().get_mut(&stream_id) {
+                    policy.relay_only = true;
+                }
+                if let Some(channel) = self.candidate_channels.lock().unwrap().get(&stream_id) {
+                    channel.lock().unwrap().rearm();
+                }
+                let mut component_ids = self
+                    .components
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .filter(|(component_stream_id, _)| *component_stream_id == stream_id)
+                    .map(|(_, component_id)| *component_id)
+                    .collect::<Vec<_>>();
+                component_ids.sort();
+                let result = (|| {
+                    for relay in &servers {
+                        for &component_id in &component_ids {
+                            relay.apply(&self.agent, stream_id, component_id)?;
+                            self.add_pending_relay((stream_id, component_id), relay.addr);
+                        }
+                    }
+                    self.start_gathering(stream_id)
+                })();
+                self.events.emit(AgentEvent::RelayFallback {
+                    stream_id,
+                    servers: servers.iter().map(|relay| relay.addr).collect(),
+                    started: result.is_ok(),
+                });
+                (AuditOperation::RelayFallback { stream_id }, result.map_err(OperationError::from))
+            }
+            ControlMsg::StunProbed(stream_id, responded) => {
+                let server = match self.finish_stun_probe(stream_id, responded) {
+                    Some(server) => server,
+                    // The stream has been dropped meanwhile
+                    None => return,
+                };
+                let result = self.start_gathering(stream_id);
+                (AuditOperation::StunProbe { stream_id, server, responded }, result.map_err(OperationError::from))
+            }
+            ControlMsg::DropComponent((stream_id, component_id)) => {
+                let key = (stream_id, component_id);
+                if self.state_sinks.lock().unwrap().remove(&key).is_some() {
+                    let _ = self.agent.detach_recv(stream_id, component_id, &self.recv_ctx(stream_id));
+                }
+                if let Some(shared) = self.components.lock().unwrap().remove(&key) {
+                    shared.close();
+                }
+                self.pending_relays.lock().unwrap().remove(&key);
+                self.relay_servers.lock().unwrap().remove(&key);
+                (AuditOperation::DropComponent { stream_id, component_id }, Ok(()))
+            }
+            ControlMsg::SetControllingMode(controlling) => {
+                self.set_controlling_mode(controlling);
+                (AuditOperation::SetControllingMode { controlling }, Ok(()))
             }
             ControlMsg::DropStream(stream_id) => {
+                // Packets sent before the stream has been dropped are still delivered, including
+                // those which have not been moved from the data channel to the queue yet
+                {
+                    let mut data = self.data.lock().unwrap();
+                    let mut outbound = self.outbound.lock().unwrap();
+                    while let Ok(Some((key, buf))) = data.try_next() {
+                        outbound.push(key, buf);
+                    }
+                }
+                let pending = self.outbound.lock().unwrap().take_stream(stream_id);
+                for (key, buf) in pending {
+                    self.handle_msg(ControlMsg::Send(key, buf));
+                }
                 self.remove_stream_internal(stream_id);
+                (AuditOperation::DropStream { stream_id }, Ok(()))
+            }
+        };
+
+        if let Err(err) = &result {
+            *self.last_error.lock().unwrap() = Some(err.to_string());
+        }
+        if audit {
+            self.audit.emit(AuditRecord {
+                timestamp: SystemTime::now(),
+                operation,
+                result,
+            });
+        }
+    }
+
+    /// Starts gathering candidates for a stream, or queues it if too many streams are gathering,
+    /// see [Agent::set_max_concurrent_gathering].
+    fn start_gathering(&self, stream_id: c_uint) -> Result<(), ffi::NiceError> {
+        if self.gathering_completed(stream_id) {
+            // libnice ignores the request, only relays added since are still allocated (and
+            // reported by another gathering done signal), so no slot is taken
+            if self.relays_pending(stream_id) {
+                self.restart_gathering_progress(stream_id);
+            } else {
+                finish_candidates(&self.candidate_channels, stream_id);
+            }
+            return Ok(());
+        }
+        if self.probe_stun_server(stream_id) {
+            // Gathering starts once a STUN server responded, see StunProbed
+            return Ok(());
+        }
+        if let Some(position) = self.gather_gate.lock().unwrap().request(stream_id, Instant::now()) {
+            self.events.emit(AgentEvent::GatheringQueued { stream_id, position });
+            return Ok(());
+        }
+        self.restart_gathering_progress(stream_id);
+        let result = self.agent.gather_candidates(stream_id);
+        if result.is_err() {
+            let dequeued = self.gather_gate.lock().unwrap().finish(stream_id, Instant::now());
+            start_dequeued(&self.events, &self.msgs_sender, dequeued);
+        } else {
+            // libnice creates the sockets of the host candidates while gathering
+            self.apply_socket_options(stream_id);
+        }
+        result
+    }
+
+    /// Probes the next STUN server set via [StreamBuilder::set_stun_servers] for a stream which
+    /// is about to gather, returns whether a probe is running.
+    ///
+    /// The last server is configured without probing, as there is nothing to fall back to.
+    fn probe_stun_server(&self, stream_id: c_uint) -> bool {
+        let mut selections = self.stun_selections.lock().unwrap();
+        let selection = match selections.get_mut(&stream_id) {
+            Some(selection) => selection,
+            None => return false,
+        };
+        if selection.probe.is_some() {
+            return true;
+        }
+        while selection.next + 1 < selection.servers.len() {
+            let server = selection.servers[selection.next];
+            selection.next += 1;
+            if let Some(probe) = self.start_stun_probe(stream_id, server, selection.timeout) {
+                selection.probe = Some(probe);
+                return true;
+            }
+        }
+        let selection = selections.remove(&stream_id).expect("selection exists");
+        let server = selection.servers[selection.next];
+        let _ = set_stun_server(&self.agent, Some(server));
+        self.events.emit(AgentEvent::StunServerSelected {
+            stream_id,
+            server,
+            unresponsive: selection.servers[..selection.next].to_vec(),
+        });
+        false
+    }
+
+    /// Gathers the candidates of a temporary stream with `server` configured, whose server
+    /// reflexive candidate (or the lack of one) is reported via [ControlMsg::StunProbed].
+    fn start_stun_probe(&self, stream_id: c_uint, server: SocketAddr, timeout: Duration) -> Option<StunProbe> {
+        set_stun_server(&self.agent, Some(server)).ok()?;
+        let probe_stream_id = self.agent.add_stream(1).ok()?;
+        self.stun_probes.lock().unwrap().insert(probe_stream_id, stream_id);
+        if self.agent.gather_candidates(probe_stream_id).is_err() {
+            self.stun_probes.lock().unwrap().remove(&probe_stream_id);
+            self.agent.remove_stream(probe_stream_id);
+            return None;
+        }
+        let probes = Arc::clone(&self.stun_probes);
+        let msgs_sender = self.msgs_sender.clone();
+        let timer = self.get_timer_source().start(timeout, Box::new(move || {
+            if probes.lock().unwrap().remove(&probe_stream_id).is_some() {
+                let _ = msgs_sender.unbounded_send(ControlMsg::StunProbed(stream_id, false));
+            }
+            false
+        }));
+        Some(StunProbe {
+            stream_id: probe_stream_id,
+            server,
+            _timer: timer,
+        })
+    }
+
+    /// Removes the probe of a stream, returning the server probed. If it responded, the server
+    /// remains configured and the selection is done.
+    fn finish_stun_probe(&self, stream_id: c_uint, responded: bool) -> Option<SocketAddr> {
+        let mut selections = self.stun_selections.lock().unwrap();
+        let selection = selections.get_mut(&stream_id)?;
+        let probe = selection.probe.take()?;
+        self.agent.remove_stream(probe.stream_id);
+        if responded {
+            let selection = selections.remove(&stream_id).expect("selection exists");
+            self.events.emit(AgentEvent::StunServerSelected {
+                stream_id,
+                server: probe.server,
+                unresponsive: selection.servers[..selection.next - 1].to_vec(),
+            });
+        }
+        Some(probe.server)
+    }
+
+    /// Returns whether a component of a stream is in the `Failed` state.
+    fn stream_failed(&self, stream_id: c_uint) -> bool {
+        self.components
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|((component_stream_id, _), shared)| {
+                *component_stream_id == stream_id && shared.state() == ComponentState::Failed
+            })
+    }
+
+    /// Returns whether libnice has completed gathering for a stream, after which it ignores
+    /// further requests to gather.
+    fn gathering_completed(&self, stream_id: c_uint) -> bool {
+        self.gathering
+            .lock()
+            .unwrap()
+            .get(&stream_id)
+            .map_or(false, |progress| progress.lock().unwrap().status().done)
+    }
+
+    /// Returns whether relay servers added to a stream have not allocated a candidate yet.
+    fn relays_pending(&self, stream_id: c_uint) -> bool {
+        self.pending_relays
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|((relay_stream_id, _), pending)| *relay_stream_id == stream_id && *pending > 0)
+    }
+
+    fn restart_gathering_progress(&self, stream_id: c_uint) {
+        if let Some(progress) = self.gathering.lock().unwrap().get(&stream_id) {
+            let stun_configured = match self.agent.get_nice_property(NiceAgentPropertyType::StunServer) {
+                Ok(NiceAgentProperty::StunServer(server)) => server.is_some(),
+                _ => false,
+            };
+            let relays_configured = self
+                .relay_servers
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|((relay_stream_id, _), _)| *relay_stream_id == stream_id)
+                .map(|(_, servers)| servers.len())
+                .sum();
+            progress.lock().unwrap().restart(stun_configured, relays_configured);
+        }
+    }
+
+    /// Applies the options set via [StreamBuilder::set_socket_options] to the sockets of all
+    /// components of a stream.
+    fn apply_socket_options(&self, stream_id: c_uint) {
+        let options = match self.socket_options.lock().unwrap().get(&stream_id) {
+            Some(options) => *options,
+            None => return,
+        };
+        let mut component_ids = self
+            .components
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(component_stream_id, _)| *component_stream_id == stream_id)
+            .map(|(_, component_id)| *component_id)
+            .collect::<Vec<_>>();
+        component_ids.sort();
+        for component_id in component_ids {
+            for socket in self.agent.get_sockets(stream_id, component_id) {
+                if let Err(err) = options.apply(socket) {
+                    self.events.emit(AgentEvent::SocketOptionsFailed {
+                        stream_id,
+                        component_id,
+                        error: err.to_string(),
+                    });
+                }
             }
         }
     }
 
     /// Removes a stream from the nice agent.
     /// This steam must not be registered at this agent.
-    fn remove_stream_internal(&mut self, stream_id: u32) {
+    fn remove_stream_internal(&self, stream_id: u32) {
         let sinks = self.state_sinks.lock().unwrap()
             .drain_filter(|(sink_stream_id, sink_component_id), _| *sink_stream_id == stream_id)
             .collect::<Vec<_>>();
 
+        let recv_ctx = self.recv_ctx(stream_id);
         for ((_, component_id), _) in sinks {
-            let _ = self.agent.detach_recv(stream_id, component_id, &self.ctx);
+            let _ = self.agent.detach_recv(stream_id, component_id, &recv_ctx);
         }
 
         self.agent.remove_stream(stream_id);
-        self.candidate_sinks.lock().unwrap().remove(&stream_id);
+        if let Some(channel) = self.candidate_channels.lock().unwrap().remove(&stream_id) {
+            channel.lock().unwrap().finish();
+        }
+        self.candidate_policies.lock().unwrap().remove(&stream_id);
+        self.candidate_emitters.lock().unwrap().remove(&stream_id);
+        self.components.lock().unwrap().retain(|(component_stream_id, _), shared| {
+            if *component_stream_id != stream_id {
+                return true;
+            }
+            shared.close();
+            false
+        });
+        self.pending_relays.lock().unwrap().retain(|(relay_stream_id, _), _| *relay_stream_id != stream_id);
+        self.relay_servers.lock().unwrap().retain(|(relay_stream_id, _), _| *relay_stream_id != stream_id);
+        self.relay_fallbacks.lock().unwrap().remove(&stream_id);
+        if let Some(probe) = self.stun_selections.lock().unwrap().remove(&stream_id).and_then(|selection| selection.probe) {
+            self.stun_probes.lock().unwrap().remove(&probe.stream_id);
+            self.agent.remove_stream(probe.stream_id);
+        }
+        self.gathering.lock().unwrap().remove(&stream_id);
+        self.stream_user_data.lock().unwrap().remove(&stream_id);
+        self.recv_contexts.lock().unwrap().remove(&stream_id);
+        self.socket_options.lock().unwrap().remove(&stream_id);
+        let dequeued = self.gather_gate.lock().unwrap().remove(stream_id, Instant::now());
+        start_dequeued(&self.events, &self.msgs_sender, dequeued);
+        for (_, buf) in self.outbound.lock().unwrap().take_stream(stream_id) {
+            self.memory.release_outbound(buf.len());
+        }
     }
 }
 
-/*
- * The nice wrapper itself is safe to use across threads
- */
-unsafe impl Sync for Agent {}
-
-/* TODO: Is this really true? */
-unsafe impl Send for Agent {}
+impl Agent {
+    /// Removes all streams like dropping the agent does, their components report `Disconnected`.
+    fn close_streams(&self) {
+        self.deliver_final_state(None, ComponentState::Disconnected);
+        self.remove_all_streams();
+    }
 
-impl Future for Agent {
-    type Output = (); // never
+    fn remove_all_streams(&self) {
+        let mut stream_ids = self.components.lock().unwrap()
+            .keys()
+            .map(|(stream_id, _)| *stream_id)
+            .collect::<Vec<_>>();
+        stream_ids.sort_unstable();
+        stream_ids.dedup();
+        for stream_id in stream_ids {
+            self.remove_stream_internal(stream_id);
+        }
+    }
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    /// Handles all pending control messages before any packets. Packets are queued per component
+    /// and sent round-robin, at most [SEND_BUDGET] per poll, so no component can starve the
+    /// others and control messages are handled at least once per budget.
+    fn poll_msgs(&self, cx: &mut Context) -> Poll<Result<Infallible, AgentError>> {
+        if let Some(error) = self.terminated() {
+            return Poll::Ready(Err(error));
+        }
+        if !self.components.lock().unwrap().is_empty() {
+            let now = Instant::now();
+            if let Some(since_last_iteration) = self.context_health.check(now) {
+                self.events.emit(AgentEvent::MainContextStalled { since_last_iteration });
+            }
+            if let Some(since_last_iteration) = self.context_health.lost(now) {
+                return self.terminate(AgentError::MainContextLost { since_last_iteration });
+            }
+            self.context_health.register(cx.waker());
+        }
         loop {
-            let msg = {
-                let msgs = &mut self.msgs;
-                pin_mut!(msgs);
-                ready!(msgs.poll_next(cx)).expect("msgs stream ended prematurely")
+            let msg = match self.msgs.lock().unwrap().poll_next_unpin(cx) {
+                Poll::Ready(msg) => msg,
+                Poll::Pending => break,
             };
-            self.handle_msg(msg);
+            // The agent holds a sender itself, so the channel never ends
+            self.handle_msg(msg.expect("msgs stream ended prematurely"));
+        }
+        {
+            let mut data = self.data.lock().unwrap();
+            let mut outbound = self.outbound.lock().unwrap();
+            while let Poll::Ready(msg) = data.poll_next_unpin(cx) {
+                let (key, buf) = msg.expect("data stream ended prematurely");
+                outbound.push(key, buf);
+            }
         }
-    }
-}
 
-impl Drop for Agent {
-    fn drop(&mut self) {
-        for (_, sink) in self.state_sinks.lock().expect("failed to lock stream state sinks").iter_mut() {
-            let _ = sink.send(NiceComponentState::Disconnected);
-            sink.close_channel()
+        for _ in 0..SEND_BUDGET {
+            let next = self.outbound.lock().unwrap().pop();
+            match next {
+                Some((key, buf)) => self.handle_msg(ControlMsg::Send(key, buf)),
+                None => return Poll::Pending,
+            }
         }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+
+    /// Stops the agent with `error`, which all further polls resolve with, and closes its
+    /// streams.
+    fn terminate(&self, error: AgentError) -> Poll<Result<Infallible, AgentError>> {
+        *self.terminated.lock().unwrap() = Some(error.clone());
+        self.events.emit(AgentEvent::Terminated { error: error.clone() });
+        self.close_streams();
+        Poll::Ready(Err(error))
     }
 }
 
-/// Builder for ICE [Stream]s.
-pub struct StreamBuilder<'a> {
-    agent: &'a mut Agent,
-    components: usize,
+/// Drives the agent, i.e. passes the requests of its streams and components to libnice.
+///
+/// Resolves only once the agent stopped operating, see [AgentError].
+impl Future for Agent {
+    type Output = Result<Infallible, AgentError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.poll_msgs(cx)
+    }
+}
+
+impl Future for &Agent {
+    type Output = Result<Infallible, AgentError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.poll_msgs(cx)
+    }
+}
+
+impl Drop for Agent {
+    /// Closes all streams and components of this agent.
+    ///
+    /// Remaining [Stream] and [StreamComponent] handles report [Stream::is_closed] afterwards and
+    /// their operations fail instead of being silently dropped.
+    fn drop(&mut self) {
+        self.msgs.get_mut().unwrap().close();
+        self.data.get_mut().unwrap().close();
+        self.deliver_final_state(None, ComponentState::Disconnected);
+        for (_, sink) in self.state_sinks.lock().expect("failed to lock stream state sinks").iter_mut() {
+            sink.close();
+        }
+        self.remove_all_streams();
+    }
+}
+
+/// Builder for ICE [Stream]s.
+pub struct StreamBuilder<'a> {
+    agent: &'a Agent,
+    components: usize,
     inbound_buf_size: usize,
+    state_buf_size: usize,
     port_ranges: HashMap<usize, (u16, u16)>,
+    fallback_port_range: Option<(u16, u16)>,
+    relay_servers: Vec<RelayServer>,
+    relay_fallback: Vec<RelayServer>,
+    stun_servers: Vec<SocketAddr>,
+    stun_timeout: Duration,
+    candidate_policy: LocalCandidatePolicy,
+    sort_candidates: bool,
+    max_candidates_per_component: Option<usize>,
+    user_data: Option<UserData>,
+    rtp_rtcp: bool,
+    slow_consumer: SlowConsumerThreshold,
+    rtt_probes: bool,
+    defer_gathering: bool,
+    emit_sdp_candidates: bool,
+    recv_ctx: Option<MainContext>,
+    socket_options: Option<SocketOptions>,
 }
 
 impl<'a> StreamBuilder<'a> {
     /// See [Agent::stream_builder].
-    pub fn new(agent: &'a mut Agent, components: usize) -> Self {
+    pub fn new(agent: &'a Agent, components: usize) -> Self {
         Self {
             agent,
             components,
             inbound_buf_size: 10,
+            state_buf_size: 8,
             port_ranges: HashMap::new(),
+            fallback_port_range: None,
+            relay_servers: Vec::new(),
+            relay_fallback: Vec::new(),
+            stun_servers: Vec::new(),
+            stun_timeout: Duration::from_secs(3),
+            candidate_policy: LocalCandidatePolicy::default(),
+            sort_candidates: false,
+            max_candidates_per_component: None,
+            user_data: None,
+            rtp_rtcp: false,
+            slow_consumer: SlowConsumerThreshold::default(),
+            rtt_probes: false,
+            defer_gathering: false,
+            emit_sdp_candidates: true,
+            recv_ctx: None,
+            socket_options: None,
         }
     }
 
@@ -246,10 +1512,41 @@ impl<'a> StreamBuilder<'a> {
         self
     }
 
+    /// Sets when an [AgentEvent::SlowConsumer] event is emitted for a component whose inbound
+    /// buffer is full, by default after 64 consecutive dropped packets or 500ms.
+    pub fn set_slow_consumer_threshold(&mut self, threshold: SlowConsumerThreshold) -> &mut Self {
+        self.slow_consumer = threshold;
+        self
+    }
+
+    /// Enables RTT probes on the components of the stream (disabled by default), see
+    /// [StreamComponent::measure_rtt].
+    ///
+    /// Probes are custom application packets, not STUN, and only understood by this crate. So
+    /// the remote peer must use this crate as well and enable them too, e.g. after agreeing on
+    /// them via signaling. While enabled, inbound packets which look like probes are answered and
+    /// not passed to the application.
+    pub fn set_rtt_probes(&mut self, enabled: bool) -> &mut Self {
+        self.rtt_probes = enabled;
+        self
+    }
+
+    /// Sets the number of state transitions queued per component until it is polled (default 8).
+    ///
+    /// Once the queue is full, the newest queued transition is merged with the incoming one
+    /// (e.g. `Connecting -> Connected` and `Connected -> Ready` become `Connecting -> Ready`), so
+    /// the latest state is always delivered.
+    pub fn set_state_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.state_buf_size = size;
+        self
+    }
+
     /// Limits the range of ports used for host candidates.
     ///
-    /// If the range is exhausted, [StreamBuilder::build] will fail.
-    /// To set the range per component, use [StreamBuilder::set_component_port_range].
+    /// If the range is exhausted, [StreamBuilder::build] fails with
+    /// [StreamBuildError::PortRangeExhausted] unless a fallback range is set.
+    /// To set the range per component, use [StreamBuilder::set_component_port_range] or
+    /// [StreamBuilder::set_port_ranges].
     pub fn set_port_range(&mut self, min_port: u16, max_port: u16) -> &mut Self {
         for i in 0..self.components {
             self.port_ranges.insert(i, (min_port, max_port));
@@ -260,46 +1557,298 @@ impl<'a> StreamBuilder<'a> {
     /// Limits the range of ports used for host candidates for the component at the specified index.
     /// Note that the first component (with id `1`) is at index `0`.
     ///
-    /// If the range is exhausted, [StreamBuilder::build] will fail.
+    /// If the range is exhausted, [StreamBuilder::build] fails with
+    /// [StreamBuildError::PortRangeExhausted] unless a fallback range is set.
     /// To set the range for all components, use [StreamBuilder::set_port_range].
     ///
-    /// # Panics
-    ///
-    /// Panics if `component_index >= components`.
+    /// Fails if `component_index >= components`.
     pub fn set_component_port_range(
         &mut self,
         component_index: usize,
         min_port: u16,
         max_port: u16,
-    ) -> &mut Self {
+    ) -> Result<&mut Self, StreamConfigError> {
         if component_index >= self.components {
-            panic!(
-                "index {} of of range (size: {})",
-                component_index, self.components
-            );
+            return Err(StreamConfigError::ComponentIndexOutOfRange {
+                component_index,
+                components: self.components,
+            });
         }
         self.port_ranges
             .insert(component_index, (min_port, max_port));
+        Ok(self)
+    }
+
+    /// Limits the range of ports used for host candidates per component, the range of the first
+    /// component (with id `1`) first. Components without a range in `ranges` keep their range.
+    ///
+    /// Fails without changing any range if there are more ranges than components.
+    pub fn set_port_ranges(&mut self, ranges: &[(u16, u16)]) -> Result<&mut Self, StreamConfigError> {
+        if ranges.len() > self.components {
+            return Err(StreamConfigError::ComponentIndexOutOfRange {
+                component_index: ranges.len() - 1,
+                components: self.components,
+            });
+        }
+        for (index, range) in ranges.iter().enumerate() {
+            self.port_ranges.insert(index, *range);
+        }
+        Ok(self)
+    }
+
+    /// Sets a port range used for all components if gathering fails with the configured port
+    /// ranges, e.g. because another process took the ports.
+    ///
+    /// If gathering fails with the fallback range as well, [StreamBuilder::build] fails with
+    /// [StreamBuildError::PortRangeExhausted]. Only applies if gathering starts when the stream
+    /// is built (see [StreamBuilder::defer_gathering] and [Agent::set_max_concurrent_gathering]).
+    pub fn set_fallback_port_range(&mut self, min_port: u16, max_port: u16) -> &mut Self {
+        self.fallback_port_range = Some((min_port, max_port));
+        self
+    }
+
+    /// Adds a TURN relay server used to gather relayed candidates for all components.
+    ///
+    /// May be called multiple times to use multiple relay servers.
+    /// To add relay servers after the stream has been built, use [Stream::add_relay_server].
+    pub fn add_relay_server(&mut self, relay: RelayServer) -> &mut Self {
+        self.relay_servers.push(relay);
+        self
+    }
+
+    /// Retries once via `relays` if a component of the stream fails while no relay server has
+    /// been configured for it and its selected pair (if any) was not relayed.
+    ///
+    /// The relay servers are added to all components and gathering is restarted, after which
+    /// only relayed candidates are emitted. libnice only supports forcing relayed connections
+    /// agent-wide (see [NiceAgentProperty::ForceRelay](crate::ffi::NiceAgentProperty::ForceRelay)),
+    /// so the local host and server reflexive candidates are still paired with the remote ones,
+    /// but these pairs have already failed.
+    ///
+    /// The fallback is reported via [AgentEvent::RelayFallback], upon which the new candidates
+    /// must be subscribed via [Stream::candidates] and sent to the remote peer.
+    pub fn set_relay_fallback(&mut self, relays: Vec<RelayServer>) -> &mut Self {
+        self.relay_fallback = relays;
+        self
+    }
+
+    /// Sets the STUN servers used to gather server reflexive candidates, in order of preference.
+    ///
+    /// Before the stream gathers, each server but the last is probed by gathering the
+    /// candidates of a temporary stream: the first one yielding a server reflexive candidate
+    /// within `timeout` is used, otherwise the next one is probed. libnice contacts the STUN
+    /// server only once per stream, so the stream itself cannot fall back once it gathered.
+    /// The selection is reported via [AgentEvent::StunServerSelected]. Until then, the
+    /// candidates of the stream are delayed, and failures to gather are reported via
+    /// [Agent::audit_log] like with [StreamBuilder::defer_gathering].
+    ///
+    /// libnice configures the STUN server for the whole agent, so the server selected remains
+    /// configured for other streams as well (see [Agent::set_stun_server]). Streams gathering
+    /// while another stream probes may contact the server being probed.
+    pub fn set_stun_servers(&mut self, servers: Vec<SocketAddr>, timeout: Duration) -> &mut Self {
+        self.stun_servers = servers;
+        self.stun_timeout = timeout;
+        self
+    }
+
+    /// Restricts or prioritizes the address families of the stream's candidates.
+    ///
+    /// This only affects this stream, the local addresses of the agent are left untouched.
+    /// With [AddressFamilyPolicy::Ipv4Only] and [AddressFamilyPolicy::Ipv6Only], local
+    /// candidates of other families are still gathered by libnice but not emitted, and remote
+    /// candidates of other families are rejected (see [AgentEvent::CandidateRejected]). Since
+    /// neither side learns a candidate of the other family, libnice never forms (and checks)
+    /// pairs of it. To not gather them in the first place, add only addresses of the family
+    /// via [Agent::add_local_address].
+    ///
+    /// [AddressFamilyPolicy::PreferIpv6] only changes the priorities signaled to the remote
+    /// peer, see its documentation.
+    pub fn set_address_family_policy(&mut self, policy: AddressFamilyPolicy) -> &mut Self {
+        self.candidate_policy.address_family = policy;
+        self
+    }
+
+    /// Overrides the local preference of the local candidates matched by `selector`.
+    ///
+    /// This only changes the priority of the emitted candidates, i.e. what is signaled to the
+    /// remote peer. libnice does not allow changing the preferences it uses internally, so the
+    /// local agent keeps ordering its checks and, while controlling, nominating by its own
+    /// priorities. The override therefore only steers the selection if the remote peer is
+    /// controlling, e.g. towards wired interfaces over WLAN or VPN interfaces.
+    /// Higher values are preferred, libnice uses up to `65535` for host candidates.
+    /// If multiple overrides match a candidate, the one added first applies.
+    pub fn set_local_preference(&mut self, selector: CandidateSelector, preference: u16) -> &mut Self {
+        self.candidate_policy.local_preferences.push((selector, preference));
+        self
+    }
+
+    /// Hides the local addresses of the host, for privacy: host candidates are not emitted (nor
+    /// included in descriptions generated from the emitted candidates) and the related
+    /// addresses of server reflexive and relayed candidates are replaced with `0.0.0.0 0`.
+    ///
+    /// libnice still gathers host candidates, as they are the base of the other candidates, and
+    /// connectivity checks may still reach the peer from them within the same network. Without
+    /// a STUN or TURN server, no candidates are emitted at all. To send all traffic via relays,
+    /// additionally set [NiceAgentProperty::ForceRelay](crate::ffi::NiceAgentProperty::ForceRelay)
+    /// on the agent. [Stream::raw_candidates] is not affected.
+    pub fn set_redact_host_candidates(&mut self, redact: bool) -> &mut Self {
+        self.candidate_policy.redact_host = redact;
+        self
+    }
+
+    /// Holds back the local candidates until gathering is done and then emits them sorted by
+    /// component and priority (highest first), instead of emitting them as they are found.
+    ///
+    /// Useful if only the first few candidates can be signaled. Candidates found after
+    /// gathering has completed (e.g. late relay allocations) are emitted after the next
+    /// [Stream::regather].
+    pub fn set_sort_candidates_by_priority(&mut self, sort: bool) -> &mut Self {
+        self.sort_candidates = sort;
+        self
+    }
+
+    /// Stops emitting local candidates for a component once `max` of them have been emitted,
+    /// e.g. to bound the size of a SIP header or QR code carrying them.
+    ///
+    /// Combine with [StreamBuilder::set_sort_candidates_by_priority] to keep the `max`
+    /// candidates with the highest priority instead of the first ones found.
+    pub fn max_candidates_per_component(&mut self, max: usize) -> &mut Self {
+        self.max_candidates_per_component = Some(max);
+        self
+    }
+
+    /// Does not start gathering candidates when the stream is built, but only once
+    /// [Stream::start_gathering] is called.
+    ///
+    /// This allows to finish configuring the stream first (e.g. via [Stream::add_relay_server]
+    /// or [Stream::set_port_range]) or to wait for the consent of the user before any traffic is
+    /// sent to STUN or TURN servers.
+    pub fn defer_gathering(&mut self) -> &mut Self {
+        self.defer_gathering = true;
+        self
+    }
+
+    /// Whether the [futures::Stream] implementation of the [Stream] emits the local candidates
+    /// (default `true`).
+    ///
+    /// If disabled, local candidates are only converted to SDP once a subscription is created via
+    /// [Stream::candidates] (which still receives the candidates reported before), so
+    /// applications which consume the candidates reported by libnice via
+    /// [Stream::raw_candidates] skip the conversion.
+    pub fn set_emit_sdp_candidates(&mut self, emit: bool) -> &mut Self {
+        self.emit_sdp_candidates = emit;
+        self
+    }
+
+    /// Receives the packets of the stream's components on `ctx` instead of the agent's context,
+    /// so a stream with heavy traffic can be isolated on its own thread.
+    ///
+    /// By default, all streams share the [MainContext] of the agent. Connectivity checks, timers
+    /// and events of the stream are still processed on the agent's context, only the receive
+    /// callbacks (and with them [PacketSink]s and [PacketTransform]s) run on `ctx`, which must be
+    /// iterated by some thread.
+    pub fn set_recv_context(&mut self, ctx: MainContext) -> &mut Self {
+        self.recv_ctx = Some(ctx);
+        self
+    }
+
+    /// Applies `options` to the sockets of the stream's components whenever they gathered
+    /// candidates, including after [Stream::regather].
+    ///
+    /// Failures are reported via [AgentEvent::SocketOptionsFailed]. See
+    /// [StreamComponent::set_socket_options] to apply options to a single component.
+    pub fn set_socket_options(&mut self, options: SocketOptions) -> &mut Self {
+        self.socket_options = Some(options);
+        self
+    }
+
+    /// Attaches application data to the stream, see [Stream::set_user_data].
+    pub fn set_user_data(&mut self, data: UserData) -> &mut Self {
+        self.user_data = Some(data);
         self
     }
 
     /// Build the [Stream].
-    pub fn build(&mut self) -> BoolResult<Stream> {
-        let stream_id = self.agent.agent.add_stream(self.components as c_uint)?;
+    ///
+    /// The configuration is validated first, see [StreamConfigError]. On failure, everything
+    /// set up so far is removed again.
+    pub fn build(&mut self) -> Result<Stream, StreamBuildError> {
+        build_error::validate(self.components, &self.port_ranges)?;
+        if let Some(range) = self.fallback_port_range {
+            let fallback = (0..self.components).map(|index| (index, range)).collect();
+            build_error::validate(self.components, &fallback)?;
+        }
+
+        let stream_id = self
+            .agent
+            .agent
+            .add_stream(self.components as c_uint)
+            .map_err(|cause| StreamBuildError::new(StreamBuildPhase::AddStream, cause))?;
 
-        match self.configure_stream(stream_id) {
+        let handle = Arc::new(StreamHandle {
+            id: stream_id,
+            msg_sink: self.agent.msgs_sender.clone(),
+            discarded: AtomicBool::new(false),
+        });
+        match self.configure_stream(stream_id, &handle) {
             Ok(stream) => Ok(stream),
             Err(error) => {
+                // Removed right away, not once the partially built components are dropped
+                handle.discard();
                 self.agent.remove_stream_internal(stream_id);
                 Err(error)
             }
         }
     }
 
-    fn configure_stream(&mut self, stream_id: u32) -> BoolResult<Stream> {
-        let agent = &mut self.agent;
-        let ffi = &mut agent.agent;
+    /// Starts gathering, retrying with the fallback port range if it fails.
+    fn gather(&self, stream_id: c_uint) -> Result<(), StreamBuildError> {
+        let cause = match self.agent.start_gathering(stream_id) {
+            Ok(()) => return Ok(()),
+            Err(cause) => cause,
+        };
+        let mut ranges = self
+            .port_ranges
+            .values()
+            .filter(|range| **range != (0, 0))
+            .cloned()
+            .collect::<Vec<_>>();
+        if ranges.is_empty() || cause != ffi::NiceError::NoHostCandidates(stream_id) {
+            return Err(StreamBuildError::new(StreamBuildPhase::Gather, cause));
+        }
+        ranges.sort_unstable();
+        ranges.dedup();
+        if let Some((min_port, max_port)) = self.fallback_port_range {
+            let ffi = &self.agent.agent;
+            for component_id in 1..=(self.components as c_uint) {
+                ffi.set_port_range(stream_id, component_id, min_port, max_port);
+            }
+            if self.agent.start_gathering(stream_id).is_ok() {
+                return Ok(());
+            }
+            ranges.retain(|range| *range != (min_port, max_port));
+            ranges.push((min_port, max_port));
+        }
+        Err(StreamBuildError::PortRangeExhausted { ranges })
+    }
 
+    fn configure_stream(&mut self, stream_id: u32, handle: &Arc<StreamHandle>) -> Result<Stream, StreamBuildError> {
+        let agent = self.agent;
+        let ffi = &agent.agent;
+
+        #[cfg(any(test, feature = "testing"))]
+        let deterministic = *agent.deterministic.lock().unwrap();
+        #[cfg(any(test, feature = "testing"))]
+        {
+            if let Some(ids) = deterministic {
+                let (ufrag, pwd) = ids.credentials(stream_id);
+                let ufrag = CString::new(ufrag).expect("generated ufrag has no null bytes");
+                let pwd = CString::new(pwd).expect("generated pwd has no null bytes");
+                ffi.set_local_credentials(stream_id, &ufrag, &pwd).map_err(|_| {
+                    StreamBuildError::new(StreamBuildPhase::SetCredentials, ffi::NiceError::Failed("set_local_credentials"))
+                })?;
+            }
+        }
         let (local_ufrag, local_pwd) = ffi.get_local_credentials(stream_id).expect("local credentials");
         let local_ufrag = local_ufrag
             .into_string()
@@ -308,77 +1857,422 @@ impl<'a> StreamBuilder<'a> {
             .into_string()
             .expect("generated pwd is valid utf8");
 
+        if let Some(ctx) = &self.recv_ctx {
+            agent.recv_contexts.lock().unwrap().insert(stream_id, ctx.clone());
+        }
+        if let Some(options) = self.socket_options {
+            agent.socket_options.lock().unwrap().insert(stream_id, options);
+        }
+
         let mut components = Vec::new();
         for i in 0..(self.components as c_uint) {
             let component_id = i + 1;
-            let (mut source_sender, source) = mpsc::channel(self.inbound_buf_size);
-            let recv_handle = ffi.attach_recv(stream_id, component_id, &agent.ctx, move |buf| {
-                let _ = source_sender.try_send(buf.to_vec());
-            })?;
-
-            let (state_sender, state_stream) = mpsc::channel(8);
+            let (state_sender, state_stream) = state_queue::channel(self.state_buf_size);
             agent.state_sinks.lock().unwrap().insert((stream_id, component_id), state_sender);
 
-            components.push(StreamComponent {
-                _recv_handle: recv_handle,
+            let shared = Arc::new(ComponentShared::new(
+                stream_id,
+                component_id,
+                agent.events.clone(),
+                self.slow_consumer,
+                agent.memory.clone(),
+            ));
+            shared.rtt_probes.set_enabled(self.rtt_probes);
+            agent.components.lock().unwrap().insert((stream_id, component_id), shared.clone());
+
+            let (source_sender, source) = mpsc::channel(self.inbound_buf_size);
+            let packet_sink: SharedPacketSink = Arc::new(Mutex::new(Box::new(ChannelPacketSink {
+                sender: source_sender.clone(),
+                shared: shared.clone(),
+            })));
+
+            let mut component = StreamComponent {
+                agent: ffi.clone(),
+                ctx: agent.recv_ctx(stream_id),
+                timers: agent.get_timer_source(),
+                workers: agent.get_worker_pool(),
+                recv_handle: None,
+                packet_sink,
+                source_sender,
+                shared,
                 stream_id,
                 component_id,
                 state: ComponentState::Disconnected,
                 state_stream,
+                last_transition: None,
                 source,
+                reported_state: ComponentState::Disconnected,
+                read_buf: Vec::new(),
+                read_pos: 0,
+                read_packet: false,
                 sink: agent.msgs_sender.clone(),
-            });
+                data_sink: agent.data_sender.clone(),
+                stream: handle.clone(),
+                kind: ComponentKind::from_id(component_id, self.rtp_rtcp),
+            };
+            component
+                .resume_recv()
+                .map_err(|_| StreamBuildError::new(StreamBuildPhase::AttachRecv, ffi::NiceError::Failed("attach_recv")))?;
+            components.push(component);
         }
 
         for (index, (min_port, max_port)) in &self.port_ranges {
             ffi.set_port_range(stream_id, *index as c_uint + 1, *min_port, *max_port);
         }
 
-        let (candidate_sink, candidates) = mpsc::unbounded();
-        agent.candidate_sinks.lock().unwrap().insert(stream_id, candidate_sink);
+        for relay in &self.relay_servers {
+            for component_id in 1..=(self.components as c_uint) {
+                relay
+                    .apply(&agent.agent, stream_id, component_id)
+                    .map_err(|cause| StreamBuildError::new(StreamBuildPhase::ConfigureRelay, cause))?;
+                agent.add_pending_relay((stream_id, component_id), relay.addr);
+            }
+        }
+
+        let candidate_channel = Arc::new(Mutex::new(CandidateChannel::new()));
+        let candidates = if self.emit_sdp_candidates {
+            candidate_channel.lock().unwrap().subscribe()
+        } else {
+            mpsc::unbounded().1
+        };
+        agent.candidate_channels.lock().unwrap().insert(stream_id, candidate_channel.clone());
+        if !self.relay_fallback.is_empty() {
+            agent.relay_fallbacks.lock().unwrap().insert(stream_id, self.relay_fallback.clone());
+        }
+        if !self.stun_servers.is_empty() {
+            let selection = StunSelection {
+                servers: self.stun_servers.clone(),
+                timeout: self.stun_timeout,
+                next: 0,
+                probe: None,
+            };
+            agent.stun_selections.lock().unwrap().insert(stream_id, selection);
+        }
+        let mut candidate_policy = self.candidate_policy.clone();
+        if let Some(network) = &*agent.active_network.lock().unwrap() {
+            candidate_policy.network = Some(network.addresses.clone());
+        }
+        #[cfg(any(test, feature = "testing"))]
+        {
+            candidate_policy.deterministic = deterministic;
+        }
+        agent.candidate_policies.lock().unwrap().insert(stream_id, candidate_policy);
+        let emitter = CandidateEmitter::new(self.sort_candidates, self.max_candidates_per_component);
+        agent.candidate_emitters.lock().unwrap().insert(stream_id, emitter);
+        let gathering = Arc::new(Mutex::new(GatheringProgress::new(self.components)));
+        agent.gathering.lock().unwrap().insert(stream_id, gathering.clone());
+        candidate_channel.lock().unwrap().set_converter(Box::new(StreamCandidateConverter {
+            stream_id,
+            policies: Arc::clone(&agent.candidate_policies),
+            emitters: Arc::clone(&agent.candidate_emitters),
+            gathering: Arc::clone(&gathering),
+        }));
+
+        if !self.defer_gathering {
+            /* this call will already trigger some candidate found events */
+            self.gather(stream_id)?;
+        }
 
-        /* this call will already trigger some candidate found events */
-        ffi.gather_candidates(stream_id)?;
+        let user_data = Arc::new(UserDataSlot::default());
+        user_data.set(self.user_data.clone());
+        agent.stream_user_data.lock().unwrap().insert(stream_id, user_data.clone());
 
         Ok(Stream {
+            agent: agent.agent.clone(),
             id: stream_id,
             component_count: self.components,
             local_ufrag,
             local_pwd,
             msg_sink: agent.msgs_sender.clone(),
             candidates,
+            candidate_channel,
             components,
+            dropped_components: Vec::new(),
+            rtp_rtcp: self.rtp_rtcp,
+            remote_ice_options: None,
+            remote_ice_lite: false,
+            remote_description: None,
+            gathering_started: !self.defer_gathering,
+            gathering,
+            handle: handle.clone(),
+            user_data,
+            role_changes: agent.role_changes.clone(),
         })
     }
 }
 
+/// Delivers a state transition to the receiver of a component and only then reacts to it, as
+/// reacting (e.g. rechecking) may make libnice report further transitions.
+fn deliver_transition(
+    state_sinks: &Mutex<HashMap<ComponentId, StateSender>>,
+    shared: &Arc<ComponentShared>,
+    transition: StateTransition,
+) {
+    let key = (shared.stream_id, shared.component_id);
+    {
+        let mut state_sinks = state_sinks.lock().unwrap();
+        if let Some(sink) = state_sinks.get_mut(&key) {
+            if sink.push(transition).is_err() {
+                state_sinks.remove(&key);
+            }
+        }
+    }
+    reconnect::on_transition(shared, transition);
+}
+
+/// Notifies about and starts the streams released from the gathering queue.
+/// Ends the current gathering run of a stream, emitting the candidates held back until then.
+fn finish_candidates(channels: &Mutex<HashMap<c_uint, Arc<Mutex<CandidateChannel>>>>, stream_id: c_uint) {
+    if let Some(channel) = channels.lock().unwrap().get(&stream_id) {
+        channel.lock().unwrap().finish();
+    }
+}
+
+/// Converts the local candidates of a stream using the agent's policy and emitter for it.
+struct StreamCandidateConverter {
+    stream_id: c_uint,
+    policies: Arc<Mutex<HashMap<c_uint, LocalCandidatePolicy>>>,
+    emitters: Arc<Mutex<HashMap<c_uint, CandidateEmitter>>>,
+    gathering: Arc<Mutex<GatheringProgress>>,
+}
+
+impl CandidateConverter for StreamCandidateConverter {
+    fn convert(&mut self, candidate: &ffi::NiceCandidate) -> Option<Candidate> {
+        let candidate = match self.policies.lock().unwrap().get(&self.stream_id) {
+            Some(policy) => policy.apply(candidate.to_sdp())?,
+            None => candidate.to_sdp(),
+        };
+        self.gathering.lock().unwrap().record(candidate.component, &candidate.c_type);
+        match self.emitters.lock().unwrap().get_mut(&self.stream_id) {
+            Some(emitter) => emitter.push(candidate),
+            None => Some(candidate),
+        }
+    }
+
+    fn flush(&mut self) -> Vec<Candidate> {
+        match self.emitters.lock().unwrap().get_mut(&self.stream_id) {
+            Some(emitter) => emitter.flush(),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn start_dequeued(events: &EventSinks, sink: &mpsc::UnboundedSender<ControlMsg>, dequeued: Vec<(c_uint, Duration)>) {
+    for (stream_id, waited) in dequeued {
+        events.emit(AgentEvent::GatheringDequeued { stream_id, waited });
+        let _ = sink.unbounded_send(ControlMsg::StartGathering(stream_id));
+    }
+}
+
+/// The STUN servers of a stream, see [StreamBuilder::set_stun_servers].
+struct StunSelection {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+    /// The index of the next server to probe.
+    next: usize,
+    probe: Option<StunProbe>,
+}
+
+/// A temporary stream gathering candidates to find out whether a STUN server responds.
+struct StunProbe {
+    stream_id: c_uint,
+    server: SocketAddr,
+    /// Reports the server as unresponsive once expired.
+    _timer: Timer,
+}
+
+fn set_stun_server(agent: &ffi::NiceAgent, server: Option<SocketAddr>) -> BoolResult<()> {
+    agent.set_nice_property(NiceAgentProperty::StunServer(server.map(|server| server.ip().to_string())))?;
+    let port = server.map_or(0, |server| u32::from(server.port()));
+    agent.set_nice_property(NiceAgentProperty::StunPort(port))
+}
+
+/// A TURN server used to gather relayed candidates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayServer {
+    /// The address of the TURN server. libnice does not resolve host names.
+    pub addr: SocketAddr,
+    /// The TURN username.
+    pub username: String,
+    /// The TURN password.
+    pub password: String,
+    /// The transport used to communicate with the TURN server.
+    pub relay_type: RelayType,
+}
+
+impl RelayServer {
+    /// Creates a new relay server description.
+    pub fn new(
+        addr: SocketAddr,
+        username: impl Into<String>,
+        password: impl Into<String>,
+        relay_type: RelayType,
+    ) -> Self {
+        Self {
+            addr,
+            username: username.into(),
+            password: password.into(),
+            relay_type,
+        }
+    }
+
+    fn apply(&self, agent: &ffi::NiceAgent, stream_id: c_uint, component_id: c_uint) -> Result<(), ffi::NiceError> {
+        let to_cstring = |value: &str| {
+            CString::new(value).map_err(|_| ffi::NiceError::InvalidArgument {
+                operation: "set_relay_info",
+                reason: "relay settings must not contain null bytes",
+            })
+        };
+        agent.set_relay_info(
+            stream_id,
+            component_id,
+            &to_cstring(&self.addr.ip().to_string())?,
+            self.addr.port(),
+            &to_cstring(&self.username)?,
+            &to_cstring(&self.password)?,
+            self.relay_type,
+        )
+    }
+}
+
+/// Receiver of the inbound packets of a [StreamComponent].
+///
+/// By default, a component queues its packets to be read via its [futures::Stream]/[AsyncRead]
+/// implementation. A custom sink can be installed via [StreamComponent::set_packet_sink] to
+/// process packets inline instead, without any intermediate queue.
+pub trait PacketSink: Send + 'static {
+    /// Called for every inbound packet.
+    ///
+    /// This is called from the thread iterating the [MainContext] of the [Agent], so it must
+    /// return quickly to not delay the processing of other packets and connectivity checks.
+    fn on_packet(&mut self, packet: &[u8]);
+}
+
+type SharedPacketSink = Arc<Mutex<Box<dyn PacketSink>>>;
+
+/// The default [PacketSink], queueing packets for [StreamComponent] to read them.
+///
+/// Packets are dropped if the queue is full, see [AgentEvent::SlowConsumer].
+struct ChannelPacketSink {
+    sender: mpsc::Sender<Vec<u8>>,
+    shared: Arc<ComponentShared>,
+}
+
+impl PacketSink for ChannelPacketSink {
+    fn on_packet(&mut self, packet: &[u8]) {
+        if !self.shared.memory.try_reserve_inbound(packet.len()) {
+            self.shared.on_inbound_dropped();
+            return;
+        }
+        match self.sender.try_send(packet.to_vec()) {
+            Ok(()) => self.shared.slow_consumer.lock().unwrap().on_delivered(),
+            Err(err) => {
+                self.shared.memory.release_inbound(packet.len());
+                if err.is_full() {
+                    self.shared.on_inbound_dropped();
+                }
+            }
+        }
+    }
+}
+
+/// A [PacketSink] passing each packet to a callback, see [StreamComponent::on_data].
+struct CallbackPacketSink<F>(F);
+
+impl<F: FnMut(Vec<u8>) + Send + 'static> PacketSink for CallbackPacketSink<F> {
+    fn on_packet(&mut self, packet: &[u8]) {
+        (self.0)(packet.to_vec())
+    }
+}
+
+/// Guard keeping a callback registered via [StreamComponent::on_data] alive.
+///
+/// Dropping this guard restores the default behavior of emitting packets via the component's
+/// [futures::Stream] and [AsyncRead] implementations. Like [ffi::AttachRecvHandle], this replaces
+/// **any** [PacketSink] installed at that time, even if the callback has already been replaced.
+#[must_use = "when a DataCallbackGuard is dropped, it deregisters the callback"]
+pub struct DataCallbackGuard {
+    packet_sink: SharedPacketSink,
+    source_sender: mpsc::Sender<Vec<u8>>,
+    shared: Arc<ComponentShared>,
+}
+
+impl Drop for DataCallbackGuard {
+    fn drop(&mut self) {
+        let sink = ChannelPacketSink {
+            sender: self.source_sender.clone(),
+            shared: self.shared.clone(),
+        };
+        *self.packet_sink.lock().unwrap() = Box::new(sink);
+    }
+}
+
+/// Credentials of a single candidate, see [Stream::add_remote_candidate_with_credentials].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CandidateCredentials {
+    /// The candidate's username.
+    pub username: CString,
+    /// The candidate's password.
+    pub password: CString,
+}
+
 enum ControlMsg {
     SetRemoteCredentials(c_uint, CString, CString),
-    AddRemoteCandidate(ComponentId, Candidate),
+    AddRemoteCandidate(ComponentId, Candidate, Option<CandidateCredentials>),
+    RemoteGatheringDone(c_uint),
+    StartGathering(c_uint),
+    Regather(c_uint),
+    SetPortRange(ComponentId, u16, u16),
+    AddRelayServer(ComponentId, RelayServer),
+    /// Created by the agent itself once a component of a stream with a relay fallback failed.
+    RelayFallback(c_uint, Vec<RelayServer>),
+    /// Created by the agent itself once the STUN server probed for a stream responded (or not).
+    StunProbed(c_uint, bool),
+    /// Only created by the agent itself from the [DataMsg]s it received.
     Send(ComponentId, Vec<u8>),
+    DropComponent(ComponentId),
+    SetControllingMode(bool),
     DropStream(c_uint)
 }
 
+/// An outbound packet of a component.
+type DataMsg = (ComponentId, Vec<u8>);
+
 /// An ICE stream consisting of multiple components.
 ///
 /// Implements [futures::Stream] which emits the local ICE candidates for this stream as they are
-/// being discovered.
+/// being discovered. The stream ends once gathering is done, at which point
+/// [IceAttribute::EndOfCandidates] should be sent to the remote peer.
 ///
-/// Attention: This stream must be kept alive while using any of the components.
-///            If not done, the stream and the components will be unregistered
+/// The stream is removed from the agent once it and all of its components have been dropped.
 pub struct Stream {
+    agent: ffi::NiceAgent,
     id: c_uint,
     component_count: usize,
     local_ufrag: String,
     local_pwd: String,
     msg_sink: mpsc::UnboundedSender<ControlMsg>,
     candidates: mpsc::UnboundedReceiver<Candidate>,
+    candidate_channel: Arc<Mutex<CandidateChannel>>,
     components: Vec<StreamComponent>,
+    dropped_components: Vec<c_uint>,
+    rtp_rtcp: bool,
+    remote_ice_options: Option<IceOptions>,
+    /// Whether an applied remote description announced ICE lite.
+    remote_ice_lite: bool,
+    /// The remote description applied via [Stream::apply_answer] and
+    /// [Stream::update_remote_description].
+    remote_description: Option<SessionDescription>,
+    gathering_started: bool,
+    gathering: Arc<Mutex<GatheringProgress>>,
+    handle: Arc<StreamHandle>,
+    user_data: Arc<UserDataSlot>,
+    role_changes: EventSinks<RoleChange>,
 }
 
 impl Stream {
     /// See [Agent::stream_builder].
-    pub fn builder(agent: &mut Agent, components: usize) -> StreamBuilder {
+    pub fn builder(agent: &Agent, components: usize) -> StreamBuilder {
         StreamBuilder::new(agent, components)
     }
 
@@ -387,28 +2281,419 @@ impl Stream {
         self.id
     }
 
-    /// Returns the local STUN ufrag for this stream.
-    pub fn get_local_ufrag(&self) -> &str {
-        &self.local_ufrag
+    /// Returns the local STUN ufrag for this stream.
+    pub fn get_local_ufrag(&self) -> &str {
+        &self.local_ufrag
+    }
+
+    /// Returns the local STUN pwd for this stream.
+    pub fn get_local_pwd(&self) -> &str {
+        &self.local_pwd
+    }
+
+    /// Returns a new subscription to the changes of the agent's controlling mode, whether
+    /// requested via [Agent::set_controlling_mode] or due to a role conflict with the remote peer.
+    ///
+    /// Only changes which occur after subscribing are delivered.
+    pub fn role_changes(&self) -> mpsc::UnboundedReceiver<RoleChange> {
+        self.role_changes.subscribe()
+    }
+
+    /// Attaches application data to this stream (replacing any previous data), which can be
+    /// recovered by id via [Agent::stream_user_data], e.g. when handling [AgentEvent]s.
+    pub fn set_user_data(&self, data: UserData) {
+        self.user_data.set(Some(data));
+    }
+
+    /// Returns the data attached to this stream, if any has been set and is of type `T`.
+    pub fn user_data<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.user_data.get()
+    }
+
+    /// Returns the local STUN credentials for this stream.
+    pub fn get_local_credentials(&self) -> IceCredentials {
+        IceCredentials {
+            ufrag: self.local_ufrag.clone(),
+            pwd: self.local_pwd.clone(),
+        }
+    }
+
+    /// Restarts ICE processing for this stream (an ICE restart), generating new local credentials.
+    ///
+    /// The new credentials must be sent to the remote peer, which must restart as well.
+    /// This also releases any pair pinned via [StreamComponent::pin_selected_pair].
+    pub fn restart(&mut self) -> BoolResult<()> {
+        self.check_open()?;
+        self.agent.restart_stream(self.id)?;
+        let (local_ufrag, local_pwd) = self.agent.get_local_credentials(self.id)?;
+        self.local_ufrag = local_ufrag.into_string().expect("generated ufrag is valid utf8");
+        self.local_pwd = local_pwd.into_string().expect("generated pwd is valid utf8");
+        Ok(())
+    }
+
+    /// Returns whether the [Agent] of this stream has been dropped.
+    ///
+    /// Once closed, all operations of this stream and its components fail.
+    pub fn is_closed(&self) -> bool {
+        self.handle.msg_sink.is_closed()
+    }
+
+    fn check_open(&self) -> BoolResult<()> {
+        if self.is_closed() {
+            return Err(glib_bool_error!("agent has been closed"));
+        }
+        Ok(())
+    }
+
+    fn send_msg(&self, msg: ControlMsg) -> BoolResult<()> {
+        send_control_msg(&self.msg_sink, msg)
+    }
+
+    /// Set the remote STUN credentials for this stream.
+    pub fn set_remote_credentials(&mut self, ufrag: CString, pwd: CString) -> BoolResult<()> {
+        self.send_msg(ControlMsg::SetRemoteCredentials(self.id, ufrag, pwd))
+    }
+
+    /// Sets the ICE options advertised by the remote peer (its `a=ice-options` attribute).
+    pub fn set_remote_ice_options(&mut self, options: IceOptions) {
+        self.remote_ice_options = Some(options);
+    }
+
+    /// Returns the ICE options of the remote peer, if they have been set.
+    pub fn get_remote_ice_options(&self) -> Option<&IceOptions> {
+        self.remote_ice_options.as_ref()
+    }
+
+    /// Returns whether the remote peer is expected to signal the end of its candidates.
+    ///
+    /// A peer which does not support trickle ICE sends all of its candidates in its description,
+    /// so there is no point in waiting for an end-of-candidates indication from it.
+    /// Until the remote options have been set, trickle support is assumed.
+    pub fn expects_end_of_candidates(&self) -> bool {
+        self.remote_ice_options
+            .as_ref()
+            .map_or(true, |options| options.trickle)
+    }
+
+    /// Validates and normalizes a remote ICE candidate (see [CandidateValidation]) and adds it
+    /// to this stream.
+    ///
+    /// Candidates usually come from the remote peer, so they are validated instead of trusted.
+    pub fn add_remote_candidate(&mut self, candidate: Candidate) -> Result<(), CandidateError> {
+        candidate.validate(self.component_count)?;
+        let candidate = candidate.normalize();
+        let msg = ControlMsg::AddRemoteCandidate((self.id, candidate.component), candidate, None);
+        self.send_msg(msg).map_err(|_| CandidateError::Closed)
+    }
+
+    /// Adds a new remote ICE candidate with its own credentials for this stream.
+    ///
+    /// Only needed for the MSN and OC2007 compatibility modes, where credentials are exchanged
+    /// per candidate rather than per stream.
+    pub fn add_remote_candidate_with_credentials(
+        &mut self,
+        candidate: Candidate,
+        credentials: CandidateCredentials,
+    ) -> Result<(), CandidateError> {
+        candidate.validate(self.component_count)?;
+        let candidate = candidate.normalize();
+        let key = (self.id, candidate.component);
+        self.send_msg(ControlMsg::AddRemoteCandidate(key, candidate, Some(credentials)))
+            .map_err(|_| CandidateError::Closed)
+    }
+
+    /// Applies the ICE parameters of a parsed remote description (usually the answer to a local
+    /// offer): the credentials, the ICE options, the candidates and end-of-candidates, in that
+    /// order.
+    ///
+    /// Everything is validated before anything is applied, so nothing is applied if the
+    /// credentials contain null bytes or any candidate is invalid (see [CandidateValidation]).
+    ///
+    /// If the remote peer is an ICE lite implementation (see [SessionDescription::ice_lite]), the
+    /// agent is put into controlling mode first, as required by RFC 8445. Note that libnice only
+    /// supports a controlling mode per agent, so this affects all of its streams.
+    pub fn apply_answer(&mut self, answer: &SessionDescription) -> BoolResult<()> {
+        self.check_open()?;
+        let (ufrag, pwd, candidates) = self.validate_description(answer)?;
+        self.apply_validated_description(answer, ufrag, pwd, candidates)
+    }
+
+    /// Validates everything [Stream::apply_answer] applies, returns the credentials and the
+    /// normalized candidates.
+    fn validate_description(&self, description: &SessionDescription) -> BoolResult<(CString, CString, Vec<Candidate>)> {
+        let credentials = &description.credentials;
+        let ufrag = CString::new(credentials.ufrag.as_str()).map_err(|_| glib_bool_error!("ufrag contains null bytes"))?;
+        let pwd = CString::new(credentials.pwd.as_str()).map_err(|_| glib_bool_error!("pwd contains null bytes"))?;
+        let mut candidates = Vec::with_capacity(description.candidates.len());
+        for candidate in &description.candidates {
+            candidate.validate(self.component_count)?;
+            candidates.push(candidate.clone().normalize());
+        }
+        Ok((ufrag, pwd, candidates))
+    }
+
+    fn apply_validated_description(
+        &mut self,
+        answer: &SessionDescription,
+        ufrag: CString,
+        pwd: CString,
+        candidates: Vec<Candidate>,
+    ) -> BoolResult<()> {
+        self.apply_remote_ice_lite(answer.ice_lite)?;
+        self.set_remote_credentials(ufrag, pwd)?;
+        self.set_remote_ice_options(answer.options.clone());
+        for candidate in candidates {
+            self.send_msg(ControlMsg::AddRemoteCandidate((self.id, candidate.component), candidate, None))?;
+        }
+        if answer.end_of_candidates {
+            self.set_remote_gathering_done()?;
+        }
+        self.remote_description = Some(answer.clone());
+        Ok(())
+    }
+
+    /// Applies a new version of the remote description, e.g. after a renegotiation or with
+    /// further trickled candidates, compared to the one applied before via this method or
+    /// [Stream::apply_answer].
+    ///
+    /// If the remote credentials changed, the remote peer restarted ICE: this stream is restarted
+    /// as well (see [Stream::restart]) and the whole description is applied. Otherwise only the
+    /// new candidates, the ICE options and a new end-of-candidates are applied. Nothing is applied
+    /// (and this stream is not restarted) if the credentials or any of the new candidates are
+    /// invalid.
+    pub fn update_remote_description(
+        &mut self,
+        description: SessionDescription,
+    ) -> BoolResult<RemoteDescriptionUpdate> {
+        let (new_candidates, end_of_candidates) =
+            match renegotiation::diff(self.remote_description.as_ref(), &description) {
+                DescriptionDiff::Full { restart } => {
+                    // Validated first, so an invalid description does not restart this stream
+                    self.check_open()?;
+                    let (ufrag, pwd, candidates) = self.validate_description(&description)?;
+                    if restart {
+                        self.restart()?;
+                    }
+                    self.apply_validated_description(&description, ufrag, pwd, candidates)?;
+                    return Ok(RemoteDescriptionUpdate {
+                        ice_restart: restart,
+                        added_candidates: description.candidates.len(),
+                        end_of_candidates: description.end_of_candidates,
+                    });
+                }
+                DescriptionDiff::Incremental { new_candidates, end_of_candidates } => {
+                    (new_candidates, end_of_candidates)
+                }
+            };
+
+        self.check_open()?;
+        for candidate in &new_candidates {
+            candidate.validate(self.component_count)?;
+        }
+        self.apply_remote_ice_lite(description.ice_lite)?;
+        self.set_remote_ice_options(description.options.clone());
+        for candidate in &new_candidates {
+            let candidate = candidate.clone().normalize();
+            self.send_msg(ControlMsg::AddRemoteCandidate((self.id, candidate.component), candidate, None))?;
+        }
+        if end_of_candidates {
+            self.set_remote_gathering_done()?;
+        }
+
+        let update = RemoteDescriptionUpdate {
+            ice_restart: false,
+            added_candidates: new_candidates.len(),
+            end_of_candidates,
+        };
+        if let Some(applied) = &mut self.remote_description {
+            applied.options = description.options;
+            applied.fingerprint = description.fingerprint;
+            applied.candidates.extend(new_candidates);
+            applied.end_of_candidates |= end_of_candidates;
+            applied.ice_lite = description.ice_lite;
+        }
+        Ok(update)
+    }
+
+    /// Returns whether the remote peer announced to be an ICE lite implementation in the
+    /// description applied via [Stream::apply_answer] or [Stream::update_remote_description].
+    pub fn is_remote_ice_lite(&self) -> bool {
+        self.remote_ice_lite
+    }
+
+    fn apply_remote_ice_lite(&mut self, ice_lite: bool) -> BoolResult<()> {
+        if ice_lite && !self.remote_ice_lite {
+            self.send_msg(ControlMsg::SetControllingMode(true))?;
+        }
+        self.remote_ice_lite = ice_lite;
+        Ok(())
+    }
+
+    /// Signals that the remote peer has finished gathering candidates (`a=end-of-candidates`).
+    pub fn set_remote_gathering_done(&mut self) -> BoolResult<()> {
+        self.send_msg(ControlMsg::RemoteGatheringDone(self.id))
+    }
+
+    /// Returns whether local candidate gathering has completed, i.e. the last candidate has been
+    /// emitted (see [Stream::candidates]) and `a=end-of-candidates` should be sent to the remote
+    /// peer once it has been signaled.
+    pub fn is_local_gathering_done(&self) -> bool {
+        !self.candidate_channel.lock().unwrap().is_gathering()
+    }
+
+    /// Returns the progress of candidate gathering, e.g. to show it while setting up a call.
+    ///
+    /// Counts only include candidates emitted by this stream (i.e. not filtered by the
+    /// [AddressFamilyPolicy]), across all (re)gathering runs.
+    pub fn gathering_status(&self) -> GatheringStatus {
+        // Counting requires the candidates reported so far to be converted
+        self.candidate_channel.lock().unwrap().convert_pending();
+        self.gathering.lock().unwrap().status()
+    }
+
+    /// Returns a summary of each of the last (re)gathering runs of this stream, oldest first,
+    /// e.g. to find out why only host candidates have been gathered.
+    pub fn gathering_reports(&self) -> Vec<GatheringReport> {
+        self.gathering.lock().unwrap().reports()
+    }
+
+    /// Estimates the behavior of the NAT in front of this agent from the mappings observed in
+    /// the latest completed gathering run (see [GatheringReport::estimate_nat]), or the running
+    /// one if none completed yet, e.g. to triage connectivity complaints.
+    ///
+    /// Mappings of different runs are never compared, as NATs expire mappings in the meantime
+    /// and the local ports may differ between runs.
+    /// The more components and relay servers the stream has, the more reliable the estimate is.
+    pub fn estimate_nat(&self) -> NatEstimate {
+        let reports = self.gathering_reports();
+        reports
+            .iter()
+            .rev()
+            .find(|report| report.done)
+            .or_else(|| reports.last())
+            .map_or_else(|| NatEstimate::from_mappings(&[]), GatheringReport::estimate_nat)
+    }
+
+    /// Limits the range of ports used for host candidates of all components.
+    ///
+    /// Only takes effect if gathering has not yet started, see [StreamBuilder::set_port_range].
+    pub fn set_port_range(&mut self, min_port: u16, max_port: u16) -> BoolResult<()> {
+        for component_id in self.component_ids() {
+            self.send_msg(ControlMsg::SetPortRange((self.id, component_id), min_port, max_port))?;
+        }
+        Ok(())
+    }
+
+    /// Limits the range of ports used for host candidates of the component at the specified index.
+    /// Note that the first component (with id `1`) is at index `0`.
+    ///
+    /// Only takes effect if gathering has not yet started,
+    /// see [StreamBuilder::set_component_port_range].
+    ///
+    /// Fails if `component_index >= components`.
+    pub fn set_component_port_range(
+        &mut self,
+        component_index: usize,
+        min_port: u16,
+        max_port: u16,
+    ) -> Result<(), StreamConfigError> {
+        if component_index >= self.component_count {
+            return Err(StreamConfigError::ComponentIndexOutOfRange {
+                component_index,
+                components: self.component_count,
+            });
+        }
+        let component_id = component_index as c_uint + 1;
+        self.send_msg(ControlMsg::SetPortRange((self.id, component_id), min_port, max_port))
+            .map_err(|_| StreamConfigError::Closed)
+    }
+
+    /// Adds a TURN relay server for all components of this stream.
+    ///
+    /// If the stream has already started gathering, a relayed candidate is allocated right away.
+    /// Call [Stream::regather] beforehand if this stream has already emitted all of its candidates.
+    pub fn add_relay_server(&mut self, relay: RelayServer) -> BoolResult<()> {
+        for component_id in self.component_ids() {
+            self.send_msg(ControlMsg::AddRelayServer((self.id, component_id), relay.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Starts gathering candidates for a stream built with [StreamBuilder::defer_gathering].
+    ///
+    /// Fails if gathering has already been started. Gathering starts once the [Agent] handles
+    /// the request, failures (e.g. exhausted port ranges) are reported via [Agent::audit_log].
+    pub fn start_gathering(&mut self) -> BoolResult<()> {
+        self.check_open()?;
+        if self.gathering_started {
+            return Err(glib_bool_error!("gathering has already been started"));
+        }
+        self.send_msg(ControlMsg::StartGathering(self.id))?;
+        self.gathering_started = true;
+        Ok(())
+    }
+
+    /// Re-runs candidate gathering for this stream and resumes emitting the candidates found.
+    ///
+    /// This stream must be `poll()`ed again afterwards to receive the new candidates, which ends
+    /// once gathering has completed again.
+    ///
+    /// libnice gathers host and server reflexive candidates only once per stream. Once that has
+    /// completed, regathering only yields the relayed candidates of relay servers added since
+    /// (see [Stream::add_relay_server]), if there are none the candidates end right away.
+    /// Build a new stream to gather all candidates again.
+    pub fn regather(&mut self) -> BoolResult<()> {
+        self.check_open()?;
+        {
+            let mut channel = self.candidate_channel.lock().unwrap();
+            channel.rearm();
+            self.candidates = channel.subscribe();
+        }
+        self.gathering_started = true;
+        self.send_msg(ControlMsg::Regather(self.id))
+    }
+
+    /// Returns a new subscription to the local candidates of this stream, which ends once
+    /// gathering is done (or right after the candidates of the last run if gathering is done
+    /// already).
+    ///
+    /// Subscriptions are independent of each other and of the [futures::Stream] implementation
+    /// of this stream, e.g. for signaling and logging the candidates. Each one starts with the
+    /// candidates emitted so far. Call this again after [Stream::regather] to receive the
+    /// candidates of the new gathering run, e.g. after an ICE restart.
+    pub fn candidates(&mut self) -> mpsc::UnboundedReceiver<Candidate> {
+        self.candidate_channel.lock().unwrap().subscribe()
     }
 
-    /// Returns the local STUN pwd for this stream.
-    pub fn get_local_pwd(&self) -> &str {
-        &self.local_pwd
+    /// Like [Stream::candidates], but each candidate is tagged with the [ComponentKind] of the
+    /// component it belongs to, e.g. to route candidates to per-component signaling.
+    pub fn tagged_candidates(&mut self) -> impl FuturesStream<Item = ComponentCandidate> {
+        let rtp_rtcp = self.rtp_rtcp;
+        self.candidates().map(move |candidate| ComponentCandidate {
+            kind: candidate.component_kind(rtp_rtcp),
+            candidate,
+        })
     }
 
-    /// Set the remote STUN credentials for this stream.
-    pub fn set_remote_credentials(&mut self, ufrag: CString, pwd: CString) {
-        let msg = ControlMsg::SetRemoteCredentials(self.id, ufrag, pwd);
-        let _ = self.msg_sink.unbounded_send(msg);
+    /// Like [Stream::candidates], but for the local candidates as reported by libnice, without
+    /// converting them to SDP first. The [AddressFamilyPolicy], local preference overrides and
+    /// the batching and limits of [StreamBuilder] do not apply to them.
+    ///
+    /// Use [ffi::NiceCandidate::to_sdp] to convert a candidate once needed.
+    pub fn raw_candidates(&mut self) -> mpsc::UnboundedReceiver<ffi::NiceCandidate> {
+        self.candidate_channel.lock().unwrap().subscribe_raw()
     }
 
-    /// Adds a new remote ICE candidate for this stream.
-    pub fn add_remote_candidate(&mut self, candidate: Candidate) {
-        assert!(candidate.component > 0);
-        assert!((candidate.component as usize) <= self.component_count);
-        let msg = ControlMsg::AddRemoteCandidate((self.id, candidate.component), candidate);
-        let _ = self.msg_sink.unbounded_send(msg);
+    /// Applies an ICE attribute received from the remote peer.
+    pub fn apply_remote_attribute(&mut self, attribute: IceAttribute) -> BoolResult<()> {
+        match attribute {
+            IceAttribute::Options(options) => {
+                self.set_remote_ice_options(options);
+                Ok(())
+            }
+            IceAttribute::Candidate(candidate) => Ok(self.add_remote_candidate(candidate)?),
+            IceAttribute::EndOfCandidates => self.set_remote_gathering_done(),
+        }
     }
 
     /// Returns a references to the components of this stream.
@@ -421,20 +2706,70 @@ impl Stream {
         &mut self.components
     }
 
+    /// Returns the component of the given kind, unless it has been taken from this stream.
+    pub fn component(&self, kind: ComponentKind) -> Option<&StreamComponent> {
+        self.components.iter().find(|component| component.kind == kind)
+    }
+
+    /// Returns the component of the given kind, unless it has been taken from this stream.
+    pub fn component_mut(&mut self, kind: ComponentKind) -> Option<&mut StreamComponent> {
+        self.components.iter_mut().find(|component| component.kind == kind)
+    }
+
+    /// Returns the RTP component of a stream built via [Agent::rtp_rtcp_stream_builder].
+    pub fn rtp(&mut self) -> Option<&mut StreamComponent> {
+        self.component_mut(ComponentKind::Rtp)
+    }
+
+    /// Returns the RTCP component of a stream built via [Agent::rtp_rtcp_stream_builder].
+    pub fn rtcp(&mut self) -> Option<&mut StreamComponent> {
+        self.component_mut(ComponentKind::Rtcp)
+    }
+
+    /// Drops a surplus component, e.g. the RTCP component once rtcp-mux has been negotiated.
+    ///
+    /// libnice cannot remove single components, so the component stays part of the stream
+    /// within libnice (it still takes part in gathering and eventually fails its connectivity
+    /// checks). Its local candidates are no longer emitted by this stream and all of its
+    /// resources held by this crate are released. Relay servers and port ranges set via this
+    /// stream afterwards no longer apply to it.
+    ///
+    /// Fails if the component has been taken from this stream or is the last remaining one.
+    pub fn drop_component(&mut self, component_id: c_uint) -> BoolResult<()> {
+        self.check_open()?;
+        let index = self
+            .components
+            .iter()
+            .position(|component| component.component_id == component_id)
+            .ok_or_else(|| glib_bool_error!("component {} is not part of the stream", component_id))?;
+        if self.component_ids().count() <= 1 {
+            return Err(glib_bool_error!("a stream needs at least one component"));
+        }
+        self.send_msg(ControlMsg::DropComponent((self.id, component_id)))?;
+        self.candidate_channel.lock().unwrap().drop_component(component_id);
+        self.dropped_components.push(component_id);
+        self.components.remove(index);
+        Ok(())
+    }
+
+    /// The ids of the components which have not been dropped.
+    fn component_ids(&self) -> impl Iterator<Item = c_uint> + '_ {
+        (1..=(self.component_count as c_uint)).filter(move |id| !self.dropped_components.contains(id))
+    }
+
     /// Returns the components of this stream, returning an empty Vec on subsequent calls.
     pub fn take_components(&mut self) -> Vec<StreamComponent> {
         std::mem::replace(&mut self.components, Vec::new())
     }
 
-    /*
     /// Returns the components of this stream, consuming the stream.
     ///
-    /// Note that this should probably only be called after all ICE candidates have been exchanged.
-    /// Until then, use [Stream::mut_components] or [Stream::take_components] instead.
-    pub fn into_components(self) -> Vec<StreamComponent> {
-        self.components
+    /// The components keep the stream registered at the agent. Note that local candidates can no
+    /// longer be received afterwards, so this should only be called once all ICE candidates
+    /// have been exchanged. Until then, use [Stream::mut_components] or [Stream::take_components].
+    pub fn into_components(mut self) -> Vec<StreamComponent> {
+        self.take_components()
     }
-    */
 }
 
 impl FuturesStream for Stream {
@@ -447,22 +2782,267 @@ impl FuturesStream for Stream {
     }
 }
 
-impl Drop for Stream {
+/// Shared by a [Stream] and its components, removes the stream once the last of them is dropped.
+struct StreamHandle {
+    id: c_uint,
+    msg_sink: mpsc::UnboundedSender<ControlMsg>,
+    discarded: AtomicBool,
+}
+
+impl StreamHandle {
+    /// Marks the stream as already removed, e.g. after [StreamBuilder::build] failed.
+    fn discard(&self) {
+        self.discarded.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for StreamHandle {
     fn drop(&mut self) {
-        let _ = self.msg_sink.unbounded_send(ControlMsg::DropStream(self.id));
+        // The agent removes all of its streams itself when dropped
+        if !self.msg_sink.is_closed() && !self.discarded.load(Ordering::Relaxed) {
+            let _ = self.msg_sink.unbounded_send(ControlMsg::DropStream(self.id));
+        }
+    }
+}
+
+/// Queues a message for the [Agent], failing if it has been dropped.
+fn send_control_msg(sink: &mpsc::UnboundedSender<ControlMsg>, msg: ControlMsg) -> BoolResult<()> {
+    sink.unbounded_send(msg)
+        .map_err(|_| glib_bool_error!("agent has been closed"))
+}
+
+/// State of a component shared between its handles, its writers and its receive callback.
+struct ComponentShared {
+    stream_id: c_uint,
+    component_id: c_uint,
+    events: EventSinks,
+    #[cfg(feature = "pcap")]
+    capture: ComponentCapture,
+    rtt_probes: Arc<RttProbes>,
+    first_packet: FirstPacketSignal,
+    /// Set once the agent removed the component, see [ComponentShared::close].
+    removed: AtomicBool,
+    /// Set by [AsyncWrite::poll_close], see [StreamComponent::release].
+    released: AtomicBool,
+    transform: Mutex<Option<Box<dyn PacketTransform>>>,
+    last_sent: Mutex<Instant>,
+    last_received: Mutex<Instant>,
+    keepalive: Mutex<Option<Timer>>,
+    idle_timer: Mutex<Option<Timer>>,
+    disconnect: Mutex<DisconnectWatch>,
+    created_at: Instant,
+    state_history: Mutex<Vec<StateTransition>>,
+    /// The number of remote candidates added via the agent, see [FailureReason].
+    remote_candidates: AtomicUsize,
+    traffic: TrafficCounters,
+    user_data: UserDataSlot,
+    slow_consumer: Mutex<SlowConsumerDetector>,
+    congestion: Mutex<CongestionDetector>,
+    memory: Arc<MemoryAccount>,
+}
+
+impl ComponentShared {
+    fn new(
+        stream_id: c_uint,
+        component_id: c_uint,
+        events: EventSinks,
+        slow_consumer: SlowConsumerThreshold,
+        memory: Arc<MemoryAccount>,
+    ) -> Self {
+        Self {
+            stream_id,
+            component_id,
+            events,
+            #[cfg(feature = "pcap")]
+            capture: ComponentCapture::default(),
+            rtt_probes: Default::default(),
+            first_packet: FirstPacketSignal::default(),
+            removed: AtomicBool::new(false),
+            released: AtomicBool::new(false),
+            transform: Mutex::new(None),
+            last_sent: Mutex::new(Instant::now()),
+            last_received: Mutex::new(Instant::now()),
+            keepalive: Mutex::new(None),
+            idle_timer: Mutex::new(None),
+            disconnect: Mutex::default(),
+            created_at: Instant::now(),
+            state_history: Mutex::new(Vec::new()),
+            remote_candidates: AtomicUsize::new(0),
+            traffic: TrafficCounters::default(),
+            user_data: UserDataSlot::default(),
+            slow_consumer: Mutex::new(SlowConsumerDetector::new(slow_consumer)),
+            congestion: Mutex::new(CongestionDetector::default()),
+            memory,
+        }
+    }
+
+    /// Returns the signals known to the component itself for classifying a failure, the
+    /// candidate counts are filled in by the agent.
+    fn failure_signals(&self) -> FailureSignals {
+        let history = self.state_history.lock().unwrap();
+        FailureSignals {
+            previous: history.last().map(|transition| transition.state),
+            checks_started: history.iter().any(|transition| transition.state == ComponentState::Connecting),
+            remote_candidates: self.remote_candidates.load(Ordering::Relaxed),
+            ..FailureSignals::default()
+        }
+    }
+
+    /// Called from the agent whenever libnice reports a state change of the component (or the
+    /// agent closes it), returns the recorded transition.
+    fn record_state(&self, state: ComponentState, failure: Option<FailureReason>) -> StateTransition {
+        let timestamp = Instant::now();
+        let mut history = self.state_history.lock().unwrap();
+        let previous = history.last().map_or(ComponentState::Disconnected, |transition| transition.state);
+        if history.len() >= MAX_STATE_HISTORY {
+            history.remove(0);
+        }
+        let transition = StateTransition {
+            previous,
+            state,
+            timestamp,
+            elapsed: timestamp.duration_since(self.created_at),
+            failure,
+        };
+        history.push(transition);
+        transition
+    }
+
+    /// Called for every packet received by the component.
+    fn on_inbound(&self, buf: &[u8]) {
+        #[cfg(feature = "pcap")]
+        self.capture.record(Direction::Inbound, buf);
+        self.traffic.record_received(buf.len());
+        *self.last_received.lock().unwrap() = Instant::now();
+    }
+
+    /// Time since the last packet has been sent or received via the component (or since it has
+    /// been created).
+    fn idle_for(&self) -> Duration {
+        let last_sent = *self.last_sent.lock().unwrap();
+        let last_received = *self.last_received.lock().unwrap();
+        last_sent.max(last_received).elapsed()
+    }
+
+    /// Called whenever an inbound packet is dropped because the inbound buffer is full.
+    fn on_inbound_dropped(&self) {
+        self.traffic.record_dropped();
+        let report = self.slow_consumer.lock().unwrap().on_dropped(Instant::now());
+        if let Some((dropped, duration)) = report {
+            self.events.emit(AgentEvent::SlowConsumer {
+                stream_id: self.stream_id,
+                component_id: self.component_id,
+                dropped,
+                duration,
+            });
+        }
+    }
+
+    /// Returns the state last reported by libnice.
+    fn state(&self) -> ComponentState {
+        self.state_history
+            .lock()
+            .unwrap()
+            .last()
+            .map_or(ComponentState::Disconnected, |transition| transition.state)
+    }
+
+    /// Called by the agent for every packet passed to libnice while the component is connected,
+    /// `complete` is false if it could not be sent (or only partially).
+    fn on_send_result(&self, complete: bool) {
+        if let Some(congested) = self.congestion.lock().unwrap().on_send(complete) {
+            self.events.emit(AgentEvent::Congestion {
+                stream_id: self.stream_id,
+                component_id: self.component_id,
+                congested,
+            });
+        }
+    }
+
+    /// Called by the agent once it removed the stream or dropped the component.
+    fn close(&self) {
+        self.removed.store(true, Ordering::Relaxed);
+        self.first_packet.close();
+    }
+
+    /// Checks whether packets can still be sent via the component.
+    fn check_sendable(&self, sink: &mpsc::UnboundedSender<DataMsg>) -> Result<(), SendError> {
+        if sink.is_closed() {
+            return Err(SendError::AgentClosed);
+        }
+        if self.removed.load(Ordering::Relaxed) || self.released.load(Ordering::Relaxed) {
+            return Err(SendError::StreamClosed);
+        }
+        Ok(())
+    }
+
+    /// Queues a packet sent by the application via the component.
+    fn send(&self, sink: &mpsc::UnboundedSender<DataMsg>, buf: Vec<u8>) -> Result<(), SendError> {
+        self.check_sendable(sink)?;
+        let buf = match &mut *self.transform.lock().unwrap() {
+            Some(transform) => transform.outbound(buf).map_err(|err| SendError::TransformFailed(err.kind()))?,
+            None => buf,
+        };
+        let len = buf.len();
+        if !self.memory.try_reserve_outbound(len) {
+            return Err(SendError::QueueFull);
+        }
+        self.on_outbound(&buf);
+        sink.unbounded_send(((self.stream_id, self.component_id), buf)).map_err(|_| {
+            self.memory.release_outbound(len);
+            SendError::AgentClosed
+        })
+    }
+
+    /// Called for every packet sent by the application via the component.
+    fn on_outbound(&self, buf: &[u8]) {
+        #[cfg(feature = "pcap")]
+        self.capture.record(Direction::Outbound, buf);
+        self.traffic.record_sent(buf.len());
+        *self.last_sent.lock().unwrap() = Instant::now();
+    }
+
+    /// Replaces the active keepalive timer (if any), cancelling the previous one.
+    fn set_keepalive(&self, timer: Option<Timer>) {
+        let previous = std::mem::replace(&mut *self.keepalive.lock().unwrap(), timer);
+        drop(previous);
     }
 }
 
 /// A single ICE stream component.
 /// It implements [Stream]+[Sink] as well as [AsyncRead]+[AsyncWrite].
+///
+/// Components (and their [ComponentWriter]s) keep their stream registered at the agent, the
+/// libnice stream is only removed once the [Stream] and all of its component handles have been
+/// dropped. Dropping the [Agent] closes them regardless.
 pub struct StreamComponent {
-    _recv_handle: ffi::AttachRecvHandle,
+    agent: ffi::NiceAgent,
+    /// The context the packets are received on, see [StreamBuilder::set_recv_context].
+    ctx: MainContext,
+    timers: Arc<dyn TimerSource>,
+    workers: Option<Arc<WorkerPool>>,
+    recv_handle: Option<ffi::AttachRecvHandle>,
+    packet_sink: SharedPacketSink,
+    source_sender: mpsc::Sender<Vec<u8>>,
+    shared: Arc<ComponentShared>,
     stream_id: c_uint,
     component_id: c_uint,
     state: ComponentState,
-    state_stream: mpsc::Receiver<ComponentState>,
+    state_stream: StateReceiver,
+    last_transition: Option<StateTransition>,
     source: mpsc::Receiver<Vec<u8>>,
+    /// The state last reported via [DatagramTransport::poll_event].
+    reported_state: ComponentState,
+    /// The last inbound packet, of which [AsyncRead] has consumed `read_pos` bytes.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    /// Whether [AsyncRead] has read a packet yet, i.e. `read_buf` holds one.
+    read_packet: bool,
     sink: mpsc::UnboundedSender<ControlMsg>,
+    data_sink: mpsc::UnboundedSender<DataMsg>,
+    /// Keeps the stream registered while the component is alive.
+    stream: Arc<StreamHandle>,
+    kind: ComponentKind,
 }
 
 impl StreamComponent {
@@ -476,18 +3056,373 @@ impl StreamComponent {
         self.component_id
     }
 
-    /// Adds a remote ICE candidate to this stream component.
-    pub fn add_remote_candidate(&mut self, candidate: Candidate) {
-        let msg = ControlMsg::AddRemoteCandidate((self.stream_id, self.component_id), candidate);
-        let _ = self.sink.unbounded_send(msg);
+    /// Returns the role of this component within its stream.
+    pub fn kind(&self) -> ComponentKind {
+        self.kind
+    }
+
+    /// Attaches application data to this component (replacing any previous data), which can be
+    /// recovered by id via [Agent::component_user_data], e.g. when handling [AgentEvent]s.
+    pub fn set_user_data(&self, data: UserData) {
+        self.shared.user_data.set(Some(data));
+    }
+
+    /// Returns the data attached to this component, if any has been set and is of type `T`.
+    pub fn user_data<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.shared.user_data.get()
+    }
+
+    /// Returns whether the [Agent] of this component has been dropped.
+    ///
+    /// Once closed, sending fails and no more packets are received.
+    pub fn is_closed(&self) -> bool {
+        self.stream.msg_sink.is_closed()
+    }
+
+    /// Validates and normalizes a remote ICE candidate (see [CandidateValidation]) and adds it
+    /// to this stream component. The component id of the candidate must be the one of this
+    /// component.
+    pub fn add_remote_candidate(&mut self, candidate: Candidate) -> Result<(), CandidateError> {
+        if candidate.component != self.component_id {
+            return Err(CandidateError::ComponentMismatch {
+                component: candidate.component,
+                expected: self.component_id,
+            });
+        }
+        candidate.validate(self.component_id as usize)?;
+        let msg = ControlMsg::AddRemoteCandidate((self.stream_id, self.component_id), candidate.normalize(), None);
+        send_control_msg(&self.sink, msg).map_err(|_| CandidateError::Closed)
+    }
+
+    /// Returns the original length of the packet most recently read via [AsyncRead], if any.
+    pub fn current_packet_len(&self) -> Option<usize> {
+        if self.read_packet {
+            Some(self.read_buf.len())
+        } else {
+            None
+        }
+    }
+
+    /// Returns how many bytes of the packet most recently read via [AsyncRead] did not fit into
+    /// the read buffer and will be returned by the following reads.
+    ///
+    /// Reads never span multiple packets, so once this is zero, the next read starts a new packet.
+    pub fn unread_packet_len(&self) -> usize {
+        self.read_buf.len() - self.read_pos
+    }
+
+    /// Stops receiving packets for this component until [StreamComponent::resume_recv] is called.
+    ///
+    /// This detaches the libnice receive callback, so incoming packets stay queued in the socket
+    /// (and may be dropped by the operating system once its buffer is full) instead of being
+    /// dropped when the inbound buffer of this component is full.
+    ///
+    /// **Note**: While paused, libnice does not process any incoming STUN messages for this
+    ///           component either, so connectivity checks and keepalives will not be answered.
+    ///           Only pause for short periods of time.
+    pub fn pause_recv(&mut self) {
+        self.recv_handle = None;
+    }
+
+    /// Resumes receiving packets after [StreamComponent::pause_recv].
+    ///
+    /// Does nothing if receiving is not paused.
+    pub fn resume_recv(&mut self) -> BoolResult<()> {
+        if self.is_closed() {
+            return Err(glib_bool_error!("agent has been closed"));
+        }
+        if self.shared.released.load(Ordering::Relaxed) {
+            return Err(glib_bool_error!("component has been closed"));
+        }
+        if self.recv_handle.is_none() {
+            let (stream_id, component_id) = (self.stream_id, self.component_id);
+            let agent = self.agent.clone();
+            let packet_sink = self.packet_sink.clone();
+            let shared = self.shared.clone();
+            let recv_handle = self.agent.attach_recv(stream_id, component_id, &self.ctx, move |buf| {
+                shared.on_inbound(buf);
+                if shared.rtt_probes.handle_packet(&agent, stream_id, component_id, buf) {
+                    return;
+                }
+                let transformed = match &mut *shared.transform.lock().unwrap() {
+                    Some(transform) => match transform.inbound(buf) {
+                        Some(packet) => Some(packet),
+                        None => return,
+                    },
+                    None => None,
+                };
+                shared.first_packet.on_packet();
+                packet_sink.lock().unwrap().on_packet(transformed.as_deref().unwrap_or(buf));
+            })?;
+            self.recv_handle = Some(recv_handle);
+        }
+        Ok(())
+    }
+
+    /// Returns whether receiving has been paused via [StreamComponent::pause_recv].
+    pub fn is_recv_paused(&self) -> bool {
+        self.recv_handle.is_none()
+    }
+
+    /// Moves the receive callback of this component to `ctx` at runtime, e.g. to move a busy
+    /// component onto a thread of its own. See [ffi::AttachRecvHandle::migrate].
+    ///
+    /// Like [StreamBuilder::set_recv_context], this only affects receiving. The new context is
+    /// also used if receiving is paused and resumed later.
+    pub fn migrate_recv(&mut self, ctx: MainContext) {
+        if let Some(recv_handle) = &self.recv_handle {
+            recv_handle.migrate(&ctx);
+        }
+        self.ctx = ctx;
+    }
+
+    /// Installs a custom [PacketSink] which receives all inbound packets of this component.
+    ///
+    /// While a custom sink is installed, no packets are emitted via the [futures::Stream] and
+    /// [AsyncRead] implementations of this component, state changes are still tracked though.
+    ///
+    /// If the agent has a [WorkerPool], the sink is called on one of its threads, see
+    /// [Agent::set_worker_pool].
+    pub fn set_packet_sink(&mut self, sink: impl PacketSink) {
+        let sink: Box<dyn PacketSink> = match &self.workers {
+            Some(workers) => Box::new(workers.dispatch(Box::new(sink), self.shared.clone())),
+            None => Box::new(sink),
+        };
+        *self.packet_sink.lock().unwrap() = sink;
+    }
+
+    /// Registers a callback which is called for every inbound packet of this component, as an
+    /// alternative to consuming the component as a [futures::Stream].
+    ///
+    /// The callback is called from the thread iterating the [MainContext] of the [Agent] and is
+    /// deregistered once the returned guard is dropped.
+    /// See [StreamComponent::set_packet_sink] for more details.
+    pub fn on_data<F: FnMut(Vec<u8>) + Send + 'static>(&mut self, f: F) -> DataCallbackGuard {
+        self.set_packet_sink(CallbackPacketSink(f));
+        DataCallbackGuard {
+            packet_sink: self.packet_sink.clone(),
+            source_sender: self.source_sender.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Removes a custom [PacketSink] installed via [StreamComponent::set_packet_sink] and
+    /// resumes emitting inbound packets via the [futures::Stream] and [AsyncRead] implementations.
+    pub fn reset_packet_sink(&mut self) {
+        let sink = ChannelPacketSink {
+            sender: self.source_sender.clone(),
+            shared: self.shared.clone(),
+        };
+        *self.packet_sink.lock().unwrap() = Box::new(sink);
+    }
+
+    /// Installs a [PacketTransform] which transforms all packets sent and received via this
+    /// component (and its [ComponentWriter]s) from now on, e.g. an SRTP context once the DTLS
+    /// handshake has completed. Replaces any previously installed transform.
+    ///
+    /// Packets already queued are not transformed.
+    pub fn set_packet_transform(&mut self, transform: impl PacketTransform) {
+        *self.shared.transform.lock().unwrap() = Some(Box::new(transform));
+    }
+
+    /// Removes the [PacketTransform] installed via [StreamComponent::set_packet_transform].
+    pub fn reset_packet_transform(&mut self) {
+        self.shared.transform.lock().unwrap().take();
     }
 
     /// Sends a packet of data via this component.
     ///
     /// Note that the [Agent] needs to be `poll()`ed for sending to make progress.
-    pub fn unbounded_send(&mut self, item: Vec<u8>) {
-        let msg = ControlMsg::Send((self.stream_id, self.component_id), item);
-        let _ = self.sink.unbounded_send(msg);
+    ///
+    /// Fails if the agent or the stream has been closed or the memory limit of the agent is
+    /// reached, see [SendError].
+    pub fn unbounded_send(&mut self, item: Vec<u8>) -> BoolResult<()> {
+        Ok(self.shared.send(&self.data_sink, item)?)
+    }
+
+    /// Starts capturing all packets sent and received via this component into `out` in the pcap
+    /// format. Any previously active capture of this component is stopped.
+    ///
+    /// Synthetic IP/UDP headers are generated using the addresses of the selected pair at the time
+    /// each packet is captured (unspecified addresses if there is none yet).
+    /// Capturing stops on the first error writing to `out`.
+    #[cfg(feature = "pcap")]
+    pub fn start_capture(&mut self, out: impl Write + Send + 'static) -> io::Result<()> {
+        self.shared.capture.start(Box::new(out))
+    }
+
+    /// Stops capturing packets started via [StreamComponent::start_capture].
+    #[cfg(feature = "pcap")]
+    pub fn stop_capture(&mut self) {
+        self.shared.capture.stop();
+    }
+
+    /// Starts sending `payload` whenever nothing has been sent via this component for `interval`,
+    /// keeping NAT/firewall bindings and TURN permissions alive during idle periods.
+    ///
+    /// Keepalives are suppressed as long as the application itself sends packets at least
+    /// once per `interval`. Replaces any previously configured keepalive.
+    /// The payload must be something the remote application is prepared to receive and ignore.
+    pub fn set_app_keepalive(&mut self, interval: Duration, payload: Vec<u8>) {
+        let (stream_id, component_id) = (self.stream_id, self.component_id);
+        let agent = self.agent.clone();
+        let shared = Arc::downgrade(&self.shared);
+        let timer = self.timers.start(interval, Box::new(move || {
+            let shared = match shared.upgrade() {
+                Some(shared) => shared,
+                None => return false,
+            };
+            let mut last_sent = shared.last_sent.lock().unwrap();
+            if last_sent.elapsed() >= interval {
+                let _ = agent.send(stream_id, component_id, &payload);
+                shared.traffic.record_sent(payload.len());
+                *last_sent = Instant::now();
+            }
+            true
+        }));
+        self.shared.set_keepalive(Some(timer));
+    }
+
+    /// Stops sending keepalives configured via [StreamComponent::set_app_keepalive].
+    pub fn clear_app_keepalive(&mut self) {
+        self.shared.set_keepalive(None);
+    }
+
+    /// Returns whether sending via this component is currently failing, see
+    /// [AgentEvent::Congestion].
+    ///
+    /// Media encoders can use this to reduce their bitrate.
+    pub fn is_congested(&self) -> bool {
+        self.shared.congestion.lock().unwrap().is_congested()
+    }
+
+    /// Returns the time since the last packet has been sent or received via this component, or
+    /// since the component has been created if there has been none.
+    ///
+    /// Only application data (including keepalives configured via
+    /// [StreamComponent::set_app_keepalive]) counts, connectivity checks and STUN keepalives
+    /// handled by libnice do not.
+    pub fn idle_for(&self) -> Duration {
+        self.shared.idle_for()
+    }
+
+    /// Emits [AgentEvent::ComponentIdle] once nothing has been sent or received via this
+    /// component for `timeout` (see [StreamComponent::idle_for]), or stops doing so if `None`.
+    ///
+    /// The event is emitted once per idle period, i.e. again only after a packet has been sent
+    /// or received in between. It is emitted at most a quarter of `timeout` late.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => {
+                self.shared.idle_timer.lock().unwrap().take();
+                return;
+            }
+        };
+        let shared = Arc::downgrade(&self.shared);
+        let mut reported = false;
+        let period = (timeout / 4).max(Duration::from_millis(1));
+        let timer = self.timers.start(period, Box::new(move || {
+            let shared = match shared.upgrade() {
+                Some(shared) => shared,
+                None => return false,
+            };
+            let idle_for = shared.idle_for();
+            if idle_for < timeout {
+                reported = false;
+            } else if !reported {
+                reported = true;
+                shared.events.emit(AgentEvent::ComponentIdle {
+                    stream_id: shared.stream_id,
+                    component_id: shared.component_id,
+                    idle_for,
+                });
+            }
+            true
+        }));
+        let previous = std::mem::replace(&mut *self.shared.idle_timer.lock().unwrap(), Some(timer));
+        drop(previous);
+    }
+
+    /// Returns the native handles (file descriptors on Unix, `SOCKET`s on Windows) of the sockets
+    /// of this component, e.g. to set socket options not covered by [SocketOptions].
+    ///
+    /// The sockets are owned by libnice, must not be closed and only exist once the stream
+    /// started gathering.
+    pub fn native_sockets(&self) -> Vec<c_int> {
+        self.agent.get_sockets(self.stream_id, self.component_id)
+    }
+
+    /// Applies `options` to the current sockets of this component, see [SocketOptions].
+    ///
+    /// Sockets only exist once the stream started gathering. To apply options to all
+    /// components as soon as they exist, use [StreamBuilder::set_socket_options].
+    pub fn set_socket_options(&self, options: &SocketOptions) -> std::io::Result<()> {
+        for socket in self.native_sockets() {
+            options.apply(socket)?;
+        }
+        Ok(())
+    }
+
+    /// Sets how this component reacts to losing its connection, see [DisconnectPolicy].
+    ///
+    /// Replacing the policy abandons the handling of a current outage, the new policy applies
+    /// to the next loss of the connection.
+    pub fn set_disconnect_policy(&mut self, policy: DisconnectPolicy) {
+        self.shared.disconnect.lock().unwrap().set_policy(policy, self.agent.clone(), self.timers.clone());
+    }
+
+    /// Returns the policy set via [StreamComponent::set_disconnect_policy].
+    pub fn disconnect_policy(&self) -> DisconnectPolicy {
+        self.shared.disconnect.lock().unwrap().policy()
+    }
+
+    /// Pins the currently selected pair of this component, preventing it from changing due to
+    /// renomination or further connectivity checks. Keepalives are still being sent.
+    ///
+    /// libnice disables all further ICE processing for the component, the only way to resume
+    /// normal operation is an ICE restart via [Stream::restart].
+    ///
+    /// There is no way to unpin a pair: libnice offers no function to re-enable ICE processing,
+    /// only restarting the whole stream resets the component. That changes the local credentials,
+    /// which must be signaled to the remote peer, and restarts all components of the stream.
+    pub fn pin_selected_pair(&mut self) -> BoolResult<()> {
+        let (local, remote) = self
+            .agent
+            .get_selected_pair(self.stream_id, self.component_id)
+            .ok_or_else(|| glib_bool_error!("component has no selected pair"))?;
+        self.pin_pair(local.foundation(), remote.foundation())
+    }
+
+    /// Pins the (validated) pair consisting of the local and remote candidate with the given
+    /// foundations, see [StreamComponent::pin_selected_pair].
+    pub fn pin_pair(&mut self, local_foundation: &CStr, remote_foundation: &CStr) -> BoolResult<()> {
+        if self.is_closed() {
+            return Err(glib_bool_error!("agent has been closed"));
+        }
+        self.agent.set_selected_pair(self.stream_id, self.component_id, local_foundation, remote_foundation)
+    }
+
+    /// Returns a future which resolves once the first application packet has been received via
+    /// this component (right away if there has already been one).
+    ///
+    /// Unlike the `Connected` state, this shows that the remote application is up, e.g. to
+    /// start a handshake only then. RTT probes (see [StreamComponent::measure_rtt]) do not count.
+    /// The future resolves to `false` if the stream (or agent) is closed before.
+    pub fn wait_for_first_packet(&self) -> FirstPacketFuture {
+        self.shared.first_packet.wait()
+    }
+
+    /// Measures the round trip time over the selected pair of this component.
+    ///
+    /// libnice does not expose the round trip times of its STUN checks, so this sends custom
+    /// probes instead: 9 byte packets starting with `0xff` followed by `RTT`. They are only
+    /// answered if the remote peer uses this crate as well and enabled probes via
+    /// [StreamBuilder::set_rtt_probes], like this side must have. The returned future resolves
+    /// to `None` if no response has been received within `timeout`, or right away if probes are
+    /// not enabled.
+    pub fn measure_rtt(&self, timeout: Duration) -> RttFuture {
+        self.shared.rtt_probes.start(&self.agent, &*self.timers, self.stream_id, self.component_id, timeout)
     }
 
     /// Returns the current state of this component.
@@ -498,6 +3433,22 @@ impl StreamComponent {
         self.state
     }
 
+    /// Returns the transition into the current state (see [StreamComponent::get_state]), with the
+    /// previous state and when libnice reported the change.
+    pub fn last_transition(&self) -> Option<StateTransition> {
+        self.last_transition
+    }
+
+    /// Returns diagnostic information about this component, including the timestamps of its
+    /// state transitions (as reported by libnice, independent of this component being polled).
+    pub fn diagnostics(&self) -> ComponentDiagnostics {
+        ComponentDiagnostics {
+            created_at: self.shared.created_at,
+            state_history: self.shared.state_history.lock().unwrap().clone(),
+            traffic: self.shared.traffic.stats(),
+        }
+    }
+
     /// Returns a future which waits until the component is in the target state or has surpassed
     /// the target state (e.g. waiting for Connected will also be done when the state is Ready).
     ///
@@ -518,29 +3469,82 @@ impl StreamComponent {
         pin_mut!(state_stream);
         match state_stream.poll_next(cx) {
             Poll::Pending => return Poll::Pending,
-            Poll::Ready(Some(new_state)) => {
-                let old_state = std::mem::replace(&mut self.state, new_state);
+            Poll::Ready(Some(transition)) => {
+                let old_state = std::mem::replace(&mut self.state, transition.state);
+                self.last_transition = Some(transition);
                 return Poll::Ready(Some(old_state));
             }
             Poll::Ready(None) => return Poll::Ready(None),
         }
     }
 
+    /// Wraps this component in a lightweight reliability and ordering layer, see
+    /// [ReliableChannel]. The remote peer must do the same.
+    ///
+    /// Use this for e.g. a "mostly reliable" control channel without switching the whole agent to
+    /// reliable mode.
+    pub fn into_reliable(self, config: ArqConfig) -> ReliableChannel {
+        ReliableChannel::new(self, config)
+    }
+
+    /// Combines this component with an Encoder/Decoder, decoding each inbound packet into a frame
+    /// and sending each frame as a packet. See [ComponentFramed].
+    #[cfg(feature = "codec")]
+    pub fn framed<C>(self, codec: C) -> ComponentFramed<C> {
+        ComponentFramed::new(self, codec)
+    }
+
     /// Creates an writer for the stream
+    ///
+    /// Like the component itself, the writer keeps the stream registered at the agent.
     pub fn writer(&mut self) -> ComponentWriter {
         ComponentWriter{
             stream_id: self.stream_id,
             component_id: self.component_id,
-            sink: self.sink.clone()
+            data_sink: self.data_sink.clone(),
+            shared: self.shared.clone(),
+            stream: self.stream.clone(),
+        }
+    }
+
+    /// Creates a writer which spaces its packets according to `mode` instead of sending them
+    /// right away, see [PacedWriter].
+    pub fn paced_writer(&mut self, mode: PacingMode) -> PacedWriter {
+        PacedWriter::new(self.writer(), self.timers.as_ref(), mode)
+    }
+}
+
+impl StreamComponent {
+    /// Stops receiving and sending keepalives and releases the queued inbound packets.
+    ///
+    /// The stream stays registered at the agent until the component is dropped, as other handles
+    /// (e.g. [ComponentWriter]s) may still use it.
+    fn release(&mut self) {
+        self.recv_handle = None;
+        self.shared.set_keepalive(None);
+        self.shared.idle_timer.lock().unwrap().take();
+        self.shared.disconnect.lock().unwrap().stop();
+        // Packets which will never be read no longer count towards the memory limit
+        self.source.close();
+        while let Ok(Some(packet)) = self.source.try_next() {
+            self.shared.memory.release_inbound(packet.len());
         }
     }
 }
 
+impl Drop for StreamComponent {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
 /// A write for the stream
 pub struct ComponentWriter {
     stream_id: c_uint,
     component_id: c_uint,
-    sink: mpsc::UnboundedSender<ControlMsg>
+    data_sink: mpsc::UnboundedSender<DataMsg>,
+    shared: Arc<ComponentShared>,
+    stream: Arc<StreamHandle>,
 }
 
 impl ComponentWriter {
@@ -557,9 +3561,8 @@ impl ComponentWriter {
 
 impl Write for ComponentWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.sink.unbounded_send(ControlMsg::Send((self.stream_id, self.component_id), Vec::from(buf)))
-            .map_err(|err| std::io::Error::new(ErrorKind::BrokenPipe, err))
-            .map(|_| buf.len())
+        self.shared.send(&self.data_sink, Vec::from(buf))?;
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -572,7 +3575,9 @@ impl Clone for ComponentWriter {
         ComponentWriter {
             stream_id: self.stream_id,
             component_id: self.component_id,
-            sink: self.sink.clone()
+            data_sink: self.data_sink.clone(),
+            shared: self.shared.clone(),
+            stream: self.stream.clone(),
         }
     }
 }
@@ -623,21 +3628,24 @@ impl FuturesStream for StreamComponent {
         }
         let source = &mut self.source;
         pin_mut!(source);
-        source.poll_next(cx)
+        let packet = ready!(source.poll_next(cx));
+        if let Some(packet) = &packet {
+            self.shared.memory.release_inbound(packet.len());
+        }
+        Poll::Ready(packet)
     }
 }
 
 impl Sink<Vec<u8>> for StreamComponent {
-    type Error = (); // never
+    type Error = SendError;
 
     fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+        Poll::Ready(self.shared.check_sendable(&self.data_sink))
     }
 
     fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
-        let msg = ControlMsg::Send((self.stream_id, self.component_id), item);
-        let _ = self.sink.unbounded_send(msg);
-        Ok(())
+        let this = self.get_mut();
+        this.shared.send(&this.data_sink, item)
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
@@ -649,16 +3657,75 @@ impl Sink<Vec<u8>> for StreamComponent {
     }
 }
 
+/// The largest payload of a single UDP datagram.
+///
+/// When relaying via TURN, the actual limit is slightly lower due to the TURN overhead.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+impl DatagramTransport for StreamComponent {
+    fn max_datagram_size(&self) -> usize {
+        MAX_DATAGRAM_SIZE
+    }
+
+    fn send(&mut self, datagram: Vec<u8>) -> io::Result<()> {
+        check_datagram_size(self, &datagram)?;
+        Ok(self.shared.send(&self.data_sink, datagram)?)
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Vec<u8>>> {
+        Pin::new(self).poll_next(cx)
+    }
+
+    fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<TransportEvent>> {
+        loop {
+            if self.state != self.reported_state {
+                self.reported_state = self.state;
+                let event = match self.state {
+                    ComponentState::Ready => TransportEvent::Connected,
+                    ComponentState::Failed => TransportEvent::Failed,
+                    ComponentState::Disconnected => TransportEvent::Disconnected,
+                    // Intermediate states are not reported
+                    _ => continue,
+                };
+                return Poll::Ready(Some(event));
+            }
+            if ready!(self.poll_state(cx)).is_none() {
+                return Poll::Ready(None);
+            }
+        }
+    }
+}
+
 impl AsyncRead for StreamComponent {
+    /// Bytes of a packet which don't fit into `buf` are kept for the following reads, so no data
+    /// is lost. In datagram (non reliable) mode, use [StreamComponent::current_packet_len] and
+    /// [StreamComponent::unread_packet_len] to recover the packet boundaries.
+    ///
+    /// Empty packets are skipped, since reading zero bytes signals the end of the stream.
     fn poll_read(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<Result<usize, io::Error>> {
-        match self.poll_next(cx) {
-            Poll::Ready(Some(vec)) => Poll::Ready(vec.as_slice().read(buf)),
-            Poll::Ready(None) => Poll::Ready(Ok(0)),
-            Poll::Pending => Poll::Pending,
+        if self.read_pos < self.read_buf.len() {
+            let this = self.deref_mut();
+            let read = (&this.read_buf[this.read_pos..]).read(buf)?;
+            this.read_pos += read;
+            return Poll::Ready(Ok(read));
+        }
+        loop {
+            match self.as_mut().poll_next(cx) {
+                Poll::Ready(Some(vec)) if vec.is_empty() => continue,
+                Poll::Ready(Some(vec)) => {
+                    let read = vec.as_slice().read(buf)?;
+                    self.read_buf = vec;
+                    self.read_pos = read;
+                    self.read_packet = true;
+                    return Poll::Ready(Ok(read));
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
         }
     }
 }
@@ -669,15 +3736,23 @@ impl AsyncWrite for StreamComponent {
         _cx: &mut Context,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let _ = self.start_send(buf.to_vec());
-        Poll::Ready(Ok(buf.len()))
+        let result = self.shared.send(&self.data_sink, buf.to_vec());
+        Poll::Ready(result.map(|()| buf.len()).map_err(io::Error::from))
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), io::Error>> {
         Poll::Ready(Ok(()))
     }
 
+    /// Stops receiving and sending via this component: packets not read yet are discarded and
+    /// reads return end of file. Sending fails with [SendError::StreamClosed] (writes with
+    /// [io::ErrorKind::BrokenPipe]) like once the agent or the stream has been closed, including
+    /// via the [Sink] implementation and the writers of this component.
     fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        if !this.shared.released.swap(true, Ordering::Relaxed) {
+            this.release();
+        }
         Poll::Ready(Ok(()))
     }
 }
@@ -685,11 +3760,69 @@ impl AsyncWrite for StreamComponent {
 #[cfg(test)]
 mod test {
     use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn thread_safety() {
+        assert_send_sync::<Agent>();
+        assert_send::<Stream>();
+        assert_send::<StreamComponent>();
+        assert_send_sync::<ComponentWriter>();
+    }
+
+    /// Collects the timers instead of running them.
+    #[derive(Default)]
+    struct ManualTimers(Mutex<Vec<(Duration, crate::timer::TimerCallback)>>);
+
+    impl TimerSource for ManualTimers {
+        fn start(&self, period: Duration, callback: crate::timer::TimerCallback) -> Timer {
+            self.0.lock().unwrap().push((period, callback));
+            Timer::new(|| {})
+        }
+    }
+
+    #[test]
+    fn recheck_runs_after_loss_is_delivered() {
+        use futures::executor::block_on_stream;
+
+        let agent = ffi::NiceAgent::new(&MainContext::new(), NiceCompatibility::RFC5245);
+        let shared = Arc::new(ComponentShared::new(
+            1,
+            1,
+            EventSinks::default(),
+            SlowConsumerThreshold::default(),
+            Arc::default(),
+        ));
+        let timers = Arc::new(ManualTimers::default());
+        let policy = DisconnectPolicy::Recheck {
+            interval: Duration::from_secs(1),
+            give_up_after: Duration::from_secs(10),
+        };
+        shared.disconnect.lock().unwrap().set_policy(policy, agent, timers.clone());
+        let (sender, receiver) = state_queue::channel(8);
+        let state_sinks = Mutex::new(HashMap::new());
+        state_sinks.lock().unwrap().insert((1, 1), sender);
+        let deliver = |state| deliver_transition(&state_sinks, &shared, shared.record_state(state, None));
+
+        deliver(ComponentState::Ready);
+        deliver(ComponentState::Failed);
+        // The first recheck is scheduled instead of run while the loss is being reported
+        let periods = timers.0.lock().unwrap().iter().map(|(period, _)| *period).collect::<Vec<_>>();
+        assert_eq!(periods, vec![Duration::from_secs(0), Duration::from_secs(1)]);
+        let (_, mut first_recheck) = timers.0.lock().unwrap().remove(0);
+        assert!(!first_recheck());
+        // libnice checks the pairs again
+        deliver(ComponentState::Connecting);
+
+        drop(state_sinks);
+        let states = block_on_stream(receiver).map(|transition| transition.state).collect::<Vec<_>>();
+        assert_eq!(states, vec![ComponentState::Ready, ComponentState::Failed, ComponentState::Connecting]);
+    }
     use futures::StreamExt;
     use tokio::runtime;
     use glib::MainLoop;
-    use glib::translate::ToGlibPtr;
-    use std::ffi::CStr;
 
     #[test]
     fn connects_and_transmits_data() {
@@ -722,16 +3855,13 @@ mod test {
 
         println!("Creating server/client");
         // Create ICE agents
-        let mut server = Agent::new_rfc5245(main_loop.get_context());
-        let mut client = Agent::new_rfc5245(main_loop.get_context());
+        let server = Agent::new_rfc5245(main_loop.get_context());
+        let client = Agent::new_rfc5245(main_loop.get_context());
         client.set_controlling_mode(true);
 
-        unsafe {
-            let mut address: libnice_sys::NiceAddress = std::mem::MaybeUninit::uninit().assume_init();
-            libnice_sys::nice_address_set_ipv4(&mut address, 0x7F000001);
-            libnice_sys::nice_agent_add_local_address(server.agent.to_glib_none().0, &mut address);
-            libnice_sys::nice_agent_add_local_address(client.agent.to_glib_none().0, &mut address);
-        }
+        let localhost: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        server.add_local_address(localhost).unwrap();
+        client.add_local_address(localhost).unwrap();
 
         println!("Starting server/client");
         // Create one ICE stream per agent, each with one component
@@ -743,11 +3873,11 @@ mod test {
         server_stream.set_remote_credentials(
             CString::new(client_stream.get_local_ufrag()).unwrap(),
             CString::new(client_stream.get_local_pwd()).unwrap(),
-        );
+        ).unwrap();
         client_stream.set_remote_credentials(
             CString::new(server_stream.get_local_ufrag()).unwrap(),
             CString::new(server_stream.get_local_pwd()).unwrap(),
-        );
+        ).unwrap();
 
         // Poll agents to make connection (and candidate-gathering) progress
         // Note: Normally you'd want some way to drop the agent once you no longer need it,
@@ -760,11 +3890,11 @@ mod test {
         // but continuing might improve the network path taken and provide fallback options.
         for candidate in executor.block_on(server_stream.by_ref().collect::<Vec<Candidate>>()) {
             println!("Server candidate: {}", candidate.to_string());
-            client_stream.add_remote_candidate(candidate);
+            client_stream.add_remote_candidate(candidate).unwrap();
         }
         for candidate in executor.block_on(client_stream.by_ref().collect::<Vec<Candidate>>()) {
             println!("Client candidate: {}", candidate.to_string());
-            server_stream.add_remote_candidate(candidate);
+            server_stream.add_remote_candidate(candidate).unwrap();
         }
 
         // Grab components for later use (you could also ship them off to different tasks here)
@@ -781,8 +3911,8 @@ mod test {
                 .unwrap();
 
             // Send some data (potentially unreliable, hence unbounded)
-            server_component.unbounded_send(vec![1, 2, 3, 4, server_component.component_id as u8]);
-            client_component.unbounded_send(vec![42, client_component.component_id as u8]);
+            server_component.unbounded_send(vec![1, 2, 3, 4, server_component.component_id as u8]).unwrap();
+            client_component.unbounded_send(vec![42, client_component.component_id as u8]).unwrap();
 
             // Check that we received it
             // Note that we can be fairly sure here (local-to-local) but under normal circumstances
@@ -0,0 +1,34 @@
+//! The error an [Agent](crate::ice::Agent) future resolves with once it cannot operate anymore.
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Why an [Agent](crate::ice::Agent) stopped operating.
+///
+/// The agent future (and `&Agent`) resolves with this error. Its streams are closed at that
+/// point, their components report [ComponentState::Disconnected](crate::ice::ComponentState).
+/// Supervising tasks usually log it, drop the agent and create a new one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AgentError {
+    /// The [MainContext](glib::MainContext) of the agent has not been iterated for longer than
+    /// the timeout set via
+    /// [Agent::set_main_context_lost_timeout](crate::ice::Agent::set_main_context_lost_timeout),
+    /// e.g. because its [MainLoop](glib::MainLoop) has been quit.
+    MainContextLost {
+        /// Time since the context was last iterated.
+        since_last_iteration: Duration,
+    },
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AgentError::MainContextLost { since_last_iteration } => {
+                write!(f, "main context not iterated for {:?}", since_last_iteration)
+            }
+        }
+    }
+}
+
+impl Error for AgentError {}
@@ -0,0 +1,139 @@
+//! A set of agents sharing a [MainContext], e.g. for servers handling many sessions.
+use crate::ice::events::EventSinks;
+use crate::ice::{Agent, AgentEvent, NiceCompatibility};
+use futures::channel::mpsc;
+use futures::future;
+use futures::StreamExt;
+use glib::MainContext;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Identifies an agent within an [AgentSet].
+pub type AgentId = u64;
+
+/// An event of one of the agents of an [AgentSet].
+#[derive(Clone, Debug)]
+pub struct AgentSetEvent {
+    /// The agent which emitted the event.
+    pub agent_id: AgentId,
+    /// The event.
+    pub event: AgentEvent,
+}
+
+/// Owns multiple agents sharing a [MainContext] and drives all of them.
+///
+/// Awaiting `&AgentSet` (it never completes) drives all agents like awaiting each [Agent] would.
+/// Events of all agents are available via [AgentSet::events] (including
+/// [AgentEvent::Terminated] for agents which stopped operating) and [AgentSet::shutdown] closes
+/// all agents at once.
+pub struct AgentSet {
+    ctx: MainContext,
+    next_id: AtomicU64,
+    agents: Mutex<Vec<(AgentId, Arc<Agent>)>>,
+    events: EventSinks<AgentSetEvent>,
+}
+
+impl AgentSet {
+    /// Creates an empty set for agents running on `ctx`.
+    pub fn new(ctx: MainContext) -> Self {
+        Self {
+            ctx,
+            next_id: AtomicU64::new(0),
+            agents: Mutex::new(Vec::new()),
+            events: EventSinks::default(),
+        }
+    }
+
+    /// Returns the context the agents of this set run on.
+    pub fn get_ctx(&self) -> &MainContext {
+        &self.ctx
+    }
+
+    /// Creates a new agent on the context of this set and adds it.
+    pub fn create(&self, compat: NiceCompatibility) -> (AgentId, Arc<Agent>) {
+        self.insert(Agent::new(self.ctx.clone(), compat))
+    }
+
+    /// Adds an agent created on the context of this set.
+    ///
+    /// # Panics
+    /// Panics if the agent uses a different [MainContext].
+    pub fn insert(&self, agent: Agent) -> (AgentId, Arc<Agent>) {
+        assert!(agent.get_ctx() == &self.ctx, "agent uses a different main context");
+        let agent_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let sinks = self.events.clone();
+        // Ends once the agent has been dropped
+        self.ctx.spawn(agent.events().for_each(move |event| {
+            sinks.emit(AgentSetEvent { agent_id, event });
+            future::ready(())
+        }));
+        let agent = Arc::new(agent);
+        self.agents.lock().unwrap().push((agent_id, agent.clone()));
+        (agent_id, agent)
+    }
+
+    /// Returns the agent with the given id.
+    pub fn get(&self, agent_id: AgentId) -> Option<Arc<Agent>> {
+        self.agents
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(id, _)| *id == agent_id)
+            .map(|(_, agent)| agent.clone())
+    }
+
+    /// Removes an agent from this set without closing it.
+    pub fn remove(&self, agent_id: AgentId) -> Option<Arc<Agent>> {
+        let mut agents = self.agents.lock().unwrap();
+        let index = agents.iter().position(|(id, _)| *id == agent_id)?;
+        Some(agents.remove(index).1)
+    }
+
+    /// Returns the number of agents in this set.
+    pub fn len(&self) -> usize {
+        self.agents.lock().unwrap().len()
+    }
+
+    /// Returns whether this set has no agents.
+    pub fn is_empty(&self) -> bool {
+        self.agents.lock().unwrap().is_empty()
+    }
+
+    /// Returns a new subscription to the events of all agents of this set.
+    pub fn events(&self) -> mpsc::UnboundedReceiver<AgentSetEvent> {
+        self.events.subscribe()
+    }
+
+    /// Closes all agents in the order they have been added and removes them from this set.
+    ///
+    /// For each agent, the packets queued so far are sent (up to the per-poll budget), then all
+    /// of its streams are removed and their components report `Disconnected`, as if the agent was
+    /// dropped. The agents themselves are dropped once the last [Arc] handle is gone.
+    pub async fn shutdown(&self) {
+        let agents = std::mem::replace(&mut *self.agents.lock().unwrap(), Vec::new());
+        for (_, agent) in agents {
+            future::poll_fn(|cx| {
+                let _ = agent.poll_msgs(cx);
+                Poll::Ready(())
+            })
+            .await;
+            agent.close_streams();
+        }
+    }
+}
+
+impl Future for &AgentSet {
+    type Output = (); // never
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // Cloned so agents can be added and removed while polling
+        let agents = self.agents.lock().unwrap().clone();
+        for (_, agent) in agents {
+            let _ = agent.poll_msgs(cx);
+        }
+        Poll::Pending
+    }
+}
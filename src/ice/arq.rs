@@ -0,0 +1,434 @@
+//! A lightweight reliability and ordering layer on top of an unreliable component.
+//!
+//! Every packet is prefixed with a kind byte and a 32 bit sequence number. The receiver
+//! acknowledges cumulatively (with the next sequence number it expects), buffers packets which
+//! arrive out of order and delivers them in order. The sender keeps at most `window` packets in
+//! flight and retransmits them with exponential backoff until they are acknowledged.
+use crate::ffi;
+use crate::ice::StreamComponent;
+use crate::timer::Timer;
+use futures::ready;
+use futures::Sink;
+use futures::Stream as FuturesStream;
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_uint;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+const KIND_DATA: u8 = 0;
+const KIND_ACK: u8 = 1;
+const HEADER_LEN: usize = 5;
+const MAX_BACKOFF_SHIFT: u32 = 6;
+
+/// Configuration of a [ReliableChannel].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ArqConfig {
+    /// Maximum number of unacknowledged packets in flight.
+    pub window: usize,
+    /// Initial retransmission timeout, doubled on every retransmission of the same packet.
+    pub rto: Duration,
+    /// Number of retransmissions of a single packet after which the channel fails.
+    pub max_retransmissions: u32,
+}
+
+impl Default for ArqConfig {
+    fn default() -> Self {
+        Self {
+            window: 64,
+            rto: Duration::from_millis(200),
+            max_retransmissions: 10,
+        }
+    }
+}
+
+fn encode(kind: u8, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.push(kind);
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Returns whether sequence number `a` comes before `b`, accounting for wrap-around.
+fn seq_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+struct InFlight {
+    seq: u32,
+    packet: Vec<u8>,
+    sent_at: Instant,
+    retransmissions: u32,
+}
+
+/// The protocol state, independent of any I/O.
+pub(crate) struct Arq {
+    config: ArqConfig,
+    next_seq: u32,
+    in_flight: VecDeque<InFlight>,
+    queued: VecDeque<Vec<u8>>,
+    recv_next: u32,
+    out_of_order: HashMap<u32, Vec<u8>>,
+    /// Packets to be sent to the remote peer.
+    pub(crate) transmit: VecDeque<Vec<u8>>,
+    /// Payloads received in order, ready for the application.
+    pub(crate) delivered: VecDeque<Vec<u8>>,
+    pub(crate) failed: bool,
+}
+
+impl Arq {
+    pub(crate) fn new(config: ArqConfig) -> Self {
+        Self {
+            config,
+            next_seq: 0,
+            in_flight: VecDeque::new(),
+            queued: VecDeque::new(),
+            recv_next: 0,
+            out_of_order: HashMap::new(),
+            transmit: VecDeque::new(),
+            delivered: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// Queues a payload for reliable delivery.
+    pub(crate) fn send(&mut self, payload: Vec<u8>, now: Instant) {
+        self.queued.push_back(payload);
+        self.fill_window(now);
+    }
+
+    fn fill_window(&mut self, now: Instant) {
+        while self.in_flight.len() < self.config.window {
+            let payload = match self.queued.pop_front() {
+                Some(payload) => payload,
+                None => break,
+            };
+            let seq = self.next_seq;
+            self.next_seq = seq.wrapping_add(1);
+            let packet = encode(KIND_DATA, seq, &payload);
+            self.transmit.push_back(packet.clone());
+            self.in_flight.push_back(InFlight {
+                seq,
+                packet,
+                sent_at: now,
+                retransmissions: 0,
+            });
+        }
+    }
+
+    /// Handles a packet received from the remote peer. Malformed packets are ignored.
+    pub(crate) fn on_packet(&mut self, packet: &[u8], now: Instant) {
+        if packet.len() < HEADER_LEN {
+            return;
+        }
+        let mut seq = [0u8; 4];
+        seq.copy_from_slice(&packet[1..HEADER_LEN]);
+        let seq = u32::from_be_bytes(seq);
+        match packet[0] {
+            KIND_DATA => {
+                let window_end = self.recv_next.wrapping_add(self.config.window as u32);
+                if seq == self.recv_next {
+                    self.delivered.push_back(packet[HEADER_LEN..].to_vec());
+                    self.recv_next = self.recv_next.wrapping_add(1);
+                    while let Some(payload) = self.out_of_order.remove(&self.recv_next) {
+                        self.delivered.push_back(payload);
+                        self.recv_next = self.recv_next.wrapping_add(1);
+                    }
+                } else if seq_before(self.recv_next, seq) && seq_before(seq, window_end) {
+                    self.out_of_order.entry(seq).or_insert_with(|| packet[HEADER_LEN..].to_vec());
+                }
+                // Duplicates are acknowledged as well, the previous ack may have been lost
+                self.transmit.push_back(encode(KIND_ACK, self.recv_next, &[]));
+            }
+            KIND_ACK => {
+                while let Some(front) = self.in_flight.front() {
+                    if !seq_before(front.seq, seq) {
+                        break;
+                    }
+                    self.in_flight.pop_front();
+                }
+                self.fill_window(now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Retransmits timed out packets, failing the channel once a packet has been retransmitted
+    /// too often.
+    pub(crate) fn on_timer(&mut self, now: Instant) {
+        for entry in self.in_flight.iter_mut() {
+            let backoff = self.config.rto * (1 << entry.retransmissions.min(MAX_BACKOFF_SHIFT));
+            if now.duration_since(entry.sent_at) < backoff {
+                continue;
+            }
+            if entry.retransmissions >= self.config.max_retransmissions {
+                self.failed = true;
+                return;
+            }
+            entry.retransmissions += 1;
+            entry.sent_at = now;
+            self.transmit.push_back(entry.packet.clone());
+        }
+    }
+
+    /// Returns whether all queued payloads have been acknowledged.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.in_flight.is_empty() && self.queued.is_empty()
+    }
+}
+
+struct Shared {
+    arq: Arq,
+    /// The task receiving via the [futures::Stream].
+    waker: Option<Waker>,
+    /// The task waiting for all payloads to be acknowledged via [Sink::poll_flush].
+    flush_waker: Option<Waker>,
+}
+
+impl Shared {
+    fn wake_flush(&mut self) {
+        if self.arq.is_idle() || self.arq.failed {
+            if let Some(waker) = self.flush_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A reliable, ordered channel over an unreliable [StreamComponent].
+///
+/// Both peers must use a [ReliableChannel] on the component, all packets sent and received via
+/// the component are processed by it. Created via [StreamComponent::into_reliable].
+///
+/// The channel fails (ending the [futures::Stream] and failing the [Sink]) once a packet has not
+/// been acknowledged after [ArqConfig::max_retransmissions] retransmissions.
+///
+/// Sending does not depend on the [futures::Stream] being polled: the [Sink] processes the
+/// acknowledgements (and buffers any payloads) received in `poll_ready` and `poll_flush`, the
+/// latter resolving once all payloads sent have been acknowledged.
+pub struct ReliableChannel {
+    component: StreamComponent,
+    shared: Arc<Mutex<Shared>>,
+    /// Retransmission timer, cancelled once the channel is dropped.
+    _timer: Timer,
+}
+
+impl ReliableChannel {
+    pub(crate) fn new(component: StreamComponent, config: ArqConfig) -> Self {
+        let shared = Arc::new(Mutex::new(Shared {
+            arq: Arq::new(config),
+            waker: None,
+            flush_waker: None,
+        }));
+
+        let weak = Arc::downgrade(&shared);
+        let agent = component.agent.clone();
+        let (stream_id, component_id) = (component.stream_id, component.component_id);
+        let tick = (config.rto / 4).max(Duration::from_millis(10));
+        let timer = component.timers.start(tick, Box::new(move || {
+            let shared = match weak.upgrade() {
+                Some(shared) => shared,
+                None => return false,
+            };
+            let mut shared = shared.lock().unwrap();
+            shared.arq.on_timer(Instant::now());
+            flush_direct(&agent, stream_id, component_id, &mut shared.arq);
+            if shared.arq.failed {
+                if let Some(waker) = shared.waker.take() {
+                    waker.wake();
+                }
+                shared.wake_flush();
+                return false;
+            }
+            true
+        }));
+
+        Self {
+            component,
+            shared,
+            _timer: timer,
+        }
+    }
+
+    /// Returns a reference to the underlying component.
+    pub fn get_ref(&self) -> &StreamComponent {
+        &self.component
+    }
+
+    /// Returns whether all sent payloads have been acknowledged by the remote peer.
+    pub fn is_idle(&self) -> bool {
+        self.shared.lock().unwrap().arq.is_idle()
+    }
+
+    /// Returns whether the channel has failed, see [ReliableChannel].
+    pub fn is_failed(&self) -> bool {
+        self.shared.lock().unwrap().arq.failed
+    }
+
+    fn flush(&mut self, arq: &mut Arq) {
+        for packet in arq.transmit.drain(..) {
+            let _ = self.component.unbounded_send(packet);
+        }
+    }
+
+    /// Processes the packets received so far from the sending side, returns `false` once the
+    /// component has been closed.
+    fn receive_pending(&mut self, shared: &mut Shared, cx: &mut Context) -> bool {
+        let mut open = true;
+        while let Poll::Ready(packet) = Pin::new(&mut self.component).poll_next(cx) {
+            match packet {
+                Some(packet) => shared.arq.on_packet(&packet, Instant::now()),
+                None => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+        self.flush(&mut shared.arq);
+        // Polling replaced the waker of the receiving task, which has to poll again to get it
+        // back (and to take the payloads received here)
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+        open
+    }
+}
+
+/// Sends pending packets directly from the glib main context.
+fn flush_direct(agent: &ffi::NiceAgent, stream_id: c_uint, component_id: c_uint, arq: &mut Arq) {
+    for packet in arq.transmit.drain(..) {
+        let _ = agent.send(stream_id, component_id, &packet);
+    }
+}
+
+impl FuturesStream for ReliableChannel {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let shared = this.shared.clone();
+        let mut shared = shared.lock().unwrap();
+        loop {
+            if let Some(payload) = shared.arq.delivered.pop_front() {
+                return Poll::Ready(Some(payload));
+            }
+            if shared.arq.failed {
+                return Poll::Ready(None);
+            }
+            shared.waker = Some(cx.waker().clone());
+            let packet = match ready!(Pin::new(&mut this.component).poll_next(cx)) {
+                Some(packet) => packet,
+                None => return Poll::Ready(None),
+            };
+            shared.arq.on_packet(&packet, Instant::now());
+            this.flush(&mut shared.arq);
+            shared.wake_flush();
+        }
+    }
+}
+
+impl Sink<Vec<u8>> for ReliableChannel {
+    type Error = (); // once the channel has failed or the agent has been closed
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        let shared = this.shared.clone();
+        let mut shared = shared.lock().unwrap();
+        if !this.receive_pending(&mut shared, cx) || shared.arq.failed || this.component.is_closed() {
+            return Poll::Ready(Err(()));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let shared = this.shared.clone();
+        let mut shared = shared.lock().unwrap();
+        if shared.arq.failed {
+            return Err(());
+        }
+        shared.arq.send(item, Instant::now());
+        this.flush(&mut shared.arq);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        let shared = this.shared.clone();
+        let mut shared = shared.lock().unwrap();
+        if !this.receive_pending(&mut shared, cx) || shared.arq.failed {
+            return Poll::Ready(Err(()));
+        }
+        if shared.arq.is_idle() {
+            return Poll::Ready(Ok(()));
+        }
+        shared.flush_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> ArqConfig {
+        ArqConfig {
+            window: 4,
+            rto: Duration::from_millis(100),
+            max_retransmissions: 2,
+        }
+    }
+
+    /// Moves all pending packets from `from` to `to`, except for those rejected by `keep`.
+    fn deliver(from: &mut Arq, to: &mut Arq, now: Instant, mut keep: impl FnMut(usize) -> bool) {
+        let packets = from.transmit.drain(..).collect::<Vec<_>>();
+        for (index, packet) in packets.iter().enumerate() {
+            if keep(index) {
+                to.on_packet(packet, now);
+            }
+        }
+    }
+
+    #[test]
+    fn reorders_and_retransmits() {
+        let now = Instant::now();
+        let (mut a, mut b) = (Arq::new(config()), Arq::new(config()));
+        for i in 0..6u8 {
+            a.send(vec![i], now);
+        }
+        assert_eq!(a.transmit.len(), 4, "window must limit packets in flight");
+
+        // Lose the second packet
+        deliver(&mut a, &mut b, now, |index| index != 1);
+        assert_eq!(b.delivered.drain(..).collect::<Vec<_>>(), vec![vec![0]]);
+        deliver(&mut b, &mut a, now, |_| true);
+
+        // The first ack opened the window for the fifth packet, then everything times out
+        a.on_timer(now + Duration::from_millis(100));
+        deliver(&mut a, &mut b, now, |_| true);
+        assert_eq!(b.delivered.drain(..).collect::<Vec<_>>(), vec![vec![1], vec![2], vec![3], vec![4]]);
+        deliver(&mut b, &mut a, now, |_| true);
+        deliver(&mut a, &mut b, now, |_| true);
+        assert_eq!(b.delivered.drain(..).collect::<Vec<_>>(), vec![vec![5]]);
+        deliver(&mut b, &mut a, now, |_| true);
+        assert!(a.is_idle());
+    }
+
+    #[test]
+    fn fails_after_max_retransmissions() {
+        let now = Instant::now();
+        let mut arq = Arq::new(config());
+        arq.send(vec![1], now);
+        arq.on_timer(now + Duration::from_millis(100));
+        arq.on_timer(now + Duration::from_millis(300));
+        assert!(!arq.failed);
+        arq.on_timer(now + Duration::from_millis(700));
+        assert!(arq.failed);
+    }
+}
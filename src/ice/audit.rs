@@ -0,0 +1,155 @@
+//! A record of the operations an [Agent](crate::ice::Agent) performed on libnice on behalf of
+//! its [Stream](crate::ice::Stream)s and components.
+use crate::ffi::NiceError;
+use crate::ice::Candidate;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::raw::c_uint;
+use std::time::SystemTime;
+
+/// A single operation performed on libnice, see [Agent::audit_log](crate::ice::Agent::audit_log).
+#[derive(Clone, Debug)]
+pub struct AuditRecord {
+    /// When the operation has been performed (not when it has been requested).
+    pub timestamp: SystemTime,
+    /// The operation.
+    pub operation: AuditOperation,
+    /// The result of the operation.
+    pub result: Result<(), OperationError>,
+}
+
+/// Why an operation of an [AuditRecord] failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OperationError {
+    /// libnice failed.
+    Nice(NiceError),
+    /// A remote candidate has not been passed to libnice, e.g. because its address is an FQDN.
+    RejectedCandidate(String),
+    /// libnice accepted only the first `sent` bytes of a `len` bytes packet.
+    ShortSend {
+        /// The bytes sent.
+        sent: usize,
+        /// The length of the packet.
+        len: usize,
+    },
+}
+
+impl From<NiceError> for OperationError {
+    fn from(err: NiceError) -> Self {
+        OperationError::Nice(err)
+    }
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperationError::Nice(err) => err.fmt(f),
+            OperationError::RejectedCandidate(reason) => write!(f, "unsupported candidate: {}", reason),
+            OperationError::ShortSend { sent, len } => write!(f, "short send: {} of {} bytes", sent, len),
+        }
+    }
+}
+
+impl Error for OperationError {}
+
+/// An operation which has been requested via a [Stream](crate::ice::Stream) or one of its
+/// components and has been passed on to libnice.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum AuditOperation {
+    /// The remote credentials of a stream have been set.
+    SetRemoteCredentials {
+        /// The id of the stream.
+        stream_id: c_uint,
+    },
+    /// A remote candidate has been added.
+    AddRemoteCandidate {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The candidate.
+        candidate: Candidate,
+    },
+    /// The remote peer has finished gathering candidates for a stream.
+    RemoteGatheringDone {
+        /// The id of the stream.
+        stream_id: c_uint,
+    },
+    /// A packet has been sent.
+    Send {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The length of the packet.
+        len: usize,
+    },
+    /// Deferred candidate gathering of a stream has been started.
+    StartGathering {
+        /// The id of the stream.
+        stream_id: c_uint,
+    },
+    /// Candidate gathering of a stream has been restarted.
+    Regather {
+        /// The id of the stream.
+        stream_id: c_uint,
+    },
+    /// The port range of a component has been set.
+    SetPortRange {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The lowest port.
+        min_port: u16,
+        /// The highest port.
+        max_port: u16,
+    },
+    /// A relay server has been added to a component.
+    AddRelayServer {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The address of the relay server.
+        addr: SocketAddr,
+    },
+    /// A surplus component has been dropped, see
+    /// [Stream::drop_component](crate::ice::Stream::drop_component).
+    DropComponent {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+    },
+    /// The controlling mode of the agent has been changed on behalf of a stream, e.g. because
+    /// the remote peer is an ICE lite implementation.
+    SetControllingMode {
+        /// Whether the agent is now in controlling mode.
+        controlling: bool,
+    },
+    /// A stream fell back to relaying after a component failed, see
+    /// [StreamBuilder::set_relay_fallback](crate::ice::StreamBuilder::set_relay_fallback).
+    RelayFallback {
+        /// The id of the stream.
+        stream_id: c_uint,
+    },
+    /// A STUN server has been probed for a stream, see
+    /// [StreamBuilder::set_stun_servers](crate::ice::StreamBuilder::set_stun_servers).
+    StunProbe {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The address of the STUN server.
+        server: SocketAddr,
+        /// Whether the server yielded a server reflexive candidate in time.
+        responded: bool,
+    },
+    /// A stream has been removed.
+    DropStream {
+        /// The id of the stream.
+        stream_id: c_uint,
+    },
+}
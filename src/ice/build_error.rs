@@ -0,0 +1,265 @@
+//! The error returned when building a [Stream](crate::ice::Stream) fails.
+use crate::ffi::NiceError;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// An invalid configuration of a [StreamBuilder](crate::ice::StreamBuilder) or
+/// [Stream](crate::ice::Stream), detected before anything is passed to libnice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StreamConfigError {
+    /// The stream has no components.
+    NoComponents,
+    /// A component index is out of range.
+    ComponentIndexOutOfRange {
+        /// The index (the first component at index `0`).
+        component_index: usize,
+        /// The number of components of the stream.
+        components: usize,
+    },
+    /// The port range of a component has a minimum larger than its maximum.
+    InvertedPortRange {
+        /// The index of the component (the first component at index `0`).
+        component_index: usize,
+        /// The minimum port.
+        min_port: u16,
+        /// The maximum port.
+        max_port: u16,
+    },
+    /// The port ranges of several components overlap and contain fewer ports than components,
+    /// so not every component can get its own port.
+    PortRangesExhausted {
+        /// The indexes of the components sharing the ports.
+        component_indexes: Vec<usize>,
+        /// The lowest port of the shared ranges.
+        min_port: u16,
+        /// The highest port of the shared ranges.
+        max_port: u16,
+    },
+    /// The agent has been closed, so the configuration of the stream could not be changed.
+    Closed,
+}
+
+impl fmt::Display for StreamConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamConfigError::NoComponents => f.write_str("a stream needs at least one component"),
+            StreamConfigError::ComponentIndexOutOfRange {
+                component_index,
+                components,
+            } => write!(
+                f,
+                "component index {} out of range (components: {})",
+                component_index, components
+            ),
+            StreamConfigError::InvertedPortRange {
+                component_index,
+                min_port,
+                max_port,
+            } => write!(
+                f,
+                "port range {}-{} of component {} is inverted",
+                min_port, max_port, component_index
+            ),
+            StreamConfigError::PortRangesExhausted {
+                component_indexes,
+                min_port,
+                max_port,
+            } => write!(
+                f,
+                "ports {}-{} are not enough for components {:?}",
+                min_port, max_port, component_indexes
+            ),
+            StreamConfigError::Closed => f.write_str("agent has been closed"),
+        }
+    }
+}
+
+impl Error for StreamConfigError {}
+
+/// Checks the configuration of a stream with `components` components and the given port ranges
+/// per component index.
+///
+/// A range of `0-0` leaves the port up to the operating system and is never exhausted.
+pub(crate) fn validate(
+    components: usize,
+    port_ranges: &HashMap<usize, (u16, u16)>,
+) -> Result<(), StreamConfigError> {
+    if components == 0 {
+        return Err(StreamConfigError::NoComponents);
+    }
+
+    let mut ranges = port_ranges
+        .iter()
+        .filter(|(_, range)| **range != (0, 0))
+        .map(|(index, (min_port, max_port))| (*min_port, *max_port, *index))
+        .collect::<Vec<_>>();
+    if let Some((min_port, max_port, component_index)) = ranges.iter().find(|(min, max, _)| min > max) {
+        return Err(StreamConfigError::InvertedPortRange {
+            component_index: *component_index,
+            min_port: *min_port,
+            max_port: *max_port,
+        });
+    }
+
+    // Overlapping ranges are merged, each merged range needs a port for each of its components
+    ranges.sort();
+    let mut merged: Vec<(u16, u16, Vec<usize>)> = Vec::new();
+    for (min_port, max_port, index) in ranges {
+        match merged.last_mut() {
+            Some((_, merged_max, indexes)) if min_port <= *merged_max => {
+                *merged_max = (*merged_max).max(max_port);
+                indexes.push(index);
+            }
+            _ => merged.push((min_port, max_port, vec![index])),
+        }
+    }
+    for (min_port, max_port, mut component_indexes) in merged {
+        let ports = usize::from(max_port - min_port) + 1;
+        if component_indexes.len() > ports {
+            component_indexes.sort_unstable();
+            return Err(StreamConfigError::PortRangesExhausted {
+                component_indexes,
+                min_port,
+                max_port,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The step of [StreamBuilder::build](crate::ice::StreamBuilder::build) which failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StreamBuildPhase {
+    /// libnice could not add the stream.
+    AddStream,
+    /// The local credentials derived from the deterministic seed could not be set, see
+    /// [Agent::set_deterministic_seed](crate::ice::Agent::set_deterministic_seed).
+    SetCredentials,
+    /// The receive callback of a component could not be attached.
+    AttachRecv,
+    /// A relay server could not be configured.
+    ConfigureRelay,
+    /// Gathering the local candidates could not be started, e.g. because the port range of a
+    /// component is already in use.
+    Gather,
+}
+
+impl fmt::Display for StreamBuildPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StreamBuildPhase::AddStream => "adding the stream",
+            StreamBuildPhase::SetCredentials => "setting the local credentials",
+            StreamBuildPhase::AttachRecv => "attaching the receive callback",
+            StreamBuildPhase::ConfigureRelay => "configuring a relay server",
+            StreamBuildPhase::Gather => "gathering candidates",
+        })
+    }
+}
+
+/// Building a [Stream](crate::ice::Stream) failed.
+///
+/// Everything set up before the failing step has been removed again, the agent is in the same
+/// state as before the call.
+#[derive(Debug)]
+pub enum StreamBuildError {
+    /// The configuration is invalid, nothing has been passed to libnice.
+    Invalid(StreamConfigError),
+    /// Gathering could not be started, because the configured port ranges (and the fallback
+    /// range, if any) have no free ports, see
+    /// [StreamBuilder::set_fallback_port_range](crate::ice::StreamBuilder::set_fallback_port_range).
+    ///
+    /// libnice does not report why no host candidate could be created, so this is assumed
+    /// whenever that happens for a stream with port ranges, see [NiceError::NoHostCandidates].
+    PortRangeExhausted {
+        /// The distinct port ranges which have been tried, the fallback range last.
+        ranges: Vec<(u16, u16)>,
+    },
+    /// A step of setting up the stream failed.
+    Failed {
+        /// The step which failed.
+        phase: StreamBuildPhase,
+        /// The error reported by that step.
+        cause: NiceError,
+    },
+}
+
+impl StreamBuildError {
+    pub(crate) fn new(phase: StreamBuildPhase, cause: NiceError) -> Self {
+        StreamBuildError::Failed { phase, cause }
+    }
+}
+
+impl From<StreamConfigError> for StreamBuildError {
+    fn from(err: StreamConfigError) -> Self {
+        StreamBuildError::Invalid(err)
+    }
+}
+
+impl fmt::Display for StreamBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamBuildError::Invalid(err) => write!(f, "invalid stream configuration: {}", err),
+            StreamBuildError::PortRangeExhausted { ranges } => {
+                f.write_str("no free port in ")?;
+                for (index, (min_port, max_port)) in ranges.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}-{}", min_port, max_port)?;
+                }
+                Ok(())
+            }
+            StreamBuildError::Failed { phase, cause } => write!(f, "failed {}: {}", phase, cause),
+        }
+    }
+}
+
+impl Error for StreamBuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StreamBuildError::Invalid(err) => Some(err),
+            StreamBuildError::PortRangeExhausted { .. } => None,
+            StreamBuildError::Failed { cause, .. } => Some(cause),
+        }
+    }
+}
+
+/// Allows using `?` on the result of [StreamBuilder::build](crate::ice::StreamBuilder::build) in
+/// functions returning a [BoolResult](crate::ice::BoolResult).
+impl From<StreamBuildError> for glib::BoolError {
+    fn from(err: StreamBuildError) -> Self {
+        glib_bool_error!("{}", err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validates_port_ranges() {
+        let ranges = |list: &[(usize, (u16, u16))]| list.iter().cloned().collect::<HashMap<_, _>>();
+        assert_eq!(validate(0, &HashMap::new()), Err(StreamConfigError::NoComponents));
+        assert_eq!(validate(2, &ranges(&[(0, (0, 0)), (1, (0, 0))])), Ok(()));
+        assert_eq!(
+            validate(1, &ranges(&[(0, (5001, 5000))])),
+            Err(StreamConfigError::InvertedPortRange {
+                component_index: 0,
+                min_port: 5001,
+                max_port: 5000
+            })
+        );
+        assert_eq!(validate(2, &ranges(&[(0, (5000, 5001)), (1, (5001, 5001))])), Ok(()));
+        assert_eq!(
+            validate(3, &ranges(&[(0, (5000, 5001)), (1, (5001, 5001)), (2, (5000, 5000))])),
+            Err(StreamConfigError::PortRangesExhausted {
+                component_indexes: vec![0, 1, 2],
+                min_port: 5000,
+                max_port: 5001
+            })
+        );
+    }
+}
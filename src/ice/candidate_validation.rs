@@ -0,0 +1,182 @@
+//! Validation and normalization of remote candidates before they are passed to libnice.
+use crate::ice::Candidate;
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use webrtc_sdp::address::Address;
+use webrtc_sdp::attribute_type::SdpAttributeCandidateTransport;
+
+/// The highest candidate priority allowed by RFC 8445 (`2^31 - 1`).
+const MAX_PRIORITY: u64 = (1 << 31) - 1;
+
+/// The longest foundation allowed by RFC 8839.
+const MAX_FOUNDATION_LEN: usize = 32;
+
+/// Why a remote candidate cannot be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CandidateError {
+    /// The component id is `0` or larger than the number of components of the stream.
+    ComponentOutOfRange {
+        /// The component id of the candidate.
+        component: u32,
+        /// The number of components of the stream.
+        components: usize,
+    },
+    /// The component id differs from the one of the component the candidate is added to, see
+    /// [StreamComponent::add_remote_candidate](crate::ice::StreamComponent::add_remote_candidate).
+    ComponentMismatch {
+        /// The component id of the candidate.
+        component: u32,
+        /// The id of the component.
+        expected: u32,
+    },
+    /// The address is an FQDN, which must be resolved before adding the candidate.
+    UnresolvedAddress(String),
+    /// The address cannot be used by a peer, e.g. an unspecified or multicast address.
+    InvalidAddress(IpAddr),
+    /// The port (or the related port) is `0` or larger than `65535`.
+    InvalidPort(u32),
+    /// The priority is `0` or larger than `2^31 - 1`.
+    InvalidPriority(u64),
+    /// The foundation is empty, too long or contains characters other than `ice-char`s.
+    InvalidFoundation(String),
+    /// The transport is TCP but the TCP type (`tcptype`) is missing.
+    MissingTcpType,
+    /// The address family is excluded by the stream's
+    /// [AddressFamilyPolicy](crate::ice::AddressFamilyPolicy).
+    ExcludedAddressFamily(IpAddr),
+    /// The agent has been closed, so the candidate could not be added.
+    Closed,
+}
+
+impl fmt::Display for CandidateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CandidateError::ComponentOutOfRange { component, components } => {
+                write!(f, "component {} out of range (components: {})", component, components)
+            }
+            CandidateError::ComponentMismatch { component, expected } => {
+                write!(f, "candidate of component {} added to component {}", component, expected)
+            }
+            CandidateError::UnresolvedAddress(name) => write!(f, "unresolved address {}", name),
+            CandidateError::InvalidAddress(ip) => write!(f, "invalid address {}", ip),
+            CandidateError::InvalidPort(port) => write!(f, "invalid port {}", port),
+            CandidateError::InvalidPriority(priority) => write!(f, "invalid priority {}", priority),
+            CandidateError::InvalidFoundation(foundation) => write!(f, "invalid foundation {:?}", foundation),
+            CandidateError::MissingTcpType => f.write_str("tcp candidate without tcptype"),
+            CandidateError::ExcludedAddressFamily(ip) => write!(f, "address family of {} is excluded", ip),
+            CandidateError::Closed => f.write_str("agent has been closed"),
+        }
+    }
+}
+
+impl Error for CandidateError {}
+
+impl From<CandidateError> for glib::BoolError {
+    fn from(err: CandidateError) -> Self {
+        glib_bool_error!("{}", err)
+    }
+}
+
+/// Validation and normalization of remote [Candidate]s, see
+/// [Stream::add_remote_candidate](crate::ice::Stream::add_remote_candidate).
+pub trait CandidateValidation: Sized {
+    /// Checks whether the candidate can be added to a stream with `components` components.
+    fn validate(&self, components: usize) -> Result<(), CandidateError>;
+
+    /// Returns the candidate in its canonical form: IPv4-mapped IPv6 addresses are converted to
+    /// IPv4 addresses and a TCP type of UDP candidates is removed.
+    fn normalize(self) -> Self;
+}
+
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(((u32::from(high) << 16) | u32::from(low)).into()),
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+fn validate_port(port: u32) -> Result<(), CandidateError> {
+    if port == 0 || port > u32::from(u16::MAX) {
+        return Err(CandidateError::InvalidPort(port));
+    }
+    Ok(())
+}
+
+impl CandidateValidation for Candidate {
+    fn validate(&self, components: usize) -> Result<(), CandidateError> {
+        if self.component == 0 || self.component as usize > components {
+            return Err(CandidateError::ComponentOutOfRange {
+                component: self.component,
+                components,
+            });
+        }
+        let ip = match &self.address {
+            Address::Ip(ip) => canonical_ip(*ip),
+            Address::Fqdn(name) => return Err(CandidateError::UnresolvedAddress(name.clone())),
+        };
+        if ip.is_unspecified() || ip.is_multicast() {
+            return Err(CandidateError::InvalidAddress(ip));
+        }
+        validate_port(self.port)?;
+        if let Some(rport) = self.rport {
+            // Some implementations signal an unknown related address as `0.0.0.0 0`
+            if rport != 0 {
+                validate_port(rport)?;
+            }
+        }
+        if self.priority == 0 || self.priority > MAX_PRIORITY {
+            return Err(CandidateError::InvalidPriority(self.priority));
+        }
+        let is_ice_char = |c: char| c.is_ascii_alphanumeric() || c == '+' || c == '/';
+        if self.foundation.is_empty()
+            || self.foundation.len() > MAX_FOUNDATION_LEN
+            || !self.foundation.chars().all(is_ice_char)
+        {
+            return Err(CandidateError::InvalidFoundation(self.foundation.clone()));
+        }
+        if self.transport == SdpAttributeCandidateTransport::Tcp && self.tcp_type.is_none() {
+            return Err(CandidateError::MissingTcpType);
+        }
+        Ok(())
+    }
+
+    fn normalize(mut self) -> Self {
+        if let Address::Ip(ip) = &mut self.address {
+            *ip = canonical_ip(*ip);
+        }
+        if let Some(Address::Ip(ip)) = &mut self.raddr {
+            *ip = canonical_ip(*ip);
+        }
+        if self.transport == SdpAttributeCandidateTransport::Udp {
+            self.tcp_type = None;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::parse_candidate;
+
+    #[test]
+    fn validates_and_normalizes() {
+        let valid = parse_candidate("candidate:1 1 UDP 2130706431 ::ffff:192.168.1.2 50000 typ host");
+        assert_eq!(valid.validate(1), Ok(()));
+        assert_eq!(valid.clone().normalize().address.to_string(), "192.168.1.2");
+        assert_eq!(
+            valid.validate(0),
+            Err(CandidateError::ComponentOutOfRange { component: 1, components: 0 })
+        );
+
+        let unspecified = parse_candidate("candidate:1 1 UDP 2130706431 0.0.0.0 50000 typ host");
+        assert!(matches!(unspecified.validate(1), Err(CandidateError::InvalidAddress(_))));
+        let priority = parse_candidate("candidate:1 1 UDP 4294967295 192.168.1.2 50000 typ host");
+        assert_eq!(priority.validate(1), Err(CandidateError::InvalidPriority(4_294_967_295)));
+    }
+}
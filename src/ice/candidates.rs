@@ -0,0 +1,563 @@
+//! Processing of local candidates before they are emitted by a [Stream](crate::ice::Stream).
+use crate::ffi::NiceCandidate;
+#[cfg(any(test, feature = "testing"))]
+use crate::ice::deterministic::DeterministicIds;
+use crate::ice::{Candidate, CandidateType};
+use futures::channel::mpsc;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use webrtc_sdp::address::Address;
+
+/// Restricts or prioritizes the IP address families a stream connects over, see
+/// [StreamBuilder::set_address_family_policy](crate::ice::StreamBuilder::set_address_family_policy).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressFamilyPolicy {
+    /// Use all address families (the default).
+    Any,
+    /// Only emit and accept IPv4 candidates.
+    Ipv4Only,
+    /// Only emit and accept IPv6 candidates.
+    Ipv6Only,
+    /// Emit candidates of all address families but rank IPv6 candidates above IPv4 candidates
+    /// of the same type.
+    ///
+    /// This only changes the priorities of the emitted candidates, i.e. what the remote peer
+    /// sees and bases its pair priorities and nominations on. libnice keeps using its own
+    /// priorities locally.
+    PreferIpv6,
+}
+
+impl AddressFamilyPolicy {
+    /// Returns whether addresses of the family of `ip` are allowed.
+    pub fn allows(self, ip: IpAddr) -> bool {
+        match self {
+            AddressFamilyPolicy::Any | AddressFamilyPolicy::PreferIpv6 => true,
+            AddressFamilyPolicy::Ipv4Only => ip.is_ipv4(),
+            AddressFamilyPolicy::Ipv6Only => ip.is_ipv6(),
+        }
+    }
+}
+
+impl Default for AddressFamilyPolicy {
+    fn default() -> Self {
+        AddressFamilyPolicy::Any
+    }
+}
+
+/// Selects the local candidates a local preference override applies to.
+///
+/// See [StreamBuilder::set_local_preference](crate::ice::StreamBuilder::set_local_preference).
+#[derive(Clone, Debug, PartialEq)]
+pub enum CandidateSelector {
+    /// Candidates of the given type.
+    Type(CandidateType),
+    /// Candidates gathered on the interface with the given address, i.e. host candidates with
+    /// that address and server reflexive or relayed candidates with that base address.
+    Interface(IpAddr),
+    /// Like [CandidateSelector::Interface], but for all interface addresses in the given subnet
+    /// (address and prefix length).
+    Subnet(IpAddr, u8),
+}
+
+impl CandidateSelector {
+    fn matches(&self, candidate: &Candidate) -> bool {
+        let interface_addr = match (&candidate.c_type, &candidate.raddr) {
+            (CandidateType::Host, _) | (_, None) => &candidate.address,
+            (_, Some(base)) => base,
+        };
+        let interface_addr = match interface_addr {
+            Address::Ip(ip) => *ip,
+            Address::Fqdn(_) => return matches!(self, CandidateSelector::Type(c_type) if *c_type == candidate.c_type),
+        };
+        match self {
+            CandidateSelector::Type(c_type) => *c_type == candidate.c_type,
+            CandidateSelector::Interface(ip) => *ip == interface_addr,
+            CandidateSelector::Subnet(network, prefix) => in_subnet(interface_addr, *network, *prefix),
+        }
+    }
+}
+
+fn in_subnet(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => (u128::from(u32::from(ip)), u128::from(u32::from(network)), 32),
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    let prefix = u32::from(prefix).min(bits);
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    (ip >> shift) == (network >> shift)
+}
+
+/// How the local candidates of a stream are processed before they are emitted.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LocalCandidatePolicy {
+    pub(crate) address_family: AddressFamilyPolicy,
+    /// Local preference overrides, the first matching one applies.
+    pub(crate) local_preferences: Vec<(CandidateSelector, u16)>,
+    /// Whether host candidates are dropped and the related addresses of the other candidates
+    /// are hidden.
+    pub(crate) redact_host: bool,
+    /// The addresses of the active network, candidates gathered on other interfaces are dropped.
+    pub(crate) network: Option<Vec<IpAddr>>,
+    /// Whether only relayed candidates are emitted, after falling back to relaying.
+    pub(crate) relay_only: bool,
+    /// Replaces the foundations of the candidates, see
+    /// [Agent::set_deterministic_seed](crate::ice::Agent::set_deterministic_seed).
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) deterministic: Option<DeterministicIds>,
+}
+
+impl LocalCandidatePolicy {
+    /// Applies this policy to a candidate, returning `None` if it must not be emitted.
+    pub(crate) fn apply(&self, mut candidate: Candidate) -> Option<Candidate> {
+        if self.relay_only && candidate.c_type != CandidateType::Relay {
+            return None;
+        }
+        if let Some(network) = &self.network {
+            if !network.iter().any(|ip| CandidateSelector::Interface(*ip).matches(&candidate)) {
+                return None;
+            }
+        }
+        #[cfg(any(test, feature = "testing"))]
+        {
+            if let Some(ids) = &self.deterministic {
+                ids.rewrite_foundation(&mut candidate);
+            }
+        }
+        let is_ipv6 = match candidate.address {
+            Address::Ip(ip) => ip.is_ipv6(),
+            Address::Fqdn(_) => false,
+        };
+
+        let preference = self
+            .local_preferences
+            .iter()
+            .find(|(selector, _)| selector.matches(&candidate))
+            .map(|(_, preference)| *preference);
+        if let Some(preference) = preference {
+            map_local_preference(&mut candidate, |_| preference);
+        }
+
+        if let Address::Ip(ip) = candidate.address {
+            if !self.address_family.allows(ip) {
+                return None;
+            }
+        }
+        if self.address_family == AddressFamilyPolicy::PreferIpv6 {
+            // Use the upper half of the local preference range for IPv6 candidates
+            let offset = if is_ipv6 { 0x8000 } else { 0 };
+            map_local_preference(&mut candidate, |preference| offset + preference / 2);
+        }
+
+        if self.redact_host {
+            if candidate.c_type == CandidateType::Host {
+                return None;
+            }
+            // The related address is the host address (or the mapped address of a relayed
+            // candidate), signaled as unknown like browsers do
+            if candidate.raddr.is_some() {
+                let unspecified = if is_ipv6 { IpAddr::from([0u16; 8]) } else { IpAddr::from([0u8; 4]) };
+                candidate.raddr = Some(Address::Ip(unspecified));
+                candidate.rport = Some(0);
+            }
+        }
+        Some(candidate)
+    }
+}
+
+/// Replaces the local preference part of the candidate's priority (see RFC 8445, 5.1.2.1).
+pub(crate) fn map_local_preference(candidate: &mut Candidate, f: impl FnOnce(u16) -> u16) {
+    let priority = candidate.priority;
+    let local_preference = ((priority >> 8) & 0xffff) as u16;
+    candidate.priority = (priority & !0x00ff_ff00) | (u64::from(f(local_preference)) << 8);
+}
+
+/// Batching and limiting of the local candidates emitted by a stream, applied after the
+/// [LocalCandidatePolicy].
+#[derive(Debug, Default)]
+pub(crate) struct CandidateEmitter {
+    /// Candidates held back until gathering is done, if they are emitted sorted by priority.
+    batch: Option<Vec<Candidate>>,
+    max_per_component: Option<usize>,
+    emitted: HashMap<u32, usize>,
+}
+
+impl CandidateEmitter {
+    pub(crate) fn new(sort_by_priority: bool, max_per_component: Option<usize>) -> Self {
+        Self {
+            batch: if sort_by_priority { Some(Vec::new()) } else { None },
+            max_per_component,
+            emitted: HashMap::new(),
+        }
+    }
+
+    /// Called for every new local candidate, returns it if it is to be emitted right away.
+    pub(crate) fn push(&mut self, candidate: Candidate) -> Option<Candidate> {
+        match &mut self.batch {
+            Some(batch) => {
+                batch.push(candidate);
+                None
+            }
+            None => self.admit(candidate),
+        }
+    }
+
+    /// Called once gathering is done, returns the held back candidates which are to be emitted.
+    pub(crate) fn flush(&mut self) -> Vec<Candidate> {
+        let mut batch = match &mut self.batch {
+            Some(batch) => std::mem::take(batch),
+            None => return Vec::new(),
+        };
+        sort_by_priority(&mut batch);
+        batch.into_iter().filter_map(|candidate| self.admit(candidate)).collect()
+    }
+
+    fn admit(&mut self, candidate: Candidate) -> Option<Candidate> {
+        if let Some(max) = self.max_per_component {
+            let emitted = self.emitted.entry(candidate.component).or_insert(0);
+            if *emitted >= max {
+                return None;
+            }
+            *emitted += 1;
+        }
+        Some(candidate)
+    }
+}
+
+/// Converts the candidates reported by libnice to the SDP form emitted by a stream, i.e. applies
+/// its [LocalCandidatePolicy] and [CandidateEmitter].
+pub(crate) trait CandidateConverter: Send {
+    /// Returns the candidate if it is to be emitted right away.
+    fn convert(&mut self, candidate: &NiceCandidate) -> Option<Candidate>;
+
+    /// Called once gathering is done, returns the held back candidates which are to be emitted.
+    fn flush(&mut self) -> Vec<Candidate>;
+}
+
+/// Broadcasts the local candidates of a stream to its subscribers, shared between the
+/// [Stream](crate::ice::Stream) and the agent.
+///
+/// Each subscription first receives the candidates emitted so far by the current gathering run
+/// and ends once gathering is done, a subscription created after gathering is done ends right
+/// after the candidates of the last run. The same applies to subscriptions to the raw candidates
+/// reported by libnice.
+///
+/// Candidates are only converted to the SDP form while there are subscribers for it, the ones
+/// reported before are converted once the first subscription is created.
+pub(crate) struct CandidateChannel {
+    subscribers: Vec<mpsc::UnboundedSender<Candidate>>,
+    emitted: Vec<Candidate>,
+    raw_subscribers: Vec<mpsc::UnboundedSender<NiceCandidate>>,
+    emitted_raw: Vec<NiceCandidate>,
+    gathering: bool,
+    /// Components whose candidates are no longer emitted, see
+    /// [Stream::drop_component](crate::ice::Stream::drop_component).
+    dropped_components: Vec<u32>,
+    /// Candidates of the current run which have not been converted yet.
+    unconverted: Vec<NiceCandidate>,
+    /// Whether the run finished before its held back candidates could be flushed.
+    flush_pending: bool,
+    converter: Option<Box<dyn CandidateConverter>>,
+}
+
+impl CandidateChannel {
+    /// Creates a channel for a stream which is about to start gathering.
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: Vec::new(),
+            emitted: Vec::new(),
+            raw_subscribers: Vec::new(),
+            emitted_raw: Vec::new(),
+            gathering: true,
+            dropped_components: Vec::new(),
+            unconverted: Vec::new(),
+            flush_pending: false,
+            converter: None,
+        }
+    }
+
+    /// Sets how candidates are converted, without a converter they are emitted as reported.
+    pub(crate) fn set_converter(&mut self, converter: Box<dyn CandidateConverter>) {
+        self.converter = Some(converter);
+    }
+
+    pub(crate) fn subscribe_raw(&mut self) -> mpsc::UnboundedReceiver<NiceCandidate> {
+        let (sender, receiver) = mpsc::unbounded();
+        for candidate in &self.emitted_raw {
+            let _ = sender.unbounded_send(candidate.clone());
+        }
+        if self.gathering {
+            self.raw_subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Returns whether there are subscribers for candidates in the SDP form, if not they need
+    /// not be converted.
+    fn has_subscribers(&self) -> bool {
+        self.subscribers.iter().any(|subscriber| !subscriber.is_closed())
+    }
+
+    pub(crate) fn subscribe(&mut self) -> mpsc::UnboundedReceiver<Candidate> {
+        self.convert_pending();
+        let (sender, receiver) = mpsc::unbounded();
+        for candidate in &self.emitted {
+            let _ = sender.unbounded_send(candidate.clone());
+        }
+        if self.gathering {
+            self.subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Starts another gathering run, e.g. after [Stream::regather](crate::ice::Stream::regather).
+    pub(crate) fn rearm(&mut self) {
+        self.convert_pending();
+        self.gathering = true;
+        self.emitted.clear();
+        self.emitted_raw.clear();
+    }
+
+    pub(crate) fn is_gathering(&self) -> bool {
+        self.gathering
+    }
+
+    /// Stops emitting the candidates of a component, which are also removed from the candidates
+    /// emitted so far (for new subscriptions).
+    pub(crate) fn drop_component(&mut self, component_id: u32) {
+        self.dropped_components.push(component_id);
+        self.emitted.retain(|candidate| candidate.component != component_id);
+        self.emitted_raw.retain(|candidate| candidate.component_id() != component_id);
+    }
+
+    /// Sends the candidate to all subscribers, dropping those which are gone.
+    fn send(&mut self, candidate: Candidate) {
+        if self.dropped_components.contains(&candidate.component) {
+            return;
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(candidate.clone()).is_ok());
+        self.emitted.push(candidate);
+    }
+
+    fn convert(&mut self, candidate: &NiceCandidate) -> Option<Candidate> {
+        match &mut self.converter {
+            Some(converter) => converter.convert(candidate),
+            None => Some(candidate.to_sdp()),
+        }
+    }
+
+    /// Converts the candidates reported while there were no subscribers for the SDP form.
+    pub(crate) fn convert_pending(&mut self) {
+        for candidate in std::mem::take(&mut self.unconverted) {
+            if let Some(candidate) = self.convert(&candidate) {
+                self.send(candidate);
+            }
+        }
+        if self.flush_pending {
+            self.flush_pending = false;
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let candidates = match &mut self.converter {
+            Some(converter) => converter.flush(),
+            None => return,
+        };
+        for candidate in candidates {
+            self.send(candidate);
+        }
+    }
+
+    /// Emits a candidate reported by libnice to the subscribers of the raw and, if there are
+    /// any, of the SDP form.
+    ///
+    /// Candidates may still arrive after the stream has been removed or (e.g. relay candidates)
+    /// after gathering has been reported as done and not been re-armed, they are dropped.
+    pub(crate) fn send_raw(&mut self, candidate: &NiceCandidate) {
+        if !self.gathering || self.dropped_components.contains(&candidate.component_id()) {
+            return;
+        }
+        self.raw_subscribers
+            .retain(|subscriber| subscriber.unbounded_send(candidate.clone()).is_ok());
+        self.emitted_raw.push(candidate.clone());
+
+        if !self.has_subscribers() {
+            self.unconverted.push(candidate.clone());
+            return;
+        }
+        self.convert_pending();
+        if let Some(candidate) = self.convert(candidate) {
+            self.send(candidate);
+        }
+    }
+
+    /// Ends the current gathering run and with it all subscriptions, emitting the candidates
+    /// held back until then.
+    pub(crate) fn finish(&mut self) {
+        if !self.gathering {
+            return;
+        }
+        if self.has_subscribers() {
+            self.convert_pending();
+            self.flush();
+        } else {
+            self.flush_pending = true;
+        }
+        self.gathering = false;
+        self.subscribers.clear();
+        self.raw_subscribers.clear();
+    }
+}
+
+/// Sorts candidates by component and then by priority, highest first.
+pub(crate) fn sort_by_priority(candidates: &mut [Candidate]) {
+    candidates.sort_by_key(|candidate| (candidate.component, Reverse(candidate.priority)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::parse_candidate;
+
+    #[test]
+    fn address_family_policy() {
+        let v4 = parse_candidate("candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host");
+        let v6 = parse_candidate("candidate:2 1 UDP 2130706175 ::1 50001 typ host");
+
+        let mut policy = LocalCandidatePolicy::default();
+        assert!(policy.apply(v4.clone()).is_some());
+
+        policy.address_family = AddressFamilyPolicy::Ipv6Only;
+        assert!(policy.apply(v4.clone()).is_none());
+        assert_eq!(policy.apply(v6.clone()).unwrap().priority, v6.priority);
+
+        policy.address_family = AddressFamilyPolicy::PreferIpv6;
+        let v4 = policy.apply(v4).unwrap();
+        let v6 = policy.apply(v6).unwrap();
+        assert!(v6.priority > v4.priority);
+        assert_eq!(v4.priority >> 24, 126);
+        assert_eq!(v4.priority & 0xff, 255);
+    }
+
+    #[test]
+    fn local_preference_overrides() {
+        let wired = parse_candidate("candidate:1 1 UDP 2130706431 10.0.0.2 50000 typ host");
+        let wlan = parse_candidate("candidate:2 1 UDP 2130706431 192.168.1.2 50001 typ host");
+        let srflx = parse_candidate(
+            "candidate:3 1 UDP 1694498815 1.2.3.4 50002 typ srflx raddr 10.0.0.2 rport 50000",
+        );
+
+        let mut policy = LocalCandidatePolicy::default();
+        policy.local_preferences.push((CandidateSelector::Subnet("10.0.0.0".parse().unwrap(), 8), 1000));
+        policy.local_preferences.push((CandidateSelector::Type(CandidateType::Host), 10));
+
+        assert_eq!((policy.apply(wired).unwrap().priority >> 8) & 0xffff, 1000);
+        assert_eq!((policy.apply(wlan).unwrap().priority >> 8) & 0xffff, 10);
+        assert_eq!((policy.apply(srflx).unwrap().priority >> 8) & 0xffff, 1000);
+    }
+
+    #[test]
+    fn redacts_host_addresses() {
+        let host = parse_candidate("candidate:1 1 UDP 2130706431 10.0.0.2 50000 typ host");
+        let srflx = parse_candidate(
+            "candidate:3 1 UDP 1694498815 1.2.3.4 50002 typ srflx raddr 10.0.0.2 rport 50000",
+        );
+
+        let mut policy = LocalCandidatePolicy::default();
+        policy.redact_host = true;
+        assert!(policy.apply(host).is_none());
+        let srflx = policy.apply(srflx).unwrap();
+        assert_eq!(srflx.raddr.map(|raddr| raddr.to_string()), Some("0.0.0.0".to_string()));
+        assert_eq!(srflx.rport, Some(0));
+    }
+
+    #[test]
+    fn restricts_to_active_network() {
+        let wlan = parse_candidate("candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host");
+        let cellular = parse_candidate("candidate:2 1 UDP 2130706431 10.64.0.7 50001 typ host");
+        let srflx = parse_candidate("candidate:3 1 UDP 1694498815 1.2.3.4 50002 typ srflx raddr 10.64.0.7 rport 50001");
+
+        let mut policy = LocalCandidatePolicy::default();
+        policy.network = Some(vec!["10.64.0.7".parse().unwrap()]);
+        assert!(policy.apply(wlan).is_none());
+        assert!(policy.apply(cellular).is_some());
+        assert!(policy.apply(srflx).is_some());
+    }
+
+    #[test]
+    fn sorts_by_component_and_priority() {
+        let mut candidates = vec![
+            parse_candidate("candidate:1 2 UDP 2130706431 10.0.0.2 50001 typ host"),
+            parse_candidate("candidate:2 1 UDP 1694498815 1.2.3.4 50002 typ srflx raddr 10.0.0.2 rport 50000"),
+            parse_candidate("candidate:3 1 UDP 2130706431 10.0.0.2 50000 typ host"),
+        ];
+        sort_by_priority(&mut candidates);
+        let order = candidates.iter().map(|candidate| candidate.port).collect::<Vec<_>>();
+        assert_eq!(order, vec![50000, 50002, 50001]);
+    }
+
+    #[test]
+    fn limits_candidates_per_component() {
+        let host = parse_candidate("candidate:1 1 UDP 2130706431 10.0.0.2 50000 typ host");
+        let srflx = parse_candidate("candidate:2 1 UDP 1694498815 1.2.3.4 50002 typ srflx raddr 10.0.0.2 rport 50000");
+        let rtcp = parse_candidate("candidate:3 2 UDP 2130706430 10.0.0.2 50001 typ host");
+
+        let mut emitter = CandidateEmitter::new(false, Some(1));
+        assert!(emitter.push(srflx.clone()).is_some());
+        assert!(emitter.push(host.clone()).is_none());
+        assert!(emitter.push(rtcp.clone()).is_some());
+
+        let mut emitter = CandidateEmitter::new(true, Some(1));
+        assert!(emitter.push(srflx).is_none());
+        assert!(emitter.push(host).is_none());
+        assert!(emitter.push(rtcp).is_none());
+        let order = emitter.flush().iter().map(|candidate| candidate.port).collect::<Vec<_>>();
+        assert_eq!(order, vec![50000, 50001]);
+    }
+
+    #[test]
+    fn candidate_channel_rearms() {
+        let host = parse_candidate("candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host");
+        let mut channel = CandidateChannel::new();
+        let mut first = channel.subscribe();
+        channel.send(host.clone());
+        let mut late = channel.subscribe();
+        channel.finish();
+        for subscription in [&mut first, &mut late].iter_mut() {
+            assert!(subscription.try_next().unwrap().is_some());
+            assert!(subscription.try_next().unwrap().is_none());
+        }
+        // Subscriptions after gathering is done still receive the candidates of the last run
+        let mut finished = channel.subscribe();
+        assert!(finished.try_next().unwrap().is_some());
+        assert!(finished.try_next().unwrap().is_none());
+
+        channel.rearm();
+        let mut second = channel.subscribe();
+        channel.send(host);
+        assert!(second.try_next().unwrap().is_some());
+        assert!(second.try_next().is_err());
+    }
+
+    #[test]
+    fn candidate_channel_converts_lazily() {
+        let line = "candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host";
+        let host = NiceCandidate::from_sdp_without_fqdn(&parse_candidate(line)).unwrap();
+        let mut channel = CandidateChannel::new();
+        let mut raw = channel.subscribe_raw();
+        channel.send_raw(&host);
+        assert!(raw.try_next().unwrap().is_some());
+
+        // Reported before there was a subscriber for the SDP form
+        let mut late = channel.subscribe();
+        assert_eq!(late.try_next().unwrap().map(|candidate| candidate.port), Some(50000));
+        channel.finish();
+        assert!(late.try_next().unwrap().is_none());
+    }
+}
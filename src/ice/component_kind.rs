@@ -0,0 +1,100 @@
+//! Typed component ids, avoiding the mix-up of component ids (starting at `1`) and indices.
+use crate::ice::Candidate;
+use std::os::raw::c_uint;
+
+/// The id of the RTP component of streams built via [Agent::rtp_rtcp_stream_builder].
+///
+/// [Agent::rtp_rtcp_stream_builder]: crate::ice::Agent::rtp_rtcp_stream_builder
+pub const RTP_COMPONENT_ID: c_uint = 1;
+/// The id of the RTCP component of streams built via [Agent::rtp_rtcp_stream_builder].
+///
+/// [Agent::rtp_rtcp_stream_builder]: crate::ice::Agent::rtp_rtcp_stream_builder
+pub const RTCP_COMPONENT_ID: c_uint = 2;
+
+/// The role of a component within its stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ComponentKind {
+    /// The RTP component of an RTP/RTCP stream.
+    Rtp,
+    /// The RTCP component of an RTP/RTCP stream.
+    Rtcp,
+    /// A component of any other stream, identified by its component id (starting at `1`).
+    Custom(u16),
+}
+
+impl ComponentKind {
+    /// Returns the kind of the component with the given id, `rtp_rtcp` being whether its stream
+    /// has been built via [Agent::rtp_rtcp_stream_builder](crate::ice::Agent::rtp_rtcp_stream_builder).
+    pub fn from_id(component_id: c_uint, rtp_rtcp: bool) -> Self {
+        match component_id {
+            RTP_COMPONENT_ID if rtp_rtcp => ComponentKind::Rtp,
+            RTCP_COMPONENT_ID if rtp_rtcp => ComponentKind::Rtcp,
+            _ => ComponentKind::Custom(component_id as u16),
+        }
+    }
+
+    /// Returns the libnice component id.
+    pub fn id(self) -> c_uint {
+        match self {
+            ComponentKind::Rtp => RTP_COMPONENT_ID,
+            ComponentKind::Rtcp => RTCP_COMPONENT_ID,
+            ComponentKind::Custom(component_id) => c_uint::from(component_id),
+        }
+    }
+}
+
+/// Access to the component a [Candidate] belongs to, without dealing with the numeric field of
+/// the SDP attribute.
+pub trait CandidateComponent {
+    /// Returns the libnice component id (starting at `1`).
+    fn component_id(&self) -> c_uint;
+
+    /// Returns the kind of the component, see [ComponentKind::from_id].
+    fn component_kind(&self, rtp_rtcp: bool) -> ComponentKind {
+        ComponentKind::from_id(self.component_id(), rtp_rtcp)
+    }
+}
+
+impl CandidateComponent for Candidate {
+    fn component_id(&self) -> c_uint {
+        self.component as c_uint
+    }
+}
+
+/// A local candidate tagged with the component it belongs to, see
+/// [Stream::tagged_candidates](crate::ice::Stream::tagged_candidates).
+#[derive(Clone, Debug)]
+pub struct ComponentCandidate {
+    /// The component the candidate belongs to.
+    pub kind: ComponentKind,
+    /// The candidate.
+    pub candidate: Candidate,
+}
+
+impl ComponentCandidate {
+    /// Returns the libnice component id (starting at `1`).
+    pub fn component_id(&self) -> c_uint {
+        self.kind.id()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::parse_candidate;
+
+    #[test]
+    fn maps_ids() {
+        assert_eq!(ComponentKind::from_id(2, true), ComponentKind::Rtcp);
+        assert_eq!(ComponentKind::from_id(2, false), ComponentKind::Custom(2));
+        assert_eq!(ComponentKind::Rtp.id(), 1);
+        assert_eq!(ComponentKind::Custom(3).id(), 3);
+    }
+
+    #[test]
+    fn candidate_component() {
+        let candidate = parse_candidate("candidate:1 2 UDP 2130706431 10.0.0.2 50001 typ host");
+        assert_eq!(candidate.component_id(), 2);
+        assert_eq!(candidate.component_kind(true), ComponentKind::Rtcp);
+    }
+}
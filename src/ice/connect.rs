@@ -0,0 +1,137 @@
+//! Connecting a stream with a single remote peer in one call, see [connect_pair].
+use crate::ice::{Agent, ComponentState, StreamComponent};
+use crate::signaling::{self, SignalingMessage};
+use futures::future;
+use futures::task::{Context, Poll};
+use futures::{Sink, Stream as FuturesStream};
+use std::io;
+use std::pin::Pin;
+
+/// Joins the two directions of the signaling channel into a
+/// [SignalingTransport](crate::signaling::SignalingTransport).
+struct Duplex<I, O> {
+    incoming: I,
+    outgoing: O,
+}
+
+impl<I: FuturesStream + Unpin, O: Unpin> FuturesStream for Duplex<I, O> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.incoming).poll_next(cx)
+    }
+}
+
+impl<I: Unpin, O: Sink<SignalingMessage> + Unpin> Sink<SignalingMessage> for Duplex<I, O> {
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: SignalingMessage) -> Result<(), Self::Error> {
+        Pin::new(&mut self.outgoing).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
+
+/// Creates a stream with `components` components on `agent`, negotiates it with a remote peer
+/// doing the same (see [signaling::negotiate]) and returns the components once all of them are
+/// connected.
+///
+/// Unlike a helper which sets up everything itself, this takes an existing agent: creating one
+/// requires a [MainContext](glib::MainContext) which some thread iterates, and the compatibility
+/// mode, role and network configuration are up to the application. The agent must be driven
+/// (e.g. spawned on an executor) while the returned future is polled.
+///
+/// The side whose agent is in controlling mode (see [Agent::set_controlling_mode]) sends the
+/// offer, so exactly one of the two agents must be.
+///
+/// Fails if the stream cannot be built, the signaling channel fails or closes early, or a
+/// component fails to connect.
+pub async fn connect_pair<I, O>(
+    agent: &Agent,
+    signaling_in: I,
+    signaling_out: O,
+    components: usize,
+) -> io::Result<Vec<StreamComponent>>
+where
+    I: FuturesStream<Item = io::Result<SignalingMessage>> + Unpin + Send,
+    O: Sink<SignalingMessage, Error = io::Error> + Unpin + Send,
+{
+    let mut stream = agent
+        .stream_builder(components)
+        .build()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let mut transport = Duplex {
+        incoming: signaling_in,
+        outgoing: signaling_out,
+    };
+    let offerer = agent
+        .controlling_mode()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    signaling::negotiate(&mut stream, &mut transport, agent.local_ice_options(), offerer).await?;
+
+    let connected = stream
+        .take_components()
+        .into_iter()
+        .map(|component| component.wait_for_state(ComponentState::Connected));
+    future::join_all(connected)
+        .await
+        .into_iter()
+        .map(|component| {
+            component.ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, "component failed to connect"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{LoopbackFamilies, LoopbackSetup};
+    use futures::channel::mpsc;
+    use futures::future::Either;
+    use futures::{SinkExt, StreamExt};
+    use tokio::runtime;
+
+    fn channel() -> (
+        impl Sink<SignalingMessage, Error = io::Error> + Unpin + Send,
+        impl FuturesStream<Item = io::Result<SignalingMessage>> + Unpin + Send,
+    ) {
+        let (sender, receiver) = mpsc::unbounded();
+        let sender = sender.sink_map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err));
+        (sender, receiver.map(Ok))
+    }
+
+    #[test]
+    fn connects_pair() {
+        let mut executor = runtime::Builder::new().basic_scheduler().build().unwrap();
+        let setup = LoopbackSetup::new();
+        let (controlling, controlled) = setup.agent_pair(LoopbackFamilies::Ipv4).unwrap();
+
+        let (to_controlled, from_controlling) = channel();
+        let (to_controlling, from_controlled) = channel();
+        let connect = future::join(
+            connect_pair(&controlling, from_controlled, to_controlled, 2),
+            connect_pair(&controlled, from_controlling, to_controlling, 2),
+        );
+        // The agents are borrowed, so they are driven alongside instead of being spawned
+        let drive = future::join(&controlling, &controlled);
+        let (controlling_components, controlled_components) =
+            match executor.block_on(future::select(Box::pin(connect), drive)) {
+                Either::Left((components, _)) => components,
+                Either::Right(_) => panic!("agent stopped"),
+            };
+        let controlling_components = controlling_components.unwrap();
+        let controlled_components = controlled_components.unwrap();
+        assert_eq!(controlling_components.len(), 2);
+        assert_eq!(controlled_components.len(), 2);
+    }
+}
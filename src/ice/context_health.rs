@@ -0,0 +1,176 @@
+//! Detection of a [MainContext](glib::MainContext) which is not being iterated.
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Waker;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the agent checks in on its context.
+pub(crate) const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+
+/// Default for [Agent::set_main_context_stall_threshold](crate::ice::Agent::set_main_context_stall_threshold).
+pub(crate) const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_secs(5);
+
+struct HealthState {
+    last_iteration: Instant,
+    threshold: Duration,
+    stalled: bool,
+    lost_timeout: Option<Duration>,
+    /// Woken by the watchdog once the context is lost, so an idle agent notices it too.
+    waker: Option<Waker>,
+    watchdog: bool,
+}
+
+/// Tracks when a heartbeat on the agent's context last fired.
+pub(crate) struct ContextHealth(Mutex<HealthState>);
+
+impl ContextHealth {
+    pub(crate) fn new(now: Instant) -> Self {
+        ContextHealth(Mutex::new(HealthState {
+            last_iteration: now,
+            threshold: DEFAULT_STALL_THRESHOLD,
+            stalled: false,
+            lost_timeout: None,
+            waker: None,
+            watchdog: false,
+        }))
+    }
+
+    pub(crate) fn set_threshold(&self, threshold: Duration) {
+        self.0.lock().unwrap().threshold = threshold;
+    }
+
+    /// Sets the lost timeout, starting the watchdog thread once a timeout is first set.
+    pub(crate) fn set_lost_timeout(self: &Arc<Self>, timeout: Option<Duration>) {
+        let mut state = self.0.lock().unwrap();
+        state.lost_timeout = timeout;
+        if timeout.is_some() && !state.watchdog {
+            state.watchdog = true;
+            let health = Arc::downgrade(self);
+            thread::Builder::new()
+                .name("nice-context-watchdog".to_owned())
+                .spawn(move || watchdog(health))
+                .expect("failed to spawn context watchdog");
+        }
+    }
+
+    /// Registers the waker of the agent task to be woken once the context is lost.
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut state = self.0.lock().unwrap();
+        match &state.waker {
+            Some(registered) if registered.will_wake(waker) => {}
+            _ => state.waker = Some(waker.clone()),
+        }
+    }
+
+    pub(crate) fn since_last_iteration(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.0.lock().unwrap().last_iteration)
+    }
+
+    /// Returns whether the time since the last heartbeat exceeds the threshold.
+    pub(crate) fn is_stalled(&self, now: Instant) -> bool {
+        let state = self.0.lock().unwrap();
+        now.saturating_duration_since(state.last_iteration) >= state.threshold
+    }
+
+    /// Returns the time since the last heartbeat if it exceeds the lost timeout (if any).
+    pub(crate) fn lost(&self, now: Instant) -> Option<Duration> {
+        let state = self.0.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.last_iteration);
+        match state.lost_timeout {
+            Some(timeout) if elapsed >= timeout => Some(elapsed),
+            _ => None,
+        }
+    }
+
+    /// Called by the heartbeat on the context, returns whether the context was considered
+    /// stalled until now.
+    pub(crate) fn on_iteration(&self, now: Instant) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.last_iteration = now;
+        std::mem::replace(&mut state.stalled, false)
+    }
+
+    /// Called while the agent is in use, returns the time since the last heartbeat once it
+    /// exceeds the threshold (only once until the context is iterated again).
+    pub(crate) fn check(&self, now: Instant) -> Option<Duration> {
+        let mut state = self.0.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.last_iteration);
+        if state.stalled || elapsed < state.threshold {
+            return None;
+        }
+        state.stalled = true;
+        Some(elapsed)
+    }
+}
+
+/// Wakes the agent task once its context is lost, independent of that context. Exits once the
+/// agent has been dropped.
+fn watchdog(health: Weak<ContextHealth>) {
+    loop {
+        let (pause, waker) = match health.upgrade() {
+            Some(health) => {
+                let mut state = health.0.lock().unwrap();
+                let elapsed = Instant::now().saturating_duration_since(state.last_iteration);
+                match state.lost_timeout {
+                    Some(timeout) if elapsed >= timeout => (HEARTBEAT_PERIOD, state.waker.take()),
+                    Some(timeout) => ((timeout - elapsed).min(HEARTBEAT_PERIOD), None),
+                    None => (HEARTBEAT_PERIOD, None),
+                }
+            }
+            None => return,
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        thread::sleep(pause);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::task::{waker, ArcWake};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn reports_stall_once() {
+        let start = Instant::now();
+        let health = ContextHealth::new(start);
+        assert_eq!(health.check(start + Duration::from_secs(1)), None);
+        let stalled = start + DEFAULT_STALL_THRESHOLD;
+        assert_eq!(health.check(stalled), Some(DEFAULT_STALL_THRESHOLD));
+        assert_eq!(health.check(stalled + Duration::from_secs(1)), None);
+        assert!(health.on_iteration(stalled + Duration::from_secs(2)));
+        assert!(!health.on_iteration(stalled + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn reports_loss_after_timeout() {
+        let start = Instant::now();
+        let health = Arc::new(ContextHealth::new(start));
+        let later = start + Duration::from_secs(60);
+        assert_eq!(health.lost(later), None);
+        health.set_lost_timeout(Some(Duration::from_secs(30)));
+        assert_eq!(health.lost(start + Duration::from_secs(10)), None);
+        assert_eq!(health.lost(later), Some(Duration::from_secs(60)));
+        health.on_iteration(later);
+        assert_eq!(health.lost(later + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn watchdog_wakes_idle_agent() {
+        struct Flag(AtomicBool);
+        impl ArcWake for Flag {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let health = Arc::new(ContextHealth::new(Instant::now()));
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        health.register(&waker(flag.clone()));
+        health.set_lost_timeout(Some(Duration::from_millis(50)));
+        thread::sleep(Duration::from_millis(500));
+        assert!(flag.0.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,116 @@
+//! Deterministic credentials and foundations for tests, see
+//! [Agent::set_deterministic_seed](crate::ice::Agent::set_deterministic_seed).
+use crate::ice::{Candidate, CandidateType};
+use std::net::IpAddr;
+use webrtc_sdp::address::Address;
+use webrtc_sdp::attribute_type::SdpAttributeCandidateTransport;
+
+/// The characters allowed in ufrags and passwords (`ice-char`, RFC 8839).
+const ICE_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Derives the local credentials and candidate foundations of an agent from a seed.
+///
+/// The values only depend on the seed, the stream id and (for foundations) the candidate type,
+/// transport and addresses without ports, so they are stable across runs on the same machine.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeterministicIds {
+    seed: u64,
+}
+
+impl DeterministicIds {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Returns the local ufrag (8 characters) and pwd (24 characters) of a stream.
+    pub(crate) fn credentials(&self, stream_id: u32) -> (String, String) {
+        let mut state = mix(self.seed, u64::from(stream_id));
+        let mut generate = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    state = splitmix64(state);
+                    ICE_CHARS[(state % ICE_CHARS.len() as u64) as usize] as char
+                })
+                .collect::<String>()
+        };
+        let ufrag = generate(8);
+        let pwd = generate(24);
+        (ufrag, pwd)
+    }
+
+    /// Replaces the foundation libnice assigned (in gathering order) with one derived from the
+    /// candidate type, the transport and the addresses, which group the candidates the same way.
+    pub(crate) fn rewrite_foundation(&self, candidate: &mut Candidate) {
+        let c_type = match candidate.c_type {
+            CandidateType::Host => 1,
+            CandidateType::Srflx => 2,
+            CandidateType::Prflx => 3,
+            CandidateType::Relay => 4,
+        };
+        let transport = match candidate.transport {
+            SdpAttributeCandidateTransport::Udp => 1,
+            SdpAttributeCandidateTransport::Tcp => 2,
+        };
+        let mut state = mix(mix(self.seed, c_type), transport);
+        for address in std::iter::once(&candidate.address).chain(candidate.raddr.as_ref()) {
+            state = match address {
+                Address::Ip(IpAddr::V4(ip)) => mix(state, u64::from(u32::from(*ip))),
+                Address::Ip(IpAddr::V6(ip)) => {
+                    let ip = u128::from(*ip);
+                    mix(mix(state, (ip >> 64) as u64), ip as u64)
+                }
+                Address::Fqdn(name) => name.bytes().fold(state, |state, byte| mix(state, u64::from(byte))),
+            };
+        }
+        // Foundations are at most 32 characters, 8 hex digits keep them readable in snapshots
+        candidate.foundation = format!("{:08x}", state as u32);
+    }
+}
+
+fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn mix(state: u64, value: u64) -> u64 {
+    splitmix64(state ^ splitmix64(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::parse_candidate;
+
+    #[test]
+    fn derives_ids_from_seed() {
+        let ids = DeterministicIds::new(42);
+        let (ufrag, pwd) = ids.credentials(1);
+        assert_eq!((ufrag.len(), pwd.len()), (8, 24));
+        assert!(ufrag.bytes().chain(pwd.bytes()).all(|c| ICE_CHARS.contains(&c)));
+        assert_eq!(ids.credentials(1), (ufrag, pwd.clone()));
+        assert_ne!(ids.credentials(2).1, pwd);
+        assert_ne!(DeterministicIds::new(43).credentials(1).1, pwd);
+    }
+
+    #[test]
+    fn rewrites_foundations() {
+        let ids = DeterministicIds::new(42);
+        let foundation = |line: &str| {
+            let mut candidate = parse_candidate(line);
+            ids.rewrite_foundation(&mut candidate);
+            candidate.foundation
+        };
+        let host = foundation("candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host");
+        assert_eq!(host.len(), 8);
+        // Ports and components do not matter, types and addresses do
+        assert_eq!(host, foundation("candidate:7 2 UDP 2130706430 192.168.1.2 50001 typ host"));
+        assert_ne!(host, foundation("candidate:1 1 UDP 2130706431 192.168.1.3 50000 typ host"));
+        let srflx = "candidate:2 1 UDP 1694498815 1.2.3.4 61000 typ srflx raddr 192.168.1.2 rport 50000";
+        assert_ne!(host, foundation(srflx));
+        assert_eq!(foundation(srflx), foundation(srflx));
+        let tcp = "candidate:1 1 TCP 2105524479 192.168.1.2 9 typ host tcptype active";
+        assert_ne!(host, foundation(tcp));
+    }
+}
@@ -0,0 +1,529 @@
+//! Diagnostic information about components, for logging and post-mortem analysis.
+use crate::ice::{CandidateType, ComponentState, FailureReason, NatEstimate};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Maximum number of transitions kept per component, older ones are discarded.
+pub(crate) const MAX_STATE_HISTORY: usize = 64;
+
+/// Maximum number of gathering runs reported per stream, older ones are discarded.
+const MAX_GATHERING_RUNS: usize = 8;
+
+/// A single state change of a component.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StateTransition {
+    /// The state before the change (`Disconnected` for the first transition).
+    pub previous: ComponentState,
+    /// The new state.
+    pub state: ComponentState,
+    /// When libnice reported the change.
+    pub timestamp: Instant,
+    /// Time since the component has been created.
+    pub elapsed: Duration,
+    /// Why the component failed, for transitions to [ComponentState::Failed] reported by
+    /// libnice (`None` for the final state delivered when a stream is removed).
+    pub failure: Option<FailureReason>,
+}
+
+/// A snapshot of diagnostic information about a component.
+///
+/// See [StreamComponent::diagnostics](crate::ice::StreamComponent::diagnostics).
+#[derive(Clone, Debug)]
+pub struct ComponentDiagnostics {
+    /// When the component has been created.
+    pub created_at: Instant,
+    /// The most recent state transitions of the component, oldest first.
+    pub state_history: Vec<StateTransition>,
+    /// The application data sent and received via the component.
+    pub traffic: TrafficStats,
+}
+
+/// Bytes of application data sent and received via a component.
+///
+/// Only payloads are counted, neither the connectivity checks of libnice nor the overhead of
+/// the IP, UDP or TURN headers.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Bytes sent in total.
+    pub bytes_sent: u64,
+    /// Bytes received in total.
+    pub bytes_received: u64,
+    /// Bytes sent while the selected pair used a relayed candidate (on either side), included
+    /// in `bytes_sent`.
+    pub relayed_bytes_sent: u64,
+    /// Bytes received while the selected pair used a relayed candidate (on either side),
+    /// included in `bytes_received`.
+    pub relayed_bytes_received: u64,
+}
+
+/// The counters behind [TrafficStats], updated for every packet.
+#[derive(Default)]
+pub(crate) struct TrafficCounters {
+    relayed: AtomicBool,
+    sent: AtomicU64,
+    received: AtomicU64,
+    relayed_sent: AtomicU64,
+    relayed_received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl TrafficCounters {
+    /// Called whenever a pair is selected for the component.
+    pub(crate) fn set_relayed(&self, relayed: bool) {
+        self.relayed.store(relayed, Ordering::Relaxed);
+    }
+
+    /// Returns whether the selected pair of the component is relayed.
+    pub(crate) fn is_relayed(&self) -> bool {
+        self.relayed.load(Ordering::Relaxed)
+    }
+
+    /// Called whenever an inbound packet of the component is dropped.
+    pub(crate) fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_sent(&self, len: usize) {
+        self.record(&self.sent, &self.relayed_sent, len);
+    }
+
+    pub(crate) fn record_received(&self, len: usize) {
+        self.record(&self.received, &self.relayed_received, len);
+    }
+
+    fn record(&self, total: &AtomicU64, relayed: &AtomicU64, len: usize) {
+        total.fetch_add(len as u64, Ordering::Relaxed);
+        if self.relayed.load(Ordering::Relaxed) {
+            relayed.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> TrafficStats {
+        TrafficStats {
+            bytes_sent: self.sent.load(Ordering::Relaxed),
+            bytes_received: self.received.load(Ordering::Relaxed),
+            relayed_bytes_sent: self.relayed_sent.load(Ordering::Relaxed),
+            relayed_bytes_received: self.relayed_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Number of local candidates of each type emitted for a component.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CandidateCounts {
+    /// Host candidates.
+    pub host: usize,
+    /// Server reflexive candidates (discovered via STUN).
+    pub server_reflexive: usize,
+    /// Peer reflexive candidates.
+    pub peer_reflexive: usize,
+    /// Relayed candidates (allocated via TURN).
+    pub relayed: usize,
+}
+
+/// Progress of the candidate gathering of a stream, see
+/// [Stream::gathering_status](crate::ice::Stream::gathering_status).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GatheringStatus {
+    /// The candidates emitted so far, per component (the first component at index `0`).
+    pub components: Vec<CandidateCounts>,
+    /// Time since gathering (or the last regathering) started, until it completed.
+    pub elapsed: Duration,
+    /// Whether gathering has completed.
+    pub done: bool,
+}
+
+/// Time from the start of a gathering run until the first candidate of each type.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CandidateDelays {
+    /// Until the first host candidate.
+    pub host: Option<Duration>,
+    /// Until the first server reflexive candidate.
+    pub server_reflexive: Option<Duration>,
+    /// Until the first relayed candidate.
+    pub relayed: Option<Duration>,
+}
+
+/// A mapping of a local address by a NAT, as observed by a server reflexive candidate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReflexiveMapping {
+    /// The id of the component the candidate was gathered for.
+    pub component_id: u32,
+    /// The local address the STUN request was sent from.
+    pub base: SocketAddr,
+    /// The address the STUN server saw the request coming from.
+    pub mapped: SocketAddr,
+}
+
+/// A summary of a single gathering run of a stream, see
+/// [Stream::gathering_reports](crate::ice::Stream::gathering_reports).
+///
+/// Unlike [GatheringStatus], counts include all candidates libnice gathered in this run,
+/// including those not emitted due to the policies of the stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GatheringReport {
+    /// The number of the run, `1` for the initial gathering.
+    pub run: usize,
+    /// The candidates gathered per component (the first component at index `0`).
+    pub components: Vec<CandidateCounts>,
+    /// Time until the first candidate of each type has been gathered.
+    pub first_candidate: CandidateDelays,
+    /// Time since the run started, until it completed.
+    pub elapsed: Duration,
+    /// Whether the run has completed.
+    pub done: bool,
+    /// Whether a STUN server was configured when the run started.
+    pub stun_configured: bool,
+    /// The number of relay servers configured for the components when the run started.
+    pub relays_configured: usize,
+    /// The mappings observed by the server reflexive candidates.
+    ///
+    /// libnice discards server reflexive candidates with the same address as their base, so
+    /// no mappings are observed if the STUN server did not respond or there is no NAT.
+    pub mappings: Vec<ReflexiveMapping>,
+}
+
+impl GatheringReport {
+    /// Returns whether the STUN server responded, as far as observable (see
+    /// [GatheringReport::mappings]).
+    pub fn stun_responded(&self) -> bool {
+        !self.mappings.is_empty()
+    }
+
+    /// Estimates the behavior of the NAT from the mappings of this run, see
+    /// [Stream::estimate_nat](crate::ice::Stream::estimate_nat).
+    pub fn estimate_nat(&self) -> NatEstimate {
+        NatEstimate::from_mappings(&self.mappings)
+    }
+
+    /// Returns the number of relayed candidates allocated on the configured relay servers.
+    pub fn relays_allocated(&self) -> usize {
+        self.components.iter().map(|counts| counts.relayed).sum()
+    }
+}
+
+/// The candidates gathered in a single run.
+struct GatheringRun {
+    started_at: Instant,
+    finished_at: Option<Instant>,
+    report: GatheringReport,
+}
+
+pub(crate) struct GatheringProgress {
+    started_at: Instant,
+    finished_at: Option<Instant>,
+    counts: Vec<CandidateCounts>,
+    runs: VecDeque<GatheringRun>,
+    run_count: usize,
+}
+
+impl GatheringProgress {
+    pub(crate) fn new(components: usize) -> Self {
+        Self {
+            started_at: Instant::now(),
+            finished_at: None,
+            counts: vec![CandidateCounts::default(); components],
+            runs: VecDeque::new(),
+            run_count: 0,
+        }
+    }
+
+    /// Records a candidate gathered by libnice for the current run, before it is filtered.
+    pub(crate) fn observe(&mut self, component_id: u32, c_type: &CandidateType, addr: SocketAddr, base: Option<SocketAddr>) {
+        let run = match self.runs.back_mut() {
+            Some(run) => run,
+            None => return,
+        };
+        let delay = run.started_at.elapsed();
+        if let Some(counts) = run.report.components.get_mut((component_id as usize).wrapping_sub(1)) {
+            count(counts, c_type);
+        }
+        let first = &mut run.report.first_candidate;
+        let first = match c_type {
+            CandidateType::Host => &mut first.host,
+            CandidateType::Srflx => &mut first.server_reflexive,
+            CandidateType::Relay => &mut first.relayed,
+            CandidateType::Prflx => return,
+        };
+        first.get_or_insert(delay);
+        if let (CandidateType::Srflx, Some(base)) = (c_type, base) {
+            run.report.mappings.push(ReflexiveMapping {
+                component_id,
+                base,
+                mapped: addr,
+            });
+        }
+    }
+
+    pub(crate) fn record(&mut self, component_id: u32, c_type: &CandidateType) {
+        if let Some(counts) = self.counts.get_mut((component_id as usize).wrapping_sub(1)) {
+            count(counts, c_type);
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        let now = Instant::now();
+        self.finished_at = Some(now);
+        if let Some(run) = self.runs.back_mut() {
+            run.finished_at.get_or_insert(now);
+        }
+    }
+
+    /// Restarts the clock for regathering and starts a new run, candidates found so far are
+    /// still counted.
+    pub(crate) fn restart(&mut self, stun_configured: bool, relays_configured: usize) {
+        self.started_at = Instant::now();
+        self.finished_at = None;
+        self.run_count += 1;
+        if self.runs.len() >= MAX_GATHERING_RUNS {
+            self.runs.pop_front();
+        }
+        self.runs.push_back(GatheringRun {
+            started_at: self.started_at,
+            finished_at: None,
+            report: GatheringReport {
+                run: self.run_count,
+                components: vec![CandidateCounts::default(); self.counts.len()],
+                first_candidate: CandidateDelays::default(),
+                elapsed: Duration::from_secs(0),
+                done: false,
+                stun_configured,
+                relays_configured,
+                mappings: Vec::new(),
+            },
+        });
+    }
+
+    /// Returns the reports of the last runs, oldest first.
+    pub(crate) fn reports(&self) -> Vec<GatheringReport> {
+        self.runs
+            .iter()
+            .map(|run| {
+                let end = run.finished_at.unwrap_or_else(Instant::now);
+                GatheringReport {
+                    elapsed: end.duration_since(run.started_at),
+                    done: run.finished_at.is_some(),
+                    ..run.report.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the candidates libnice gathered for a component in the retained runs, including
+    /// the ones the stream did not emit.
+    pub(crate) fn observed(&self, component_id: u32) -> CandidateCounts {
+        let mut observed = CandidateCounts::default();
+        for run in &self.runs {
+            if let Some(counts) = run.report.components.get((component_id as usize).wrapping_sub(1)) {
+                observed.host += counts.host;
+                observed.server_reflexive += counts.server_reflexive;
+                observed.peer_reflexive += counts.peer_reflexive;
+                observed.relayed += counts.relayed;
+            }
+        }
+        observed
+    }
+
+    pub(crate) fn status(&self) -> GatheringStatus {
+        let end = self.finished_at.unwrap_or_else(Instant::now);
+        GatheringStatus {
+            components: self.counts.clone(),
+            elapsed: end.duration_since(self.started_at),
+            done: self.finished_at.is_some(),
+        }
+    }
+}
+
+fn count(counts: &mut CandidateCounts, c_type: &CandidateType) {
+    match c_type {
+        CandidateType::Host => counts.host += 1,
+        CandidateType::Srflx => counts.server_reflexive += 1,
+        CandidateType::Prflx => counts.peer_reflexive += 1,
+        CandidateType::Relay => counts.relayed += 1,
+    }
+}
+
+/// When to report a [SlowConsumer](crate::ice::AgentEvent::SlowConsumer) event, see
+/// [StreamBuilder::set_slow_consumer_threshold](crate::ice::StreamBuilder::set_slow_consumer_threshold).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SlowConsumerThreshold {
+    /// Number of consecutive dropped inbound packets.
+    pub packets: usize,
+    /// Time since the first of the consecutive dropped packets.
+    pub duration: Duration,
+}
+
+/// 64 packets or 500ms.
+impl Default for SlowConsumerThreshold {
+    fn default() -> Self {
+        Self {
+            packets: 64,
+            duration: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Tracks inbound packets dropped because the application did not read them fast enough.
+pub(crate) struct SlowConsumerDetector {
+    threshold: SlowConsumerThreshold,
+    dropped: usize,
+    since: Option<Instant>,
+    reported: bool,
+}
+
+impl SlowConsumerDetector {
+    pub(crate) fn new(threshold: SlowConsumerThreshold) -> Self {
+        Self {
+            threshold,
+            dropped: 0,
+            since: None,
+            reported: false,
+        }
+    }
+
+    /// Records a dropped packet, returns the number of packets dropped so far and for how long
+    /// once the threshold is exceeded (only once until a packet is delivered again).
+    pub(crate) fn on_dropped(&mut self, now: Instant) -> Option<(usize, Duration)> {
+        self.dropped += 1;
+        let duration = now.duration_since(*self.since.get_or_insert(now));
+        if self.reported || (self.dropped < self.threshold.packets && duration < self.threshold.duration) {
+            return None;
+        }
+        self.reported = true;
+        Some((self.dropped, duration))
+    }
+
+    /// Records a delivered packet, ending the current streak of dropped packets.
+    pub(crate) fn on_delivered(&mut self) {
+        self.dropped = 0;
+        self.since = None;
+        self.reported = false;
+    }
+}
+
+/// Consecutive failed sends after which a component is considered congested and consecutive
+/// successful sends after which it is not anymore.
+const CONGESTION_PACKETS: usize = 8;
+
+/// Tracks failed and short sends of a component, which libnice reports when the socket buffer is
+/// full (or the TCP connection of a TCP candidate is congested).
+#[derive(Default)]
+pub(crate) struct CongestionDetector {
+    congested: bool,
+    /// Consecutive sends contradicting the current state.
+    streak: usize,
+}
+
+impl CongestionDetector {
+    pub(crate) fn is_congested(&self) -> bool {
+        self.congested
+    }
+
+    /// Records the result of a send, returns the new state if it changed.
+    pub(crate) fn on_send(&mut self, complete: bool) -> Option<bool> {
+        if complete != self.congested {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < CONGESTION_PACKETS {
+            return None;
+        }
+        self.streak = 0;
+        self.congested = !self.congested;
+        Some(self.congested)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slow_consumer_reported_once_per_streak() {
+        let mut detector = SlowConsumerDetector::new(SlowConsumerThreshold {
+            packets: 3,
+            duration: Duration::from_secs(1),
+        });
+        let now = Instant::now();
+        assert_eq!(detector.on_dropped(now), None);
+        assert_eq!(detector.on_dropped(now), None);
+        assert_eq!(detector.on_dropped(now), Some((3, Duration::from_secs(0))));
+        assert_eq!(detector.on_dropped(now), None);
+
+        detector.on_delivered();
+        assert_eq!(detector.on_dropped(now), None);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(detector.on_dropped(later), Some((2, Duration::from_secs(2))));
+    }
+
+    #[test]
+    fn reports_gathering_runs() {
+        let base = "10.0.0.2:50000".parse().unwrap();
+        let mapped = "1.2.3.4:61000".parse().unwrap();
+        let mut progress = GatheringProgress::new(2);
+        progress.restart(true, 0);
+        progress.observe(1, &CandidateType::Host, base, None);
+        progress.observe(2, &CandidateType::Srflx, mapped, Some(base));
+        progress.finish();
+        progress.restart(false, 1);
+        progress.observe(1, &CandidateType::Relay, mapped, Some(base));
+
+        let reports = progress.reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].run, reports[0].done, reports[0].stun_responded()), (1, true, true));
+        assert_eq!(reports[0].components[1].server_reflexive, 1);
+        assert!(reports[0].first_candidate.host.is_some() && reports[0].first_candidate.relayed.is_none());
+        assert_eq!(reports[0].mappings, vec![ReflexiveMapping { component_id: 2, base, mapped }]);
+        assert_eq!((reports[1].run, reports[1].done, reports[1].relays_allocated()), (2, false, 1));
+        assert!(!reports[1].stun_responded());
+
+        let observed = progress.observed(1);
+        assert_eq!((observed.host, observed.relayed), (1, 1));
+        assert_eq!(progress.observed(2).server_reflexive, 1);
+    }
+
+    #[test]
+    fn congestion_needs_consecutive_results() {
+        let mut detector = CongestionDetector::default();
+        for _ in 0..CONGESTION_PACKETS - 1 {
+            assert_eq!(detector.on_send(false), None);
+        }
+        assert_eq!(detector.on_send(true), None);
+        for _ in 0..CONGESTION_PACKETS - 1 {
+            assert_eq!(detector.on_send(false), None);
+        }
+        assert_eq!(detector.on_send(false), Some(true));
+        assert!(detector.is_congested());
+        for _ in 0..CONGESTION_PACKETS - 1 {
+            assert_eq!(detector.on_send(true), None);
+        }
+        assert_eq!(detector.on_send(true), Some(false));
+    }
+
+    #[test]
+    fn traffic_counts_relayed_bytes() {
+        let traffic = TrafficCounters::default();
+        traffic.record_sent(100);
+        traffic.set_relayed(true);
+        traffic.record_sent(10);
+        traffic.record_received(20);
+        traffic.set_relayed(false);
+        traffic.record_received(200);
+        assert_eq!(
+            traffic.stats(),
+            TrafficStats {
+                bytes_sent: 110,
+                bytes_received: 220,
+                relayed_bytes_sent: 10,
+                relayed_bytes_received: 20,
+            }
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Events emitted by an [Agent](crate::ice::Agent) about its streams and components.
+use crate::ice::{AgentError, Candidate, StateTransition};
+use futures::channel::mpsc;
+use std::net::SocketAddr;
+use std::os::raw::c_uint;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An event emitted via [Agent::events](crate::ice::Agent::events).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum AgentEvent {
+    /// A TURN allocation event for a component.
+    Relay {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// What happened.
+        event: RelayEvent,
+    },
+    /// A pair using a relayed candidate on at least one side has been selected for a component,
+    /// i.e. the component fell back to relaying.
+    RelayedPairSelected {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// Whether the local candidate is relayed.
+        local_relayed: bool,
+        /// Whether the remote candidate is relayed (via a relay server of the remote peer).
+        remote_relayed: bool,
+        /// The local relay server used by the pair, if the local candidate is relayed and the
+        /// server could be identified. libnice does not expose this directly, so the server is
+        /// only known if it is the only one configured for the component or the only one with
+        /// the IP of the relayed candidate.
+        relay_server: Option<SocketAddr>,
+    },
+    /// The state of a component changed.
+    ComponentStateChanged {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The previous and new state and when the change was reported, including the
+        /// [reason](StateTransition::failure) if the component failed.
+        transition: StateTransition,
+    },
+    /// Inbound packets of a component are being dropped because the application does not read
+    /// them fast enough (its inbound buffer is full), see
+    /// [StreamBuilder::set_slow_consumer_threshold](crate::ice::StreamBuilder::set_slow_consumer_threshold).
+    ///
+    /// Emitted once per streak of dropped packets, i.e. again only after a packet could be
+    /// delivered in between.
+    SlowConsumer {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The number of consecutive packets dropped so far.
+        dropped: usize,
+        /// Time since the first of these packets was dropped.
+        duration: Duration,
+    },
+    /// Sending via a component started or stopped failing, see
+    /// [StreamComponent::is_congested](crate::ice::StreamComponent::is_congested).
+    ///
+    /// A component is considered congested after several consecutive packets could not be sent
+    /// (or only partially), usually because the socket buffer is full, and not anymore after
+    /// several consecutive packets have been sent again. Only packets sent while the component
+    /// is connected are considered, as libnice fails every send before that.
+    Congestion {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// Whether the component is congested now.
+        congested: bool,
+    },
+    /// Nothing has been sent or received via a component for the timeout configured via
+    /// [StreamComponent::set_idle_timeout](crate::ice::StreamComponent::set_idle_timeout).
+    ///
+    /// Applications usually react by sending keepalives or by tearing down abandoned sessions.
+    ComponentIdle {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// Time since the last packet has been sent or received.
+        idle_for: Duration,
+    },
+    /// The [MainContext](glib::MainContext) of the agent has not been iterated for longer than
+    /// the threshold (see
+    /// [Agent::set_main_context_stall_threshold](crate::ice::Agent::set_main_context_stall_threshold))
+    /// while the agent is in use, so libnice cannot send, receive or report anything.
+    ///
+    /// This is usually caused by not running a [MainLoop](glib::MainLoop) (or otherwise
+    /// iterating the context) or by blocking the thread which does.
+    MainContextStalled {
+        /// Time since the context was last iterated.
+        since_last_iteration: Duration,
+    },
+    /// A remote candidate could not be passed to libnice and is ignored, e.g. because its
+    /// address is an unresolved FQDN.
+    ///
+    /// Candidates rejected by [Stream::add_remote_candidate](crate::ice::Stream::add_remote_candidate)
+    /// are not reported, the caller already gets the error.
+    CandidateRejected {
+        /// The id of the stream the candidate was added to.
+        stream_id: c_uint,
+        /// The id of the component the candidate was added to.
+        component_id: c_uint,
+        /// The rejected candidate.
+        candidate: Candidate,
+        /// Why the candidate was rejected.
+        reason: String,
+    },
+    /// The [MainContext](glib::MainContext) is being iterated again after
+    /// [AgentEvent::MainContextStalled] has been emitted.
+    MainContextRecovered,
+    /// The agent stopped operating and its future resolved with `error`, see [AgentError].
+    ///
+    /// Emitted once, also for agents driven by an [AgentSet](crate::ice::AgentSet).
+    Terminated {
+        /// Why the agent stopped.
+        error: AgentError,
+    },
+    /// Gathering of a stream has been queued because the maximum number of streams gathering
+    /// at the same time has been reached, see
+    /// [Agent::set_max_concurrent_gathering](crate::ice::Agent::set_max_concurrent_gathering).
+    GatheringQueued {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The number of streams queued before this one.
+        position: usize,
+    },
+    /// A stream whose gathering has been queued starts gathering now.
+    GatheringDequeued {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// Time the stream waited in the queue.
+        waited: Duration,
+    },
+    /// The options set via
+    /// [StreamBuilder::set_socket_options](crate::ice::StreamBuilder::set_socket_options) could
+    /// not be applied to a socket of a component.
+    SocketOptionsFailed {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// The error reported by the operating system.
+        error: String,
+    },
+    /// A component of a stream failed and the stream falls back to relaying, see
+    /// [StreamBuilder::set_relay_fallback](crate::ice::StreamBuilder::set_relay_fallback).
+    ///
+    /// The relayed candidates must be subscribed via
+    /// [Stream::candidates](crate::ice::Stream::candidates) and sent to the remote peer.
+    RelayFallback {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The relay servers added to the components.
+        servers: Vec<SocketAddr>,
+        /// Whether gathering on the relay servers has been started, otherwise the error is
+        /// recorded in the [audit log](crate::ice::Agent::audit_log).
+        started: bool,
+    },
+    /// A STUN server has been selected for a stream, see
+    /// [StreamBuilder::set_stun_servers](crate::ice::StreamBuilder::set_stun_servers).
+    StunServerSelected {
+        /// The id of the stream.
+        stream_id: c_uint,
+        /// The server configured, which has not been probed if it is the last one.
+        server: SocketAddr,
+        /// The servers probed before which did not respond in time.
+        unresponsive: Vec<SocketAddr>,
+    },
+    /// A component which lost its connection has not recovered within the time allowed by its
+    /// [DisconnectPolicy](crate::ice::DisconnectPolicy), the application should restart ICE
+    /// (see [Stream::restart](crate::ice::Stream::restart)).
+    NeedsRestart {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// Time since the component lost its connection.
+        disconnected_for: Duration,
+    },
+    /// A component with a [DisconnectPolicy](crate::ice::DisconnectPolicy) connected again after
+    /// losing its connection, either on its own or after an ICE restart.
+    ComponentReconnected {
+        /// The id of the stream the component belongs to.
+        stream_id: c_uint,
+        /// The id of the component.
+        component_id: c_uint,
+        /// Time the component has been disconnected.
+        outage: Duration,
+    },
+}
+
+/// Identifies the relay server which allocated `relayed_addr` among the relay servers configured
+/// for a component.
+///
+/// libnice does not expose which server a relayed candidate belongs to, so this is unambiguous
+/// only if there is a single server or a single server with the same IP as the relayed address
+/// (which is where most TURN servers allocate).
+pub(crate) fn identify_relay_server(relayed_addr: SocketAddr, servers: &[SocketAddr]) -> Option<SocketAddr> {
+    if let [server] = servers {
+        return Some(*server);
+    }
+    let mut matching = servers.iter().filter(|server| server.ip() == relayed_addr.ip());
+    match (matching.next(), matching.next()) {
+        (Some(server), None) => Some(*server),
+        _ => None,
+    }
+}
+
+/// A change of the agent's controlling mode, emitted to every live stream via
+/// [Stream::role_changes](crate::ice::Stream::role_changes).
+///
+/// Protocols on top of ICE may need to react to this, e.g. by redoing the DTLS role selection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RoleChange {
+    /// Whether the agent is now in controlling mode.
+    pub controlling: bool,
+    /// Why the mode changed.
+    pub cause: RoleChangeCause,
+}
+
+/// Why the controlling mode of an agent changed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RoleChangeCause {
+    /// Via [Agent::set_controlling_mode](crate::ice::Agent::set_controlling_mode).
+    Api,
+    /// libnice resolved a role conflict with the remote peer (RFC 8445, 7.3.1.1).
+    Conflict,
+}
+
+/// The controlling mode last reported by libnice and the one requested via the API, if a
+/// request is in progress.
+#[derive(Default)]
+pub(crate) struct RoleState {
+    pub(crate) controlling: bool,
+    pub(crate) requested: Option<bool>,
+}
+
+/// The lifecycle of TURN allocations, as far as libnice exposes it.
+///
+/// libnice handles refreshing allocations internally and does not report refreshes or expiry,
+/// so only the outcome of the initial allocation can be reported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RelayEvent {
+    /// A relayed candidate has been allocated on one of the configured relay servers.
+    Allocated {
+        /// The relayed transport address allocated on the TURN server.
+        relayed_addr: SocketAddr,
+    },
+    /// Gathering has completed without allocations on some of the configured relay servers,
+    /// usually due to wrong credentials or an unreachable server.
+    AllocationFailed {
+        /// The number of relay servers without allocation.
+        missing: usize,
+    },
+}
+
+/// The subscribers of the events (or audit records) of an agent.
+#[derive(Clone)]
+pub(crate) struct EventSinks<T = AgentEvent>(Arc<Mutex<Vec<mpsc::UnboundedSender<T>>>>);
+
+impl<T> Default for EventSinks<T> {
+    fn default() -> Self {
+        EventSinks(Arc::default())
+    }
+}
+
+impl<T: Clone> EventSinks<T> {
+    pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<T> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.0.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends the event to all subscribers, dropping those which are gone.
+    pub(crate) fn emit(&self, event: T) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|sink| sink.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Returns whether there are no subscribers, in which case events need not be created.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identifies_relay_server() {
+        let a = "1.2.3.4:3478".parse().unwrap();
+        let b = "5.6.7.8:3478".parse().unwrap();
+        let relayed = "5.6.7.8:50000".parse().unwrap();
+        assert_eq!(identify_relay_server(relayed, &[a]), Some(a));
+        assert_eq!(identify_relay_server(relayed, &[a, b]), Some(b));
+        assert_eq!(identify_relay_server(relayed, &[a, b, b]), None);
+        assert_eq!(identify_relay_server(relayed, &[]), None);
+    }
+}
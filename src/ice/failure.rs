@@ -0,0 +1,103 @@
+//! Classification of component failures, see [StateTransition::failure](crate::ice::StateTransition::failure).
+use crate::ice::ComponentState;
+
+/// Why a component failed, as far as it can be derived from the agent's bookkeeping.
+///
+/// libnice itself only reports the [ComponentState::Failed] state, so the reason is inferred
+/// from the candidates gathered and added and from the states the component went through.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "kebab-case"))]
+#[non_exhaustive]
+pub enum FailureReason {
+    /// No local candidates have been gathered for the component, e.g. because no interface
+    /// matched the stream's candidate policy.
+    NoCandidates,
+    /// No remote candidates have been added to the component.
+    NoRemoteCandidates,
+    /// Connectivity checks ran, but no candidate pair succeeded.
+    AllPairsFailed,
+    /// The component was connected but the remote peer stopped responding to keepalives or
+    /// consent checks (RFC 7675).
+    ConsentExpired,
+    /// The component has relay servers configured but none of them allocated a relayed
+    /// candidate.
+    ///
+    /// libnice does not report the TURN error, so this includes unreachable relay servers,
+    /// but rejected credentials are the common cause.
+    RelayAuthFailed,
+    /// The component failed before connectivity checks started, e.g. because gathering did
+    /// not complete.
+    Timeout,
+}
+
+/// What is known about a component at the time it failed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FailureSignals {
+    /// The state before the component failed.
+    pub(crate) previous: Option<ComponentState>,
+    /// Whether the component reached the connecting state, i.e. checks started.
+    pub(crate) checks_started: bool,
+    /// Local candidates gathered for the component (before filtering).
+    pub(crate) local_candidates: usize,
+    /// Relayed candidates among them.
+    pub(crate) relayed_candidates: usize,
+    /// Relay servers configured for the component.
+    pub(crate) relays_configured: usize,
+    /// Whether gathering completed.
+    pub(crate) gathering_done: bool,
+    /// Remote candidates successfully added to the component.
+    pub(crate) remote_candidates: usize,
+}
+
+impl FailureSignals {
+    pub(crate) fn classify(&self) -> FailureReason {
+        match self.previous {
+            Some(ComponentState::Connected) | Some(ComponentState::Ready) => return FailureReason::ConsentExpired,
+            _ => {}
+        }
+        if self.relays_configured > 0 && self.gathering_done && self.relayed_candidates == 0 {
+            FailureReason::RelayAuthFailed
+        } else if self.local_candidates == 0 {
+            FailureReason::NoCandidates
+        } else if self.remote_candidates == 0 {
+            FailureReason::NoRemoteCandidates
+        } else if self.checks_started {
+            FailureReason::AllPairsFailed
+        } else {
+            FailureReason::Timeout
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classifies_failures() {
+        let checked = FailureSignals {
+            previous: Some(ComponentState::Connecting),
+            checks_started: true,
+            local_candidates: 2,
+            remote_candidates: 3,
+            gathering_done: true,
+            ..Default::default()
+        };
+        assert_eq!(checked.classify(), FailureReason::AllPairsFailed);
+
+        let connected = FailureSignals { previous: Some(ComponentState::Ready), ..checked.clone() };
+        assert_eq!(connected.classify(), FailureReason::ConsentExpired);
+
+        let relay_failed = FailureSignals { relays_configured: 1, ..checked.clone() };
+        assert_eq!(relay_failed.classify(), FailureReason::RelayAuthFailed);
+
+        let no_remote = FailureSignals { remote_candidates: 0, ..checked.clone() };
+        assert_eq!(no_remote.classify(), FailureReason::NoRemoteCandidates);
+
+        let no_local = FailureSignals { local_candidates: 0, ..checked.clone() };
+        assert_eq!(no_local.classify(), FailureReason::NoCandidates);
+
+        let unchecked = FailureSignals { checks_started: false, gathering_done: false, ..checked };
+        assert_eq!(unchecked.classify(), FailureReason::Timeout);
+    }
+}
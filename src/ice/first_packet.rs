@@ -0,0 +1,102 @@
+//! Notification about the first application packet received by a component.
+//!
+//! A component being `Connected` only means that the connectivity checks succeeded, the first
+//! packet shows that the remote application is actually sending.
+use futures::channel::oneshot;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+enum State {
+    Waiting(Vec<oneshot::Sender<()>>),
+    Received,
+    Closed,
+}
+
+/// Wakes the futures waiting for the first packet of a component.
+pub(crate) struct FirstPacketSignal(Mutex<State>);
+
+impl Default for FirstPacketSignal {
+    fn default() -> Self {
+        FirstPacketSignal(Mutex::new(State::Waiting(Vec::new())))
+    }
+}
+
+impl FirstPacketSignal {
+    /// Called for every application packet received by the component.
+    pub(crate) fn on_packet(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let State::Waiting(waiters) = &mut *state {
+            for waiter in waiters.drain(..) {
+                let _ = waiter.send(());
+            }
+            *state = State::Received;
+        }
+    }
+
+    /// Called once the component has been removed by the agent, pending futures resolve to
+    /// `false`.
+    pub(crate) fn close(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let State::Waiting(_) = &*state {
+            *state = State::Closed;
+        }
+    }
+
+    pub(crate) fn wait(&self) -> FirstPacketFuture {
+        let receiver = match &mut *self.0.lock().unwrap() {
+            State::Waiting(waiters) => {
+                let (sender, receiver) = oneshot::channel();
+                waiters.push(sender);
+                Some(receiver)
+            }
+            State::Received => None,
+            State::Closed => {
+                // Dropping the sender right away resolves the future to false
+                Some(oneshot::channel().1)
+            }
+        };
+        FirstPacketFuture { receiver }
+    }
+}
+
+/// Future returned by [StreamComponent::wait_for_first_packet](crate::ice::StreamComponent::wait_for_first_packet).
+pub struct FirstPacketFuture {
+    /// `None` if a packet has already been received.
+    receiver: Option<oneshot::Receiver<()>>,
+}
+
+impl Future for FirstPacketFuture {
+    type Output = bool; // false if the component has been closed before receiving a packet
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match &mut self.receiver {
+            Some(receiver) => Pin::new(receiver).poll(cx).map(|result| result.is_ok()),
+            None => Poll::Ready(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn resolves_on_first_packet() {
+        let signal = FirstPacketSignal::default();
+        let mut pending = signal.wait();
+        assert_eq!((&mut pending).now_or_never(), None);
+        signal.on_packet();
+        signal.close();
+        assert_eq!(pending.now_or_never(), Some(true));
+        assert_eq!(signal.wait().now_or_never(), Some(true));
+
+        let closed = FirstPacketSignal::default();
+        let pending = closed.wait();
+        closed.close();
+        assert_eq!(pending.now_or_never(), Some(false));
+        assert_eq!(closed.wait().now_or_never(), Some(false));
+    }
+}
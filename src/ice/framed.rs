@@ -0,0 +1,129 @@
+//! Integration with tokio and the codecs of tokio-util.
+use crate::ice::StreamComponent;
+use bytes::BytesMut;
+use futures::ready;
+use futures::Sink;
+use futures::Stream as FuturesStream;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A [StreamComponent] combined with a codec, preserving packet boundaries.
+///
+/// Unlike [tokio_util::codec::Framed], every inbound packet is decoded on its own (as a single
+/// frame via [Decoder::decode_eof]) and every outbound frame is sent as a single packet, which is
+/// what datagram based protocols expect. In reliable mode, where the component is a byte stream,
+/// use [tokio_util::codec::Framed] directly instead.
+///
+/// Created via [StreamComponent::framed].
+pub struct ComponentFramed<C> {
+    component: StreamComponent,
+    codec: C,
+    write_buf: BytesMut,
+}
+
+impl<C> ComponentFramed<C> {
+    pub(crate) fn new(component: StreamComponent, codec: C) -> Self {
+        Self {
+            component,
+            codec,
+            write_buf: BytesMut::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying component.
+    pub fn get_ref(&self) -> &StreamComponent {
+        &self.component
+    }
+
+    /// Returns a mutable reference to the underlying component.
+    pub fn get_mut(&mut self) -> &mut StreamComponent {
+        &mut self.component
+    }
+
+    /// Returns a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns a mutable reference to the codec.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Returns the underlying component and the codec.
+    pub fn into_inner(self) -> (StreamComponent, C) {
+        (self.component, self.codec)
+    }
+}
+
+impl<C: Decoder + Unpin> FuturesStream for ComponentFramed<C> {
+    type Item = Result<C::Item, C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let packet = match ready!(Pin::new(&mut this.component).poll_next(cx)) {
+                Some(packet) => packet,
+                None => return Poll::Ready(None),
+            };
+            let mut buf = BytesMut::from(&packet[..]);
+            match this.codec.decode_eof(&mut buf) {
+                Ok(Some(item)) => return Poll::Ready(Some(Ok(item))),
+                Ok(None) => continue, // packet does not contain a frame
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+        }
+    }
+}
+
+impl<I, C: Encoder<I> + Unpin> Sink<I> for ComponentFramed<C> {
+    type Error = C::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if self.component.is_closed() {
+            return Poll::Ready(Err(closed().into()));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.codec.encode(item, &mut this.write_buf)?;
+        let packet = this.write_buf.split().to_vec();
+        this.component.unbounded_send(packet).map_err(|_| closed().into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "agent has been closed")
+}
+
+impl tokio::io::AsyncRead for StreamComponent {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        futures::io::AsyncRead::poll_read(self, cx, buf)
+    }
+}
+
+impl tokio::io::AsyncWrite for StreamComponent {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        futures::io::AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        futures::io::AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        futures::io::AsyncWrite::poll_close(self, cx)
+    }
+}
@@ -0,0 +1,93 @@
+//! Limiting the number of streams gathering candidates at the same time, see
+//! [Agent::set_max_concurrent_gathering](crate::ice::Agent::set_max_concurrent_gathering).
+use std::collections::{HashSet, VecDeque};
+use std::os::raw::c_uint;
+use std::time::{Duration, Instant};
+
+/// Tracks which streams are gathering and which are waiting for their turn.
+#[derive(Default)]
+pub(crate) struct GatherGate {
+    limit: Option<usize>,
+    active: HashSet<c_uint>,
+    queued: VecDeque<(c_uint, Instant)>,
+}
+
+impl GatherGate {
+    /// Returns `None` if the stream may start gathering right away (or already is), otherwise
+    /// its position in the queue (the next stream to start at position `0`).
+    pub(crate) fn request(&mut self, stream_id: c_uint, now: Instant) -> Option<usize> {
+        if self.active.contains(&stream_id) {
+            return None;
+        }
+        if let Some(position) = self.queued.iter().position(|(id, _)| *id == stream_id) {
+            return Some(position);
+        }
+        if self.limit.map_or(true, |limit| self.active.len() < limit) {
+            self.active.insert(stream_id);
+            return None;
+        }
+        self.queued.push_back((stream_id, now));
+        Some(self.queued.len() - 1)
+    }
+
+    /// Moves queued streams to the active ones while below the limit, returns them with the time
+    /// they have been queued.
+    fn promote(&mut self, now: Instant) -> Vec<(c_uint, Duration)> {
+        let mut promoted = Vec::new();
+        while self.limit.map_or(true, |limit| self.active.len() < limit) {
+            let (stream_id, queued_at) = match self.queued.pop_front() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.active.insert(stream_id);
+            promoted.push((stream_id, now.saturating_duration_since(queued_at)));
+        }
+        promoted
+    }
+
+    /// Called once a stream finished (or failed) gathering, returns the streams which may start
+    /// gathering now.
+    pub(crate) fn finish(&mut self, stream_id: c_uint, now: Instant) -> Vec<(c_uint, Duration)> {
+        self.active.remove(&stream_id);
+        self.promote(now)
+    }
+
+    /// Called once a stream has been removed, returns the streams which may start gathering now.
+    pub(crate) fn remove(&mut self, stream_id: c_uint, now: Instant) -> Vec<(c_uint, Duration)> {
+        self.queued.retain(|(id, _)| *id != stream_id);
+        self.finish(stream_id, now)
+    }
+
+    /// Returns the streams which may start gathering with the new limit.
+    pub(crate) fn set_limit(&mut self, limit: Option<usize>, now: Instant) -> Vec<(c_uint, Duration)> {
+        self.limit = limit.map(|limit| limit.max(1));
+        self.promote(now)
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queues_beyond_limit() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let mut gate = GatherGate::default();
+        assert_eq!(gate.set_limit(Some(1), now), vec![]);
+        assert_eq!(gate.request(1, now), None);
+        assert_eq!(gate.request(2, now), Some(0));
+        assert_eq!(gate.request(3, now), Some(1));
+        assert_eq!(gate.request(1, now), None);
+
+        assert_eq!(gate.remove(2, now), vec![]);
+        assert_eq!(gate.finish(1, later), vec![(3, Duration::from_secs(1))]);
+        assert_eq!(gate.request(4, now), Some(0));
+        assert_eq!(gate.set_limit(None, later), vec![(4, Duration::from_secs(1))]);
+        assert_eq!(gate.queued(), 0);
+    }
+}
@@ -0,0 +1,103 @@
+//! A compact summary of the health of an agent, see [Agent::health](crate::ice::Agent::health).
+use crate::ice::ComponentState;
+
+/// A summary of the state of an agent, cheap enough to be created for every request to a
+/// health endpoint.
+///
+/// With the `serde` feature, it implements `Serialize`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AgentHealth {
+    /// The number of streams by the aggregate state of their components.
+    pub streams: StreamStateCounts,
+    /// Inbound packets dropped by the current components because the application did not read
+    /// them fast enough or the memory limit was reached.
+    pub dropped_packets: u64,
+    /// The number of components whose selected pair is relayed.
+    pub relayed_components: usize,
+    /// Bytes of packets queued by the agent, see [Agent::memory_usage](crate::ice::Agent::memory_usage).
+    pub queued_bytes: usize,
+    /// Whether the agent has streams and its [MainContext](glib::MainContext) is currently
+    /// considered stalled, see [AgentEvent::MainContextStalled](crate::ice::AgentEvent::MainContextStalled).
+    pub main_context_stalled: bool,
+    /// The error of the last operation which libnice reported as failed.
+    pub last_error: Option<String>,
+}
+
+/// The number of streams in each state, see [AgentHealth::streams].
+///
+/// The state of a stream is `Failed` if any of its components failed, otherwise the least
+/// advanced state of its components.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StreamStateCounts {
+    /// Streams which have not started gathering.
+    pub disconnected: usize,
+    /// Streams gathering candidates.
+    pub gathering: usize,
+    /// Streams performing connectivity checks.
+    pub connecting: usize,
+    /// Streams with a working pair for all components.
+    pub connected: usize,
+    /// Streams which completed connectivity checks for all components.
+    pub ready: usize,
+    /// Streams with at least one failed component.
+    pub failed: usize,
+}
+
+impl StreamStateCounts {
+    pub(crate) fn add(&mut self, state: ComponentState) {
+        let count = match state {
+            ComponentState::Disconnected => &mut self.disconnected,
+            ComponentState::Gathering => &mut self.gathering,
+            ComponentState::Connecting => &mut self.connecting,
+            ComponentState::Connected => &mut self.connected,
+            ComponentState::Ready => &mut self.ready,
+            ComponentState::Failed => &mut self.failed,
+        };
+        *count += 1;
+    }
+
+    /// Returns the total number of streams.
+    pub fn total(&self) -> usize {
+        self.disconnected + self.gathering + self.connecting + self.connected + self.ready + self.failed
+    }
+}
+
+/// Returns the aggregate state of a stream from the states of its components, see
+/// [StreamStateCounts].
+pub(crate) fn aggregate_state(states: impl IntoIterator<Item = ComponentState>) -> ComponentState {
+    fn rate(state: ComponentState) -> u8 {
+        match state {
+            ComponentState::Failed => 0,
+            ComponentState::Disconnected => 1,
+            ComponentState::Gathering => 2,
+            ComponentState::Connecting => 3,
+            ComponentState::Connected => 4,
+            ComponentState::Ready => 5,
+        }
+    }
+    states
+        .into_iter()
+        .min_by_key(|state| rate(*state))
+        .unwrap_or(ComponentState::Disconnected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aggregates_stream_state() {
+        use ComponentState::*;
+        assert_eq!(aggregate_state(vec![Ready, Connected]), Connected);
+        assert_eq!(aggregate_state(vec![Ready, Failed, Gathering]), Failed);
+        assert_eq!(aggregate_state(vec![Ready, Ready]), Ready);
+        assert_eq!(aggregate_state(vec![]), Disconnected);
+
+        let mut counts = StreamStateCounts::default();
+        counts.add(Ready);
+        counts.add(Failed);
+        assert_eq!(counts.total(), 2);
+    }
+}
@@ -0,0 +1,103 @@
+//! Accounting of the memory used by the packets queued by an agent.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Approximate memory used by the packets queued by an agent, see
+/// [Agent::memory_usage](crate::ice::Agent::memory_usage).
+///
+/// Only the payloads are counted, not the overhead of the queues themselves.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Bytes of inbound packets received but not yet read by the application.
+    pub inbound_bytes: usize,
+    /// Bytes of outbound packets queued but not yet passed to libnice.
+    pub outbound_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the total number of queued bytes.
+    pub fn total(&self) -> usize {
+        self.inbound_bytes + self.outbound_bytes
+    }
+}
+
+/// The queued bytes of an agent, shared with all of its components.
+#[derive(Debug)]
+pub(crate) struct MemoryAccount {
+    inbound: AtomicUsize,
+    outbound: AtomicUsize,
+    /// `usize::MAX` if there is no limit.
+    limit: AtomicUsize,
+}
+
+impl Default for MemoryAccount {
+    fn default() -> Self {
+        Self {
+            inbound: AtomicUsize::new(0),
+            outbound: AtomicUsize::new(0),
+            limit: AtomicUsize::new(usize::MAX),
+        }
+    }
+}
+
+impl MemoryAccount {
+    pub(crate) fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            inbound_bytes: self.inbound.load(Ordering::Relaxed),
+            outbound_bytes: self.outbound.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.limit.store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        match self.limit.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Accounts for `len` more bytes unless that would exceed the limit. Concurrent reservations
+    /// may exceed it slightly, which is fine for its purpose.
+    fn try_reserve(&self, counter: &AtomicUsize, len: usize) -> bool {
+        if self.usage().total().saturating_add(len) > self.limit.load(Ordering::Relaxed) {
+            return false;
+        }
+        counter.fetch_add(len, Ordering::Relaxed);
+        true
+    }
+
+    pub(crate) fn try_reserve_inbound(&self, len: usize) -> bool {
+        self.try_reserve(&self.inbound, len)
+    }
+
+    pub(crate) fn release_inbound(&self, len: usize) {
+        self.inbound.fetch_sub(len, Ordering::Relaxed);
+    }
+
+    pub(crate) fn try_reserve_outbound(&self, len: usize) -> bool {
+        self.try_reserve(&self.outbound, len)
+    }
+
+    pub(crate) fn release_outbound(&self, len: usize) {
+        self.outbound.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn enforces_limit() {
+        let account = MemoryAccount::default();
+        assert!(account.try_reserve_inbound(1000));
+        account.set_limit(Some(1500));
+        assert!(!account.try_reserve_outbound(600));
+        assert!(account.try_reserve_outbound(500));
+        account.release_inbound(1000);
+        assert_eq!(account.usage(), MemoryUsage { inbound_bytes: 0, outbound_bytes: 500 });
+        assert!(account.try_reserve_inbound(1000));
+    }
+}
@@ -0,0 +1,128 @@
+//! Best-effort classification of the NAT in front of an agent from the mappings observed while
+//! gathering, see [Stream::estimate_nat](crate::ice::Stream::estimate_nat).
+use crate::ice::ReflexiveMapping;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// The mapping behavior of a NAT (RFC 4787, 4.1).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NatMapping {
+    /// No mapping has been observed, either because no STUN server responded or because there
+    /// is no NAT (libnice discards mappings to the local address itself).
+    Unknown,
+    /// The NAT reuses the mapping of a local address for all destinations, so connectivity
+    /// checks from the remote peer usually succeed ("cone" NAT).
+    EndpointIndependent,
+    /// The NAT creates a new mapping per destination address (and port), so direct connections
+    /// often require a relay if the remote peer is behind such a NAT too ("symmetric" NAT).
+    AddressPortDependent,
+}
+
+/// A best-effort estimate of the NAT behavior, see [NatEstimate::from_mappings].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NatEstimate {
+    /// The estimated mapping behavior.
+    pub mapping: NatMapping,
+    /// Whether the behavior has been observed directly (a local address mapped differently for
+    /// different servers) rather than inferred from the port allocation pattern.
+    pub confident: bool,
+    /// Whether the NAT kept the local ports in all mappings.
+    pub port_preserving: bool,
+    /// The number of mappings the estimate is based on.
+    pub mappings: usize,
+}
+
+impl NatEstimate {
+    /// Estimates the NAT behavior from the mappings observed by server reflexive candidates of
+    /// a single gathering run, as mappings of different runs are not comparable.
+    ///
+    /// A local address which is mapped to different addresses (by the STUN server and the
+    /// relay servers) proves an address and port dependent mapping. Otherwise the mappings of
+    /// the local ports of the same interface are compared: a NAT which keeps their distance
+    /// (in particular one preserving the ports) most likely maps independently of the endpoint,
+    /// while one allocating unrelated ports most likely does not.
+    pub fn from_mappings(mappings: &[ReflexiveMapping]) -> Self {
+        let port_preserving = !mappings.is_empty() && mappings.iter().all(|m| m.base.port() == m.mapped.port());
+        let estimate = |mapping, confident| NatEstimate {
+            mapping,
+            confident,
+            port_preserving,
+            mappings: mappings.len(),
+        };
+
+        let mut by_base: HashMap<SocketAddr, SocketAddr> = HashMap::new();
+        for mapping in mappings {
+            match by_base.insert(mapping.base, mapping.mapped) {
+                Some(mapped) if mapped != mapping.mapped => {
+                    return estimate(NatMapping::AddressPortDependent, true);
+                }
+                _ => {}
+            }
+        }
+
+        // The offset between local and mapped port per local interface
+        let mut offsets: HashMap<IpAddr, Vec<i32>> = HashMap::new();
+        for (base, mapped) in &by_base {
+            let offset = i32::from(mapped.port()) - i32::from(base.port());
+            offsets.entry(base.ip()).or_default().push(offset);
+        }
+        let comparable = offsets.values().filter(|offsets| offsets.len() > 1).collect::<Vec<_>>();
+        if comparable.is_empty() {
+            let mapping = if port_preserving {
+                NatMapping::EndpointIndependent
+            } else {
+                NatMapping::Unknown
+            };
+            return estimate(mapping, false);
+        }
+        let consistent = comparable
+            .iter()
+            .all(|offsets| offsets.iter().all(|offset| *offset == offsets[0]));
+        let mapping = if consistent {
+            NatMapping::EndpointIndependent
+        } else {
+            NatMapping::AddressPortDependent
+        };
+        estimate(mapping, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mapping(base: &str, mapped: &str) -> ReflexiveMapping {
+        ReflexiveMapping {
+            component_id: 1,
+            base: base.parse().unwrap(),
+            mapped: mapped.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn classifies_mappings() {
+        let unknown = NatEstimate::from_mappings(&[]);
+        assert_eq!((unknown.mapping, unknown.mappings), (NatMapping::Unknown, 0));
+
+        let dependent = NatEstimate::from_mappings(&[
+            mapping("10.0.0.2:5000", "1.2.3.4:40000"),
+            mapping("10.0.0.2:5000", "1.2.3.4:40001"),
+        ]);
+        assert_eq!((dependent.mapping, dependent.confident), (NatMapping::AddressPortDependent, true));
+
+        let preserving = NatEstimate::from_mappings(&[
+            mapping("10.0.0.2:5000", "1.2.3.4:5000"),
+            mapping("10.0.0.2:5001", "1.2.3.4:5001"),
+        ]);
+        assert_eq!(preserving.mapping, NatMapping::EndpointIndependent);
+        assert!(preserving.port_preserving && !preserving.confident);
+
+        let random = NatEstimate::from_mappings(&[
+            mapping("10.0.0.2:5000", "1.2.3.4:40000"),
+            mapping("10.0.0.2:5001", "1.2.3.4:52817"),
+        ]);
+        assert_eq!(random.mapping, NatMapping::AddressPortDependent);
+        assert!(!random.port_preserving);
+    }
+}
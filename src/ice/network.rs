@@ -0,0 +1,25 @@
+//! Following the network a mobile operating system considers active, see
+//! [Agent::set_active_network](crate::ice::Agent::set_active_network).
+use std::net::IpAddr;
+
+/// A network of the device as reported by the platform, e.g. an Android `Network` or an iOS
+/// `nw_interface`.
+///
+/// libnice knows nothing about platform networks, so the application resolves the network to
+/// the addresses of its interface (e.g. via `LinkProperties` on Android) and passes its handle
+/// along to identify it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlatformNetwork {
+    /// The platform-provided handle or interface identifier, e.g. `Network.getNetworkHandle()`
+    /// on Android or the interface index on iOS.
+    pub handle: u64,
+    /// The local addresses of the network's interface.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl PlatformNetwork {
+    /// Creates a network from its handle and the addresses of its interface.
+    pub fn new(handle: u64, addresses: Vec<IpAddr>) -> Self {
+        Self { handle, addresses }
+    }
+}
@@ -0,0 +1,90 @@
+//! Per-component queues of outbound packets, drained round-robin by the [Agent](crate::ice::Agent)
+//! so a component sending at a high rate cannot starve the others.
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_uint;
+
+type ComponentId = (c_uint, c_uint);
+
+#[derive(Default)]
+pub(crate) struct OutboundQueues {
+    queues: HashMap<ComponentId, VecDeque<Vec<u8>>>,
+    /// Components with queued packets, in the order they are served next.
+    order: VecDeque<ComponentId>,
+}
+
+impl OutboundQueues {
+    pub(crate) fn push(&mut self, key: ComponentId, packet: Vec<u8>) {
+        let queue = self.queues.entry(key).or_default();
+        if queue.is_empty() {
+            self.order.push_back(key);
+        }
+        queue.push_back(packet);
+    }
+
+    /// Returns the next packet of the next component in turn.
+    pub(crate) fn pop(&mut self) -> Option<(ComponentId, Vec<u8>)> {
+        let key = self.order.pop_front()?;
+        let queue = self.queues.get_mut(&key).expect("queue of scheduled component");
+        let packet = queue.pop_front().expect("scheduled component has packets");
+        if queue.is_empty() {
+            self.queues.remove(&key);
+        } else {
+            self.order.push_back(key);
+        }
+        Some((key, packet))
+    }
+
+    /// Removes and returns all packets queued for the components of a stream.
+    pub(crate) fn take_stream(&mut self, stream_id: c_uint) -> Vec<(ComponentId, Vec<u8>)> {
+        self.order.retain(|(queue_stream_id, _)| *queue_stream_id != stream_id);
+        let keys = self
+            .queues
+            .keys()
+            .filter(|(queue_stream_id, _)| *queue_stream_id == stream_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut packets = Vec::new();
+        for key in keys {
+            let queue = self.queues.remove(&key).unwrap_or_default();
+            packets.extend(queue.into_iter().map(|packet| (key, packet)));
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serves_components_round_robin() {
+        let mut queues = OutboundQueues::default();
+        for i in 0..3u8 {
+            queues.push((1, 1), vec![i]);
+        }
+        queues.push((1, 2), vec![10]);
+        queues.push((2, 1), vec![20]);
+
+        let order = std::iter::from_fn(|| queues.pop()).collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec![
+                ((1, 1), vec![0]),
+                ((1, 2), vec![10]),
+                ((2, 1), vec![20]),
+                ((1, 1), vec![1]),
+                ((1, 1), vec![2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn takes_packets_of_stream() {
+        let mut queues = OutboundQueues::default();
+        queues.push((1, 1), vec![0]);
+        queues.push((2, 1), vec![1]);
+        assert_eq!(queues.take_stream(1), vec![((1, 1), vec![0])]);
+        assert_eq!(queues.pop(), Some(((2, 1), vec![1])));
+        assert_eq!(queues.pop(), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Pacing of outbound packets, so bursts of packets (e.g. all packets of a video frame) do not
+//! overflow the small buffers of consumer-grade routers.
+use crate::ice::{ComponentWriter, SendError};
+use crate::timer::{Timer, TimerSource};
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often queued packets are checked in [PacingMode::RtpTimestamp].
+const RTP_TICK: Duration = Duration::from_millis(2);
+
+/// The largest jump of an RTP timestamp which is paced, larger jumps (e.g. after a pause or a
+/// timestamp reset) restart the pacing of that SSRC.
+const MAX_RTP_DELAY: Duration = Duration::from_secs(1);
+
+/// How a [PacedWriter] spaces its packets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PacingMode {
+    /// Sends at most one packet per interval.
+    Interval(Duration),
+    /// Sends RTP packets at the time given by their timestamp relative to the first packet of
+    /// their SSRC, at the given clock rate (e.g. `48000` for Opus, `90000` for video).
+    ///
+    /// Packets which are not RTP (e.g. RTCP or DTLS) are sent right away.
+    RtpTimestamp {
+        /// The RTP clock rate in Hz.
+        clock_rate: u32,
+    },
+}
+
+/// The pacing state of an SSRC in [PacingMode::RtpTimestamp].
+struct RtpClock {
+    base: (Instant, u32),
+    last_due: Instant,
+}
+
+/// Schedules packets according to a [PacingMode].
+pub(crate) struct Pacer {
+    mode: PacingMode,
+    /// The packets not yet sent with the time they are due, in the order they were queued.
+    queue: Vec<(Instant, Vec<u8>)>,
+    last_due: Option<Instant>,
+    clocks: HashMap<u32, RtpClock>,
+}
+
+/// Returns the SSRC and timestamp of an RTP packet, `None` for anything else, including RTCP
+/// (see RFC 5761).
+fn rtp_header(packet: &[u8]) -> Option<(u32, u32)> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 || (192..=223).contains(&packet[1]) {
+        return None;
+    }
+    let field = |at: usize| u32::from_be_bytes([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]]);
+    Some((field(8), field(4)))
+}
+
+impl Pacer {
+    pub(crate) fn new(mode: PacingMode) -> Self {
+        Self {
+            mode,
+            queue: Vec::new(),
+            last_due: None,
+            clocks: HashMap::new(),
+        }
+    }
+
+    /// How often [Pacer::take_due] should be called.
+    pub(crate) fn tick(&self) -> Duration {
+        match self.mode {
+            PacingMode::Interval(interval) => interval.max(Duration::from_millis(1)),
+            PacingMode::RtpTimestamp { .. } => RTP_TICK,
+        }
+    }
+
+    fn due(&mut self, packet: &[u8], now: Instant) -> Instant {
+        match self.mode {
+            PacingMode::Interval(interval) => {
+                let due = self.last_due.map_or(now, |last| (last + interval).max(now));
+                self.last_due = Some(due);
+                due
+            }
+            PacingMode::RtpTimestamp { clock_rate } => {
+                let (ssrc, timestamp) = match rtp_header(packet) {
+                    Some(header) => header,
+                    None => return now,
+                };
+                let clock = self.clocks.entry(ssrc).or_insert(RtpClock {
+                    base: (now, timestamp),
+                    last_due: now,
+                });
+                let (base_time, base_timestamp) = clock.base;
+                let ticks = timestamp.wrapping_sub(base_timestamp) as i32;
+                let offset = Duration::from_secs_f64(f64::from(ticks.max(0)) / f64::from(clock_rate.max(1)));
+                let due = base_time + offset;
+                if ticks < 0 || due > now + MAX_RTP_DELAY || due + MAX_RTP_DELAY < now {
+                    clock.base = (now, timestamp);
+                    clock.last_due = clock.last_due.max(now);
+                    return clock.last_due;
+                }
+                clock.last_due = clock.last_due.max(due);
+                clock.last_due
+            }
+        }
+    }
+
+    /// Queues a packet, returns when it is due.
+    pub(crate) fn push(&mut self, packet: Vec<u8>, now: Instant) -> Instant {
+        let due = self.due(&packet, now);
+        self.queue.push((due, packet));
+        due
+    }
+
+    /// Removes and returns the packets due at `now`, in the order they were queued.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let (due, pending) = std::mem::replace(&mut self.queue, Vec::new())
+            .into_iter()
+            .partition::<Vec<_>, _>(|(due, _)| *due <= now);
+        self.queue = pending;
+        due.into_iter().map(|(_, packet)| packet).collect()
+    }
+
+    /// Removes and returns all queued packets.
+    pub(crate) fn take_all(&mut self) -> Vec<Vec<u8>> {
+        self.queue.drain(..).map(|(_, packet)| packet).collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// The number of packets a [PacedWriter] queues by default, see [PacedWriter::set_max_queued].
+const DEFAULT_MAX_QUEUED: usize = 1024;
+
+/// The state shared between a [PacedWriter] and its timer.
+struct PacedQueue {
+    pacer: Pacer,
+    max_queued: usize,
+    /// The first error of sending a due packet from the timer, reported by the next write.
+    error: Option<SendError>,
+}
+
+impl PacedQueue {
+    fn take_error(&mut self) -> Result<(), SendError> {
+        match self.error {
+            // Closed components fail every write anyway
+            Some(err) if err.is_closed() => Err(err),
+            _ => self.error.take().map_or(Ok(()), Err),
+        }
+    }
+}
+
+/// Sends packets taken from the queue, whose memory is released first since sending reserves
+/// it again. Stops at the first packet which cannot be sent because the component is closed.
+fn send_queued(writer: &ComponentWriter, packets: Vec<Vec<u8>>) -> Result<(), SendError> {
+    let mut result = Ok(());
+    let mut packets = packets.into_iter();
+    for packet in &mut packets {
+        writer.shared.memory.release_outbound(packet.len());
+        match writer.shared.send(&writer.data_sink, packet) {
+            Ok(()) => {}
+            Err(err) if err.is_closed() => {
+                result = Err(err);
+                break;
+            }
+            Err(err) => {
+                result = result.and(Err(err));
+            }
+        }
+    }
+    for packet in packets {
+        writer.shared.memory.release_outbound(packet.len());
+    }
+    result
+}
+
+/// A writer for a component which spaces its packets according to a [PacingMode], see
+/// [StreamComponent::paced_writer](crate::ice::StreamComponent::paced_writer).
+///
+/// Each call to [Write::write] queues one packet, which is sent once it is due (packets due
+/// right away are sent by the call itself). Queued packets count towards the memory limit of the
+/// agent and at most [PacedWriter::set_max_queued] packets are queued, writes beyond either
+/// limit fail with [SendError::QueueFull]. Errors of sending packets once they are due are
+/// reported by the next write or flush. Packets still queued when the writer is dropped are sent
+/// right away.
+pub struct PacedWriter {
+    writer: ComponentWriter,
+    queue: Arc<Mutex<PacedQueue>>,
+    _timer: Timer,
+}
+
+impl PacedWriter {
+    pub(crate) fn new(writer: ComponentWriter, timers: &dyn TimerSource, mode: PacingMode) -> Self {
+        let pacer = Pacer::new(mode);
+        let tick = pacer.tick();
+        let queue = Arc::new(Mutex::new(PacedQueue {
+            pacer,
+            max_queued: DEFAULT_MAX_QUEUED,
+            error: None,
+        }));
+        let timer_writer = writer.clone();
+        let weak_queue = Arc::downgrade(&queue);
+        let timer = timers.start(tick, Box::new(move || {
+            let queue = match weak_queue.upgrade() {
+                Some(queue) => queue,
+                None => return false,
+            };
+            let mut queue = queue.lock().unwrap();
+            let packets = queue.pacer.take_due(Instant::now());
+            match send_queued(&timer_writer, packets) {
+                Ok(()) => true,
+                Err(err) => {
+                    queue.error.get_or_insert(err);
+                    !err.is_closed()
+                }
+            }
+        }));
+        Self {
+            writer,
+            queue,
+            _timer: timer,
+        }
+    }
+
+    /// Returns the number of packets queued but not yet due.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().pacer.len()
+    }
+
+    /// Sets how many packets may be queued at most (default `1024`).
+    pub fn set_max_queued(&mut self, max_queued: usize) {
+        self.queue.lock().unwrap().max_queued = max_queued;
+    }
+}
+
+impl Write for PacedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.shared.check_sendable(&self.writer.data_sink).map_err(io::Error::from)?;
+        let mut queue = self.queue.lock().unwrap();
+        queue.take_error().map_err(io::Error::from)?;
+        if queue.pacer.len() >= queue.max_queued || !self.writer.shared.memory.try_reserve_outbound(buf.len()) {
+            return Err(SendError::QueueFull.into());
+        }
+        let now = Instant::now();
+        queue.pacer.push(Vec::from(buf), now);
+        let due = queue.pacer.take_due(now);
+        send_queued(&self.writer, due).map_err(io::Error::from)?;
+        Ok(buf.len())
+    }
+
+    /// Sends nothing, as queued packets are not due yet, but reports errors of sending the
+    /// packets which were.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.take_error().map_err(io::Error::from)
+    }
+}
+
+impl Drop for PacedWriter {
+    fn drop(&mut self) {
+        let packets = self.queue.lock().unwrap().pacer.take_all();
+        let _ = send_queued(&self.writer, packets);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rtp(ssrc: u32, timestamp: u32) -> Vec<u8> {
+        let mut packet = vec![0x80, 111, 0, 0];
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn paces_by_rtp_timestamp() {
+        let now = Instant::now();
+        let mut pacer = Pacer::new(PacingMode::RtpTimestamp { clock_rate: 48000 });
+        assert_eq!(pacer.push(rtp(1, 1000), now), now);
+        assert_eq!(pacer.push(rtp(1, 1960), now), now + Duration::from_millis(20));
+        assert_eq!(pacer.push(rtp(2, 5), now), now);
+        assert_eq!(pacer.push(vec![0x80, 200, 0, 1], now), now);
+        // A jump of more than a second restarts the pacing of the SSRC
+        assert_eq!(pacer.push(rtp(1, 1000 + 48000 * 5), now), now + Duration::from_millis(20));
+
+        assert_eq!(pacer.take_due(now).len(), 3);
+        assert_eq!(pacer.take_due(now + Duration::from_millis(20)), vec![rtp(1, 1960), rtp(1, 241_000)]);
+    }
+
+    #[test]
+    fn paces_by_interval() {
+        let now = Instant::now();
+        let mut pacer = Pacer::new(PacingMode::Interval(Duration::from_millis(5)));
+        assert_eq!(pacer.push(vec![1], now), now);
+        assert_eq!(pacer.push(vec![2], now), now + Duration::from_millis(5));
+        assert_eq!(pacer.push(vec![3], now + Duration::from_millis(20)), now + Duration::from_millis(20));
+        assert_eq!(pacer.take_due(now + Duration::from_millis(5)), vec![vec![1], vec![2]]);
+    }
+}
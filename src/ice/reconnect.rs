@@ -0,0 +1,204 @@
+//! Reacting to components which lost their connection, see
+//! [StreamComponent::set_disconnect_policy](crate::ice::StreamComponent::set_disconnect_policy).
+use crate::ffi;
+use crate::ice::{AgentEvent, ComponentShared, ComponentState, StateTransition};
+use crate::timer::{Timer, TimerSource};
+use std::os::raw::c_uint;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// What a component does once it drops from `Connected` or `Ready` to `Disconnected` or
+/// `Failed`, e.g. after a transient loss of Wi-Fi.
+///
+/// Either way [AgentEvent::ComponentReconnected] is emitted once the component connects again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// Nothing is done, the application watches the component state itself (the default).
+    Ignore,
+    /// Passes the remote candidates to libnice again right after the loss has been delivered
+    /// and then every `interval`, which schedules connectivity checks for their pairs, until the
+    /// component is connected again. Emits [AgentEvent::NeedsRestart] if it is still
+    /// disconnected after `give_up_after`.
+    ///
+    /// Combine with [Agent::set_keepalive_conncheck](crate::ice::Agent::set_keepalive_conncheck)
+    /// to notice the loss of the connection early.
+    Recheck {
+        /// How often the pairs are checked again.
+        interval: Duration,
+        /// How long to recheck before recommending an ICE restart.
+        give_up_after: Duration,
+    },
+    /// Emits [AgentEvent::NeedsRestart] if the component is still disconnected after `grace`.
+    RecommendRestart {
+        /// How long to wait for libnice to recover on its own.
+        grace: Duration,
+    },
+}
+
+impl Default for DisconnectPolicy {
+    fn default() -> Self {
+        DisconnectPolicy::Ignore
+    }
+}
+
+fn is_connected(state: ComponentState) -> bool {
+    match state {
+        ComponentState::Connected | ComponentState::Ready => true,
+        _ => false,
+    }
+}
+
+/// Returns whether the component lost its connection with this transition.
+fn is_loss(transition: &StateTransition) -> bool {
+    is_connected(transition.previous)
+        && (transition.state == ComponentState::Disconnected || transition.state == ComponentState::Failed)
+}
+
+/// The policy of a component and the outage it is currently handling.
+#[derive(Default)]
+pub(super) struct DisconnectWatch {
+    policy: DisconnectPolicy,
+    /// The agent and timers to handle outages with, `None` for [DisconnectPolicy::Ignore].
+    handles: Option<(ffi::NiceAgent, Arc<dyn TimerSource>)>,
+    lost_at: Option<Instant>,
+    timer: Option<Timer>,
+    /// Runs the first recheck of [DisconnectPolicy::Recheck] on the next iteration.
+    first_recheck: Option<Timer>,
+}
+
+impl DisconnectWatch {
+    /// Replaces the policy, abandoning the handling of a current outage.
+    pub(super) fn set_policy(&mut self, policy: DisconnectPolicy, agent: ffi::NiceAgent, timers: Arc<dyn TimerSource>) {
+        self.handles = match policy {
+            DisconnectPolicy::Ignore => None,
+            _ => Some((agent, timers)),
+        };
+        self.policy = policy;
+        self.lost_at = None;
+        self.timer = None;
+        self.first_recheck = None;
+    }
+
+    pub(super) fn policy(&self) -> DisconnectPolicy {
+        self.policy
+    }
+
+    /// Stops handling the current outage, e.g. because the component has been closed.
+    pub(super) fn stop(&mut self) {
+        self.handles = None;
+        self.lost_at = None;
+        self.timer = None;
+        self.first_recheck = None;
+    }
+}
+
+fn recheck(agent: &ffi::NiceAgent, stream_id: c_uint, component_id: c_uint) {
+    let candidates = agent.get_remote_candidates(stream_id, component_id);
+    let candidates = candidates.iter().collect::<Vec<_>>();
+    if !candidates.is_empty() {
+        let _ = agent.add_remote_candidates(stream_id, component_id, &candidates);
+    }
+}
+
+fn emit_needs_restart(shared: &ComponentShared, lost_at: Instant) {
+    shared.events.emit(AgentEvent::NeedsRestart {
+        stream_id: shared.stream_id,
+        component_id: shared.component_id,
+        disconnected_for: lost_at.elapsed(),
+    });
+}
+
+/// Called for every state change of a component reported by libnice.
+pub(super) fn on_transition(shared: &Arc<ComponentShared>, transition: StateTransition) {
+    let mut watch = shared.disconnect.lock().unwrap();
+    let (agent, timers) = match &watch.handles {
+        Some(handles) => handles.clone(),
+        None => return,
+    };
+    if is_connected(transition.state) {
+        if let Some(lost_at) = watch.lost_at.take() {
+            watch.timer = None;
+            watch.first_recheck = None;
+            shared.events.emit(AgentEvent::ComponentReconnected {
+                stream_id: shared.stream_id,
+                component_id: shared.component_id,
+                outage: transition.timestamp.saturating_duration_since(lost_at),
+            });
+        }
+        return;
+    }
+    if !is_loss(&transition) || watch.lost_at.is_some() {
+        return;
+    }
+    let lost_at = transition.timestamp;
+    watch.lost_at = Some(lost_at);
+
+    let weak_shared: Weak<ComponentShared> = Arc::downgrade(shared);
+    // Whether the outage the timer has been started for is still being handled
+    let still_lost = move || {
+        let shared = weak_shared.upgrade()?;
+        let ongoing = shared.disconnect.lock().unwrap().lost_at == Some(lost_at);
+        if ongoing {
+            Some(shared)
+        } else {
+            None
+        }
+    };
+    let timer = match watch.policy {
+        DisconnectPolicy::Ignore => return,
+        DisconnectPolicy::Recheck { interval, give_up_after } => {
+            // libnice reports the state changes caused by the new candidates synchronously, so
+            // rechecking right away would deliver them before the loss currently being reported
+            let first_agent = agent.clone();
+            let first_still_lost = still_lost.clone();
+            watch.first_recheck = Some(timers.start(Duration::from_secs(0), Box::new(move || {
+                if let Some(shared) = first_still_lost() {
+                    recheck(&first_agent, shared.stream_id, shared.component_id);
+                }
+                false
+            })));
+            timers.start(interval, Box::new(move || {
+                let shared = match still_lost() {
+                    Some(shared) => shared,
+                    None => return false,
+                };
+                if lost_at.elapsed() >= give_up_after {
+                    emit_needs_restart(&shared, lost_at);
+                    return false;
+                }
+                recheck(&agent, shared.stream_id, shared.component_id);
+                true
+            }))
+        }
+        DisconnectPolicy::RecommendRestart { grace } => timers.start(grace, Box::new(move || {
+            if let Some(shared) = still_lost() {
+                emit_needs_restart(&shared, lost_at);
+            }
+            false
+        })),
+    };
+    watch.timer = Some(timer);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transition(previous: ComponentState, state: ComponentState) -> StateTransition {
+        StateTransition {
+            previous,
+            state,
+            timestamp: Instant::now(),
+            elapsed: Duration::from_secs(0),
+            failure: None,
+        }
+    }
+
+    #[test]
+    fn detects_loss_of_connection() {
+        assert!(is_loss(&transition(ComponentState::Ready, ComponentState::Disconnected)));
+        assert!(is_loss(&transition(ComponentState::Connected, ComponentState::Failed)));
+        assert!(!is_loss(&transition(ComponentState::Connecting, ComponentState::Failed)));
+        assert!(!is_loss(&transition(ComponentState::Ready, ComponentState::Connected)));
+    }
+}
@@ -0,0 +1,93 @@
+//! Applying updated remote descriptions, see
+//! [Stream::update_remote_description](crate::ice::Stream::update_remote_description).
+use crate::ice::Candidate;
+use crate::sdp::SessionDescription;
+
+/// What [Stream::update_remote_description](crate::ice::Stream::update_remote_description)
+/// applied.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RemoteDescriptionUpdate {
+    /// The remote credentials changed, so the stream has been restarted. The new local
+    /// credentials (see [Stream::get_local_credentials](crate::ice::Stream::get_local_credentials))
+    /// must be sent to the remote peer.
+    pub ice_restart: bool,
+    /// The number of candidates which have been added.
+    pub added_candidates: usize,
+    /// Whether end-of-candidates has been signaled by this update.
+    pub end_of_candidates: bool,
+}
+
+/// The changes of a remote description compared to the previously applied one.
+pub(crate) enum DescriptionDiff {
+    /// Nothing has been applied yet (`restart == false`) or the credentials changed, the whole
+    /// description needs to be applied.
+    Full { restart: bool },
+    /// Same credentials, only the new candidates and a new end-of-candidates need to be applied.
+    Incremental {
+        new_candidates: Vec<Candidate>,
+        end_of_candidates: bool,
+    },
+}
+
+pub(crate) fn diff(previous: Option<&SessionDescription>, new: &SessionDescription) -> DescriptionDiff {
+    let previous = match previous {
+        Some(previous) if previous.credentials == new.credentials => previous,
+        previous => return DescriptionDiff::Full { restart: previous.is_some() },
+    };
+    // Candidates do not implement Eq, their SDP representation identifies them
+    let known = previous.candidates.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let new_candidates = new
+        .candidates
+        .iter()
+        .filter(|candidate| !known.contains(&candidate.to_string()))
+        .cloned()
+        .collect();
+    DescriptionDiff::Incremental {
+        new_candidates,
+        end_of_candidates: new.end_of_candidates && !previous.end_of_candidates,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ice::CandidateType;
+    use crate::sdp::{parse_candidate, IceCredentials, IceOptions, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_SCTP_PORT};
+
+    fn description(ufrag: &str, candidates: &[&str], end_of_candidates: bool) -> SessionDescription {
+        let candidates = candidates.iter().map(|line| parse_candidate(line)).collect();
+        SessionDescription {
+            credentials: IceCredentials { ufrag: ufrag.to_owned(), pwd: "0123456789abcdefghijkl".to_owned() },
+            options: IceOptions::default(),
+            fingerprint: None,
+            setup: None,
+            candidates,
+            end_of_candidates,
+            ice_lite: false,
+            sctp_port: DEFAULT_SCTP_PORT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    #[test]
+    fn diffs_descriptions() {
+        let host = "candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host";
+        let srflx = "candidate:2 1 UDP 1694498815 1.2.3.4 50000 typ srflx raddr 192.168.1.2 rport 50000";
+        let first = description("abcd", &[host], false);
+        assert!(matches!(diff(None, &first), DescriptionDiff::Full { restart: false }));
+
+        match diff(Some(&first), &description("abcd", &[host, srflx], true)) {
+            DescriptionDiff::Incremental { new_candidates, end_of_candidates } => {
+                assert_eq!(new_candidates.len(), 1);
+                assert_eq!(new_candidates[0].c_type, CandidateType::Srflx);
+                assert!(end_of_candidates);
+            }
+            DescriptionDiff::Full { .. } => panic!("credentials did not change"),
+        }
+
+        assert!(matches!(
+            diff(Some(&first), &description("efgh", &[host], false)),
+            DescriptionDiff::Full { restart: true }
+        ));
+    }
+}
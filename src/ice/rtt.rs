@@ -0,0 +1,152 @@
+//! Round trip time measurement over a connected component.
+//!
+//! libnice neither exposes the round trip times of its connectivity checks nor passes STUN
+//! responses on to the application, so RTT probes are sent as (tiny) application packets instead.
+//! Their first byte is `0xff`, which lies outside of the ranges used by STUN, DTLS, TURN channels
+//! and RTP/RTCP (see [RFC 7983]), so they can be demultiplexed from most protocols.
+//!
+//! Probes are opt-in per stream (see
+//! [StreamBuilder::set_rtt_probes](crate::ice::StreamBuilder::set_rtt_probes)). Only then are
+//! probes sent, and inbound probes answered and withheld from the application; otherwise all
+//! packets are passed on unchanged. Other ICE implementations neither answer nor recognize
+//! probes, so both peers must run this crate and agree on them, e.g. via signaling.
+//!
+//! [RFC 7983]: https://tools.ietf.org/html/rfc7983
+use crate::ffi;
+use crate::timer::TimerSource;
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use std::future::Future;
+use std::os::raw::c_uint;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+const PROBE_MAGIC: [u8; 4] = [0xff, b'R', b'T', b'T'];
+const PROBE_REQUEST: u8 = 0;
+const PROBE_RESPONSE: u8 = 1;
+const PROBE_LEN: usize = 9;
+
+fn encode_probe(kind: u8, id: u32) -> [u8; PROBE_LEN] {
+    let mut probe = [0u8; PROBE_LEN];
+    probe[..4].copy_from_slice(&PROBE_MAGIC);
+    probe[4] = kind;
+    probe[5..].copy_from_slice(&id.to_be_bytes());
+    probe
+}
+
+fn parse_probe(packet: &[u8]) -> Option<(u8, u32)> {
+    if packet.len() != PROBE_LEN || packet[..4] != PROBE_MAGIC {
+        return None;
+    }
+    let mut id = [0u8; 4];
+    id.copy_from_slice(&packet[5..]);
+    Some((packet[4], u32::from_be_bytes(id)))
+}
+
+/// The RTT probes of a single component which are still awaiting a response.
+#[derive(Default)]
+pub(crate) struct RttProbes {
+    enabled: AtomicBool,
+    next_id: AtomicU32,
+    pending: Mutex<HashMap<u32, (Instant, oneshot::Sender<Duration>)>>,
+}
+
+impl RttProbes {
+    /// Enables probes, which both peers must have agreed on.
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Handles an inbound packet, answering probe requests and completing pending probes.
+    ///
+    /// Returns whether the packet was a probe, in which case it must not be passed on. Always
+    /// `false` unless probes are enabled.
+    pub(crate) fn handle_packet(
+        &self,
+        agent: &ffi::NiceAgent,
+        stream_id: c_uint,
+        component_id: c_uint,
+        packet: &[u8],
+    ) -> bool {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return false;
+        }
+        let (kind, id) = match parse_probe(packet) {
+            Some(probe) => probe,
+            None => return false,
+        };
+        if kind == PROBE_REQUEST {
+            let _ = agent.send(stream_id, component_id, &encode_probe(PROBE_RESPONSE, id));
+        } else if let Some((sent_at, sender)) = self.pending.lock().unwrap().remove(&id) {
+            let _ = sender.send(sent_at.elapsed());
+        }
+        true
+    }
+
+    /// Sends a new probe, giving up on it after `timeout` (or right away if probes are disabled).
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        agent: &ffi::NiceAgent,
+        timers: &dyn TimerSource,
+        stream_id: c_uint,
+        component_id: c_uint,
+        timeout: Duration,
+    ) -> RttFuture {
+        let (sender, receiver) = oneshot::channel();
+        if !self.enabled.load(Ordering::Relaxed) {
+            return RttFuture { receiver };
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.pending.lock().unwrap().insert(id, (Instant::now(), sender));
+
+        let probes = Arc::clone(self);
+        timers
+            .start(timeout, Box::new(move || {
+                probes.pending.lock().unwrap().remove(&id);
+                false
+            }))
+            .detach();
+
+        if agent.send(stream_id, component_id, &encode_probe(PROBE_REQUEST, id)).is_none() {
+            self.pending.lock().unwrap().remove(&id);
+        }
+        RttFuture { receiver }
+    }
+}
+
+/// Future returned by [StreamComponent::measure_rtt].
+///
+/// Resolves to `None` if no response has been received in time.
+///
+/// [StreamComponent::measure_rtt]: crate::ice::StreamComponent::measure_rtt
+pub struct RttFuture {
+    receiver: oneshot::Receiver<Duration>,
+}
+
+impl Future for RttFuture {
+    type Output = Option<Duration>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(rtt)) => Poll::Ready(Some(rtt)),
+            Poll::Ready(Err(_)) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn probe_round_trip() {
+        let probe = encode_probe(PROBE_RESPONSE, 0xdead_beef);
+        assert_eq!(parse_probe(&probe), Some((PROBE_RESPONSE, 0xdead_beef)));
+        assert_eq!(parse_probe(&probe[1..]), None);
+        assert_eq!(parse_probe(&[0u8; PROBE_LEN]), None);
+    }
+}
@@ -0,0 +1,64 @@
+//! The error returned when a packet cannot be queued for sending via a component.
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a packet could not be queued for sending, e.g. via the
+/// [Sink](futures::Sink) implementation of [StreamComponent](crate::ice::StreamComponent).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SendError {
+    /// The [Agent](crate::ice::Agent) has been dropped. Nothing can be sent anymore.
+    AgentClosed,
+    /// The stream of the component has been removed (see
+    /// [Agent::remove_stream](crate::ice::Agent::remove_stream)) or the component has been
+    /// dropped (see [Stream::drop_component](crate::ice::Stream::drop_component)) or closed (see
+    /// [AsyncWrite::poll_close](futures::io::AsyncWrite::poll_close)).
+    /// Nothing can be sent via this component anymore.
+    StreamClosed,
+    /// The outbound queue is full, i.e. the memory limit of the agent has been reached (see
+    /// [Agent::set_memory_limit](crate::ice::Agent::set_memory_limit)) or the queue of a
+    /// [PacedWriter](crate::ice::PacedWriter) is full. Sending may succeed again once queued
+    /// packets have been processed.
+    QueueFull,
+    /// The [PacketTransform](crate::ice::PacketTransform) of the component failed to transform
+    /// the packet, with the kind of the error it returned.
+    TransformFailed(io::ErrorKind),
+}
+
+impl SendError {
+    /// Returns whether nothing can be sent anymore, as opposed to a temporary failure.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, SendError::AgentClosed | SendError::StreamClosed)
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::AgentClosed => f.write_str("agent has been closed"),
+            SendError::StreamClosed => f.write_str("stream has been closed"),
+            SendError::QueueFull => f.write_str("outbound queue full"),
+            SendError::TransformFailed(kind) => write!(f, "packet transform failed: {:?}", kind),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+impl From<SendError> for glib::BoolError {
+    fn from(err: SendError) -> Self {
+        glib_bool_error!("{}", err)
+    }
+}
+
+impl From<SendError> for io::Error {
+    fn from(err: SendError) -> Self {
+        let kind = match err {
+            SendError::AgentClosed | SendError::StreamClosed => io::ErrorKind::BrokenPipe,
+            SendError::QueueFull => io::ErrorKind::Other,
+            SendError::TransformFailed(kind) => kind,
+        };
+        io::Error::new(kind, err)
+    }
+}
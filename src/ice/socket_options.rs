@@ -0,0 +1,49 @@
+//! Options for the UDP sockets libnice creates for a component, see
+//! [StreamBuilder::set_socket_options](crate::ice::StreamBuilder::set_socket_options).
+use crate::platform;
+use std::convert::TryFrom;
+use std::io;
+use std::os::raw::c_int;
+
+/// Socket options applied to the sockets of a component, mostly relevant for high-throughput
+/// UDP where the operating system defaults cause packet loss.
+///
+/// Options which are `None` are left at the defaults of the operating system.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// The size of the receive buffer (`SO_RCVBUF`) in bytes.
+    pub recv_buffer_size: Option<usize>,
+    /// The size of the send buffer (`SO_SNDBUF`) in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// Windows only (ignored elsewhere): whether an ICMP port unreachable message for a sent
+    /// packet makes the next receive fail with `WSAECONNRESET` (`SIO_UDP_CONNRESET`).
+    ///
+    /// Windows enables this by default, so packets sent to a remote candidate which is not
+    /// (or no longer) reachable make libnice drop received packets. Set to `Some(false)` to
+    /// avoid this.
+    pub udp_connreset: Option<bool>,
+}
+
+impl SocketOptions {
+    /// Applies the options to a native socket handle, as returned by
+    /// [StreamComponent::native_sockets](crate::ice::StreamComponent::native_sockets).
+    pub(crate) fn apply(&self, socket: c_int) -> io::Result<()> {
+        let buffer_sizes = [
+            (platform::SO_RCVBUF, self.recv_buffer_size),
+            (platform::SO_SNDBUF, self.send_buffer_size),
+        ];
+        for (option, size) in buffer_sizes.iter() {
+            if let Some(size) = size {
+                let size = c_int::try_from(*size).unwrap_or(c_int::MAX);
+                platform::set_socket_option(socket, *option, size)?;
+            }
+        }
+        #[cfg(windows)]
+        {
+            if let Some(enabled) = self.udp_connreset {
+                platform::set_udp_connreset(socket, enabled)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! The bounded queue delivering state transitions to a component.
+//!
+//! libnice reports state changes on the main context, which must never block on a slow
+//! component. Instead of dropping arbitrary updates once the queue is full, the newest queued
+//! transition is coalesced with the incoming one, so the latest state is always delivered and
+//! the `previous` states of the delivered transitions still form a chain.
+use crate::ice::StateTransition;
+use futures::Stream as FuturesStream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+struct Shared {
+    queue: VecDeque<StateTransition>,
+    capacity: usize,
+    waker: Option<Waker>,
+    /// No more transitions will be pushed.
+    closed: bool,
+    /// The receiver has been dropped.
+    receiver_gone: bool,
+}
+
+pub(crate) fn channel(capacity: usize) -> (StateSender, StateReceiver) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        capacity: capacity.max(1),
+        waker: None,
+        closed: false,
+        receiver_gone: false,
+    }));
+    (StateSender(shared.clone()), StateReceiver(shared))
+}
+
+pub(crate) struct StateSender(Arc<Mutex<Shared>>);
+
+impl StateSender {
+    /// Queues a transition, failing if the receiver has been dropped.
+    pub(crate) fn push(&self, transition: StateTransition) -> Result<(), ()> {
+        let mut shared = self.0.lock().unwrap();
+        if shared.receiver_gone {
+            return Err(());
+        }
+        if shared.queue.len() >= shared.capacity {
+            let newest = shared.queue.pop_back().expect("capacity is at least one");
+            if newest.previous != transition.state {
+                shared.queue.push_back(StateTransition { previous: newest.previous, ..transition });
+            }
+        } else {
+            shared.queue.push_back(transition);
+        }
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Ends the receiver once it has received all queued transitions.
+    pub(crate) fn close(&self) {
+        let mut shared = self.0.lock().unwrap();
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for StateSender {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+pub(crate) struct StateReceiver(Arc<Mutex<Shared>>);
+
+impl FuturesStream for StateReceiver {
+    type Item = StateTransition;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut shared = self.0.lock().unwrap();
+        if let Some(transition) = shared.queue.pop_front() {
+            return Poll::Ready(Some(transition));
+        }
+        if shared.closed {
+            return Poll::Ready(None);
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for StateReceiver {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().receiver_gone = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ice::ComponentState;
+    use futures::executor::block_on_stream;
+    use std::time::{Duration, Instant};
+
+    fn transition(previous: ComponentState, state: ComponentState) -> StateTransition {
+        StateTransition { previous, state, timestamp: Instant::now(), elapsed: Duration::from_secs(0), failure: None }
+    }
+
+    #[test]
+    fn coalesces_newest_when_full() {
+        use ComponentState::*;
+        let (sender, receiver) = channel(2);
+        sender.push(transition(Disconnected, Gathering)).unwrap();
+        sender.push(transition(Gathering, Connecting)).unwrap();
+        sender.push(transition(Connecting, Connected)).unwrap();
+        sender.push(transition(Connected, Ready)).unwrap();
+        drop(sender);
+
+        let received = block_on_stream(receiver)
+            .map(|transition| (transition.previous, transition.state))
+            .collect::<Vec<_>>();
+        assert_eq!(received, vec![(Disconnected, Gathering), (Gathering, Ready)]);
+    }
+}
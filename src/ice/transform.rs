@@ -0,0 +1,32 @@
+//! Transparent transformation of the packets of a component, e.g. SRTP protection.
+//!
+//! This crate implements neither DTLS nor SRTP. Once the DTLS handshake over a component has
+//! completed and the SRTP keys have been exported, an SRTP context (e.g. of the `srtp` crate) can
+//! be installed as a [PacketTransform] via
+//! [StreamComponent::set_packet_transform](crate::ice::StreamComponent::set_packet_transform),
+//! after which RTP/RTCP packets are protected and unprotected transparently.
+use std::io;
+
+/// Transforms all packets sent and received via a component.
+///
+/// The transform sees every packet of the component, including DTLS or STUN packets multiplexed
+/// on it, so it must pass through the packets it does not handle (see [RFC 7983] for
+/// demultiplexing by the first byte). RTT probes, if enabled (see
+/// [StreamBuilder::set_rtt_probes](crate::ice::StreamBuilder::set_rtt_probes)), bypass it.
+///
+/// [RFC 7983]: https://tools.ietf.org/html/rfc7983
+pub trait PacketTransform: Send + 'static {
+    /// Transforms an outbound packet before it is queued for sending, e.g. SRTP protect.
+    ///
+    /// An error fails the send with
+    /// [SendError::TransformFailed](crate::ice::SendError::TransformFailed), which carries the
+    /// kind of the error.
+    fn outbound(&mut self, packet: Vec<u8>) -> io::Result<Vec<u8>>;
+
+    /// Transforms an inbound packet before it is passed to the application, e.g. SRTP unprotect.
+    ///
+    /// Returns `None` to drop the packet, e.g. if its authentication failed.
+    /// This is called from the thread iterating the [MainContext](glib::MainContext) of the agent,
+    /// see [PacketSink](crate::ice::PacketSink).
+    fn inbound(&mut self, packet: &[u8]) -> Option<Vec<u8>>;
+}
@@ -0,0 +1,39 @@
+//! Application data attached to streams and components.
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+/// Arbitrary application data attached to a [Stream](crate::ice::Stream) or
+/// [StreamComponent](crate::ice::StreamComponent).
+pub type UserData = Arc<dyn Any + Send + Sync>;
+
+/// Holds the user data of a stream or component, shared with the [Agent](crate::ice::Agent) so
+/// it can be looked up by id.
+#[derive(Default)]
+pub(crate) struct UserDataSlot(Mutex<Option<UserData>>);
+
+impl UserDataSlot {
+    pub(crate) fn set(&self, data: Option<UserData>) {
+        *self.0.lock().unwrap() = data;
+    }
+
+    /// Returns the data if it has been set and is of type `T`.
+    pub(crate) fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let data = self.0.lock().unwrap().clone()?;
+        data.downcast().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn downcasts_to_stored_type() {
+        let slot = UserDataSlot::default();
+        assert!(slot.get::<String>().is_none());
+
+        slot.set(Some(Arc::new("call-42".to_owned())));
+        assert_eq!(slot.get::<String>().as_deref().map(String::as_str), Some("call-42"));
+        assert!(slot.get::<u32>().is_none());
+    }
+}
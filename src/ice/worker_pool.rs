@@ -0,0 +1,88 @@
+//! Dispatch of custom [PacketSink]s to worker threads, see
+//! [Agent::set_worker_pool](crate::ice::Agent::set_worker_pool).
+use crate::ice::{ComponentShared, PacketSink};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of threads on which the [PacketSink]s of components are called instead of on the
+/// thread iterating the [MainContext](glib::MainContext) of the [Agent](crate::ice::Agent).
+///
+/// Each component is assigned to a single worker, so its packets are still processed in order.
+/// The threads exit once the pool and all components using it have been dropped.
+pub struct WorkerPool {
+    workers: Vec<Mutex<SyncSender<Job>>>,
+    next: AtomicUsize,
+}
+
+impl WorkerPool {
+    /// Starts `threads` (at least one) worker threads, each queueing up to `queue_size` packets.
+    ///
+    /// Packets arriving while the queue of a worker is full are dropped, see
+    /// [AgentEvent::SlowConsumer](crate::ice::AgentEvent::SlowConsumer).
+    pub fn new(threads: usize, queue_size: usize) -> io::Result<Self> {
+        let mut workers = Vec::new();
+        for index in 0..threads.max(1) {
+            let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size);
+            thread::Builder::new()
+                .name(format!("libnice-worker-{}", index))
+                .spawn(move || {
+                    for job in receiver {
+                        job();
+                    }
+                })?;
+            workers.push(Mutex::new(sender));
+        }
+        Ok(Self {
+            workers,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Wraps `sink` so it is called on the next worker in turn.
+    pub(super) fn dispatch(&self, sink: Box<dyn PacketSink>, shared: Arc<ComponentShared>) -> PooledPacketSink {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+        PooledPacketSink {
+            worker: self.workers[index].lock().unwrap().clone(),
+            sink: Arc::new(Mutex::new(sink)),
+            shared,
+        }
+    }
+}
+
+/// A [PacketSink] passing copies of the packets to a custom sink on a worker thread.
+pub(super) struct PooledPacketSink {
+    worker: SyncSender<Job>,
+    sink: Arc<Mutex<Box<dyn PacketSink>>>,
+    shared: Arc<ComponentShared>,
+}
+
+impl PacketSink for PooledPacketSink {
+    fn on_packet(&mut self, packet: &[u8]) {
+        let len = packet.len();
+        if !self.shared.memory.try_reserve_inbound(len) {
+            self.shared.on_inbound_dropped();
+            return;
+        }
+        let (sink, shared, packet) = (self.sink.clone(), self.shared.clone(), packet.to_vec());
+        let job = Box::new(move || {
+            sink.lock().unwrap().on_packet(&packet);
+            shared.memory.release_inbound(len);
+        });
+        if let Err(err) = self.worker.try_send(job) {
+            self.shared.memory.release_inbound(len);
+            if let TrySendError::Full(_) = err {
+                self.shared.on_inbound_dropped();
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! Conversions between the types of this crate and those of other WebRTC/ICE crates.
+//!
+//! Candidates are exchanged in their SDP attribute form, so anything which can be parsed by
+//! [IceAttribute::parse](crate::sdp::IceAttribute::parse) converts losslessly.
+
+/// Conversions for the types of the [webrtc](https://crates.io/crates/webrtc) crate.
+#[cfg(feature = "interop-webrtc")]
+pub mod webrtc;
+
+/// Conversions for the types of the [str0m](https://crates.io/crates/str0m) crate.
+#[cfg(feature = "interop-str0m")]
+pub mod str0m;
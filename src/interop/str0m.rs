@@ -0,0 +1,56 @@
+use crate::ice::Candidate;
+use crate::sdp::{IceAttribute, IceCredentials};
+use str0m::{Candidate as Str0mCandidate, IceCreds};
+
+const CANDIDATE_PREFIX: &str = "candidate:";
+
+/// Converts a candidate of this crate into a str0m candidate.
+///
+/// Returns `None` if str0m does not support the candidate, e.g. for TCP candidates.
+pub fn to_str0m_candidate(candidate: &Candidate) -> Option<Str0mCandidate> {
+    Str0mCandidate::from_sdp_string(&format!("{}{}", CANDIDATE_PREFIX, candidate)).ok()
+}
+
+/// Converts a str0m candidate into a candidate of this crate.
+///
+/// Returns `None` if the candidate could not be parsed.
+pub fn from_str0m_candidate(candidate: &Str0mCandidate) -> Option<Candidate> {
+    match IceAttribute::parse(&candidate.to_sdp_string())? {
+        IceAttribute::Candidate(candidate) => Some(candidate),
+        _ => None,
+    }
+}
+
+impl From<IceCredentials> for IceCreds {
+    fn from(credentials: IceCredentials) -> Self {
+        IceCreds {
+            ufrag: credentials.ufrag,
+            pass: credentials.pwd,
+        }
+    }
+}
+
+impl From<IceCreds> for IceCredentials {
+    fn from(creds: IceCreds) -> Self {
+        IceCredentials {
+            ufrag: creds.ufrag,
+            pwd: creds.pass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::parse_candidate;
+
+    #[test]
+    fn candidate_round_trip() {
+        let candidate = parse_candidate("candidate:1 1 UDP 2122252543 192.168.1.2 54321 typ host");
+        let converted = to_str0m_candidate(&candidate).expect("supported by str0m");
+        assert_eq!(converted.addr(), "192.168.1.2:54321".parse().unwrap());
+        let back = from_str0m_candidate(&converted).expect("parsable");
+        assert_eq!(back.address, candidate.address);
+        assert_eq!(back.port, candidate.port);
+    }
+}
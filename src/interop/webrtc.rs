@@ -0,0 +1,74 @@
+use crate::ice::Candidate;
+use crate::sdp::{IceAttribute, IceCredentials};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_parameters::RTCIceParameters;
+
+const CANDIDATE_PREFIX: &str = "candidate:";
+
+/// Converts a local candidate into the form expected by
+/// `RTCPeerConnection::add_ice_candidate`.
+///
+/// `sdp_mid` and `sdp_mline_index` identify the media section the candidate belongs to, they are
+/// not known to the ICE agent.
+pub fn to_candidate_init(
+    candidate: &Candidate,
+    sdp_mid: Option<String>,
+    sdp_mline_index: Option<u16>,
+) -> RTCIceCandidateInit {
+    RTCIceCandidateInit {
+        candidate: format!("{}{}", CANDIDATE_PREFIX, candidate),
+        sdp_mid,
+        sdp_mline_index,
+        username_fragment: None,
+    }
+}
+
+/// Parses a candidate received from a webrtc peer.
+///
+/// Returns `None` for the empty end-of-candidates indication or if the candidate could not be
+/// parsed. The username fragment, if any, should be compared with the remote credentials by the
+/// caller.
+pub fn from_candidate_init(init: &RTCIceCandidateInit) -> Option<Candidate> {
+    match IceAttribute::parse(&init.candidate)? {
+        IceAttribute::Candidate(candidate) => Some(candidate),
+        _ => None,
+    }
+}
+
+impl From<IceCredentials> for RTCIceParameters {
+    fn from(credentials: IceCredentials) -> Self {
+        RTCIceParameters {
+            username_fragment: credentials.ufrag,
+            password: credentials.pwd,
+            ice_lite: false,
+        }
+    }
+}
+
+impl From<RTCIceParameters> for IceCredentials {
+    fn from(parameters: RTCIceParameters) -> Self {
+        IceCredentials {
+            ufrag: parameters.username_fragment,
+            pwd: parameters.password,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn candidate_init_round_trip() {
+        let init = RTCIceCandidateInit {
+            candidate: "candidate:1 1 UDP 2122252543 192.168.1.2 54321 typ host".to_owned(),
+            ..Default::default()
+        };
+        let candidate = from_candidate_init(&init).expect("valid candidate");
+        let converted = to_candidate_init(&candidate, Some("0".to_owned()), Some(0));
+        assert_eq!(from_candidate_init(&converted).unwrap().to_string(), candidate.to_string());
+        assert_eq!(converted.sdp_mid.as_deref(), Some("0"));
+
+        assert!(from_candidate_init(&RTCIceCandidateInit::default()).is_none());
+    }
+}
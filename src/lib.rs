@@ -21,6 +21,35 @@ pub mod ffi;
 /// High-level, futures-based ICE agent.
 pub mod ice;
 
+/// Helpers for the ICE related SDP attributes.
+pub mod sdp;
+
+/// Runtime-agnostic timers for the agent's internal features.
+pub mod timer;
+
+/// Datagram transport abstraction for upper layer protocols.
+pub mod transport;
+
+/// A minimal signaling protocol for trickle ICE.
+#[cfg(feature = "signaling")]
+pub mod signaling;
+
+/// Conversions to and from the types of other WebRTC crates.
+#[cfg(any(feature = "interop-webrtc", feature = "interop-str0m"))]
+pub mod interop;
+
+/// WebRTC data channels over an externally provided SCTP association.
+#[cfg(feature = "datachannel")]
+pub mod datachannel;
+
+/// Capturing of component traffic into pcap files.
+#[cfg(feature = "pcap")]
+pub mod pcap;
+
+/// Loopback fixtures for testing applications without a real network.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 mod platform;
 
 #[cfg(test)]
@@ -0,0 +1,233 @@
+//! Captures the traffic of a [StreamComponent] into a pcap file.
+//!
+//! Since libnice only hands out the payload of the datagrams, synthetic IP and UDP headers are
+//! generated from the addresses of the component's selected pair. Packets which do not fit into
+//! a single IP packet (e.g. in reliable mode) are truncated to the snapshot length.
+//!
+//! [StreamComponent]: crate::ice::StreamComponent
+use std::convert::TryFrom;
+use std::io;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 65535;
+const LINKTYPE_RAW: u32 = 101;
+const IPPROTO_UDP: u8 = 17;
+const TTL: u8 = 64;
+const UDP_HEADER_LEN: usize = 8;
+
+/// Writes IP/UDP packets in the classic pcap format.
+pub struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a new writer, immediately writing the pcap file header to `out`.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes()); // version major
+        header.extend_from_slice(&4u16.to_le_bytes()); // version minor
+        header.extend_from_slice(&0i32.to_le_bytes()); // thiszone
+        header.extend_from_slice(&0u32.to_le_bytes()); // sigfigs
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        out.write_all(&header)?;
+        Ok(Self { out })
+    }
+
+    /// Writes a single UDP datagram with synthetic IP and UDP headers.
+    ///
+    /// If the addresses are of different families, the IPv4 one is mapped to IPv6. Packets
+    /// exceeding the snapshot length are truncated, their record keeps the original length.
+    pub fn write_udp(&mut self, src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        let ip_header_len = match (src.ip(), dst.ip()) {
+            (IpAddr::V4(_), IpAddr::V4(_)) => 20,
+            _ => 40,
+        };
+        let max_payload = PCAP_SNAPLEN as usize - ip_header_len - UDP_HEADER_LEN;
+        let captured = &payload[..payload.len().min(max_payload)];
+        let packet = udp_packet(src, dst, captured);
+        let orig_len = packet.len() + (payload.len() - captured.len());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut record = Vec::with_capacity(16 + packet.len());
+        record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // incl_len
+        record.extend_from_slice(&u32::try_from(orig_len).unwrap_or(u32::MAX).to_le_bytes());
+        record.extend_from_slice(&packet);
+        self.out.write_all(&record)?;
+        self.out.flush()
+    }
+}
+
+/// Builds a raw IPv4 or IPv6 packet containing a UDP datagram.
+///
+/// The payload must fit into a single packet of at most [PCAP_SNAPLEN] bytes.
+fn udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = u16::try_from(UDP_HEADER_LEN + payload.len()).expect("payload truncated to the snaplen");
+    let mut udp = Vec::with_capacity(usize::from(udp_len));
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]); // checksum, filled in below (optional for IPv4)
+    udp.extend_from_slice(payload);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut packet = Vec::with_capacity(20 + udp.len());
+            packet.extend_from_slice(&[0x45, 0x00]);
+            packet.extend_from_slice(&(20 + udp_len).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0, 0, TTL, IPPROTO_UDP, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let checksum = checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(&udp);
+            packet
+        }
+        (src, dst) => {
+            let src = to_ipv6(src).octets();
+            let dst = to_ipv6(dst).octets();
+
+            let mut pseudo_header = Vec::with_capacity(40);
+            pseudo_header.extend_from_slice(&src);
+            pseudo_header.extend_from_slice(&dst);
+            pseudo_header.extend_from_slice(&u32::from(udp_len).to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, 0, 0, IPPROTO_UDP]);
+            let udp_checksum = match checksum(&[&pseudo_header, &udp]) {
+                0 => 0xffff,
+                checksum => checksum,
+            };
+            udp[6..8].copy_from_slice(&udp_checksum.to_be_bytes());
+
+            let mut packet = Vec::with_capacity(40 + udp.len());
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&udp_len.to_be_bytes());
+            packet.extend_from_slice(&[IPPROTO_UDP, TTL]);
+            packet.extend_from_slice(&src);
+            packet.extend_from_slice(&dst);
+            packet.extend_from_slice(&udp);
+            packet
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// The internet checksum (RFC 1071) over the concatenation of `parts`.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    let mut odd_byte = None;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        match odd_byte.take() {
+            None => odd_byte = Some(*byte),
+            Some(high) => sum += u32::from(u16::from_be_bytes([high, *byte])),
+        }
+    }
+    if let Some(high) = odd_byte {
+        sum += u32::from(u16::from_be_bytes([high, 0]));
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Direction of a captured packet, relative to the local agent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// The capture state of a single component, shared between its send and receive paths.
+#[derive(Default)]
+pub(crate) struct ComponentCapture {
+    /// The local and remote address of the selected pair, if any.
+    pair: Mutex<Option<(SocketAddr, SocketAddr)>>,
+    writer: Mutex<Option<PcapWriter<Box<dyn Write + Send>>>>,
+}
+
+impl ComponentCapture {
+    /// Called whenever libnice selects a pair for the component.
+    pub(crate) fn set_selected_pair(&self, local: SocketAddr, remote: SocketAddr) {
+        *self.pair.lock().unwrap() = Some((local, remote));
+    }
+
+    pub(crate) fn start(&self, out: Box<dyn Write + Send>) -> io::Result<()> {
+        *self.writer.lock().unwrap() = Some(PcapWriter::new(out)?);
+        Ok(())
+    }
+
+    pub(crate) fn stop(&self) {
+        *self.writer.lock().unwrap() = None;
+    }
+
+    /// Records a packet if capturing is active. Capturing stops on the first write error.
+    pub(crate) fn record(&self, direction: Direction, payload: &[u8]) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(pcap) = writer.as_mut() {
+            let unspecified = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+            let (local, remote) = self.pair.lock().unwrap().unwrap_or((unspecified, unspecified));
+            let (src, dst) = match direction {
+                Direction::Inbound => (remote, local),
+                Direction::Outbound => (local, remote),
+            };
+            if pcap.write_udp(src, dst, payload).is_err() {
+                *writer = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_ipv4_udp_record() {
+        let mut out = Vec::new();
+        {
+            let mut pcap = PcapWriter::new(&mut out).unwrap();
+            let src = "10.0.0.1:1000".parse().unwrap();
+            let dst = "10.0.0.2:2000".parse().unwrap();
+            pcap.write_udp(src, dst, &[1, 2, 3]).unwrap();
+        }
+        assert_eq!(out.len(), 24 + 16 + 20 + 8 + 3);
+        assert_eq!(&out[0..4], &PCAP_MAGIC.to_le_bytes());
+
+        let ip_header = &out[40..60];
+        assert_eq!(checksum(&[ip_header]), 0, "header checksum must verify");
+        assert_eq!(&ip_header[2..4], &31u16.to_be_bytes());
+        assert_eq!(&out[60..62], &1000u16.to_be_bytes());
+        assert_eq!(&out[68..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn truncates_oversized_payload() {
+        let mut out = Vec::new();
+        {
+            let mut pcap = PcapWriter::new(&mut out).unwrap();
+            let src = "10.0.0.1:1000".parse().unwrap();
+            let dst = "10.0.0.2:2000".parse().unwrap();
+            pcap.write_udp(src, dst, &vec![0; 70_000]).unwrap();
+        }
+        let record = &out[24..40];
+        assert_eq!(&record[8..12], &PCAP_SNAPLEN.to_le_bytes());
+        assert_eq!(&record[12..16], &(20u32 + 8 + 70_000).to_le_bytes());
+        assert_eq!(out.len(), 24 + 16 + PCAP_SNAPLEN as usize);
+        assert_eq!(&out[42..44], &0xffffu16.to_be_bytes());
+    }
+}
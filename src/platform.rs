@@ -2,11 +2,68 @@
 mod specifics {
     extern crate winapi;
     pub use winapi::shared::ws2def::{AF_INET, AF_INET6};
+    use std::io;
+    use std::os::raw::{c_char, c_int};
+    use std::ptr;
+    use winapi::shared::minwindef::{BOOL, DWORD};
+    use winapi::shared::ws2def::SOL_SOCKET;
+    use winapi::um::winsock2::{setsockopt, WSAIoctl, SOCKET, SOCKET_ERROR};
+
+    /// `_WSAIOW(IOC_VENDOR, 12)`, not exported by winapi.
+    const SIO_UDP_CONNRESET: DWORD = 0x9800_000C;
+
+    pub(crate) fn set_socket_option(socket: c_int, option: c_int, value: c_int) -> io::Result<()> {
+        let value_ptr = &value as *const c_int as *const c_char;
+        let len = std::mem::size_of::<c_int>() as c_int;
+        match unsafe { setsockopt(socket as SOCKET, SOL_SOCKET, option, value_ptr, len) } {
+            SOCKET_ERROR => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Changes whether ICMP port unreachable messages make the next receive on the UDP socket
+    /// fail with `WSAECONNRESET` (enabled by default).
+    pub(crate) fn set_udp_connreset(socket: c_int, enabled: bool) -> io::Result<()> {
+        let mut value = enabled as BOOL;
+        let mut returned: DWORD = 0;
+        let result = unsafe {
+            WSAIoctl(
+                socket as SOCKET,
+                SIO_UDP_CONNRESET,
+                &mut value as *mut BOOL as *mut _,
+                std::mem::size_of::<BOOL>() as DWORD,
+                ptr::null_mut(),
+                0,
+                &mut returned,
+                ptr::null_mut(),
+                None,
+            )
+        };
+        match result {
+            SOCKET_ERROR => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) use winapi::shared::ws2def::{SO_RCVBUF, SO_SNDBUF};
 }
 
 #[cfg(not(windows))]
 mod specifics {
     pub use libc::{AF_INET, AF_INET6};
+    use std::io;
+    use std::os::raw::c_int;
+
+    pub(crate) fn set_socket_option(socket: c_int, option: c_int, value: c_int) -> io::Result<()> {
+        let value_ptr = &value as *const c_int as *const libc::c_void;
+        let len = std::mem::size_of::<c_int>() as libc::socklen_t;
+        match unsafe { libc::setsockopt(socket, libc::SOL_SOCKET, option, value_ptr, len) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub(crate) use libc::{SO_RCVBUF, SO_SNDBUF};
 }
 
-pub use specifics::*;
\ No newline at end of file
+pub use specifics::*;
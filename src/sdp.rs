@@ -0,0 +1,194 @@
+//! Helpers for generating and parsing the ICE related SDP attributes.
+//!
+//! See [RFC 8839] for the attributes themselves. For sessions without media, complete offers and
+//! answers can be generated and parsed via [SessionDescription].
+//!
+//! [RFC 8839]: https://tools.ietf.org/html/rfc8839
+use crate::ice::Candidate;
+use std::fmt;
+use webrtc_sdp::attribute_type::{parse_attribute, SdpAttribute};
+use webrtc_sdp::SdpType;
+
+#[cfg(feature = "certificates")]
+mod certificate;
+#[cfg(feature = "certificates")]
+pub use certificate::{Certificate, CertificateError};
+mod dtls_role;
+pub use dtls_role::{DtlsRole, SetupRole};
+mod fingerprint;
+pub use fingerprint::Fingerprint;
+mod session;
+pub use session::{DescriptionKind, SessionDescription, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_SCTP_PORT};
+
+const ICE_OPTIONS_PREFIX: &str = "ice-options:";
+const CANDIDATE_PREFIX: &str = "candidate:";
+const END_OF_CANDIDATES: &str = "end-of-candidates";
+const OPTION_TRICKLE: &str = "trickle";
+const OPTION_RENOMINATION: &str = "renomination";
+
+/// The ICE credentials of one side, advertised via the `a=ice-ufrag` and `a=ice-pwd` attributes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IceCredentials {
+    /// The username fragment.
+    pub ufrag: String,
+    /// The password.
+    pub pwd: String,
+}
+
+/// The options advertised via the `a=ice-options` attribute.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IceOptions {
+    /// Whether the agent supports trickle ICE ([RFC 8838]).
+    ///
+    /// [RFC 8838]: https://tools.ietf.org/html/rfc8838
+    pub trickle: bool,
+    /// Whether the agent supports renomination of the selected pair.
+    pub renomination: bool,
+    /// Any other options which are not understood by this crate.
+    pub other: Vec<String>,
+}
+
+impl IceOptions {
+    /// Parses the value of an `ice-options` attribute.
+    ///
+    /// The value may optionally be prefixed with `a=ice-options:` or `ice-options:`.
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+        let value = value.strip_prefix("a=").unwrap_or(value);
+        let value = value.strip_prefix(ICE_OPTIONS_PREFIX).unwrap_or(value);
+
+        let mut options = IceOptions::default();
+        for token in value.split_whitespace() {
+            match token {
+                OPTION_TRICKLE => options.trickle = true,
+                OPTION_RENOMINATION => options.renomination = true,
+                other => options.other.push(other.to_owned()),
+            }
+        }
+        options
+    }
+
+    /// Returns whether no option is set, in which case the attribute should be omitted.
+    pub fn is_empty(&self) -> bool {
+        !self.trickle && !self.renomination && self.other.is_empty()
+    }
+
+    /// Returns the full `a=ice-options:` line or `None` if no options are set.
+    pub fn to_sdp_line(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(format!("a={}{}", ICE_OPTIONS_PREFIX, self))
+    }
+}
+
+/// Formats the options as the attribute value, e.g. `trickle renomination`.
+impl fmt::Display for IceOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tokens = Vec::new();
+        if self.trickle {
+            tokens.push(OPTION_TRICKLE);
+        }
+        if self.renomination {
+            tokens.push(OPTION_RENOMINATION);
+        }
+        tokens.extend(self.other.iter().map(String::as_str));
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+/// A single ICE related attribute of a session description, as exchanged via signaling.
+///
+/// With trickle ICE, these are also sent individually after the initial offer/answer.
+#[derive(Clone, Debug)]
+pub enum IceAttribute {
+    /// `a=ice-options`
+    Options(IceOptions),
+    /// `a=candidate`
+    Candidate(Candidate),
+    /// `a=end-of-candidates`, sent once the agent has finished gathering candidates.
+    EndOfCandidates,
+}
+
+impl IceAttribute {
+    /// Parses a single attribute line, with or without the leading `a=`.
+    ///
+    /// Returns `None` if the line is not an ICE attribute or could not be parsed.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let line = line.strip_prefix("a=").unwrap_or(line);
+
+        if line == END_OF_CANDIDATES {
+            return Some(IceAttribute::EndOfCandidates);
+        }
+        if line.starts_with(ICE_OPTIONS_PREFIX) {
+            return Some(IceAttribute::Options(IceOptions::parse(line)));
+        }
+        if line.starts_with(CANDIDATE_PREFIX) {
+            return match parse_attribute(line) {
+                Ok(SdpType::Attribute(SdpAttribute::Candidate(candidate))) => {
+                    Some(IceAttribute::Candidate(candidate))
+                }
+                _ => None,
+            };
+        }
+        None
+    }
+}
+
+/// Parses a candidate line (with or without the leading `a=`), panicking if it is invalid.
+#[cfg(test)]
+pub(crate) fn parse_candidate(line: &str) -> Candidate {
+    match IceAttribute::parse(line) {
+        Some(IceAttribute::Candidate(candidate)) => candidate,
+        other => panic!("invalid candidate {:?}: {:?}", line, other),
+    }
+}
+
+/// Formats the attribute as a full SDP line including the leading `a=`.
+impl fmt::Display for IceAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IceAttribute::Options(options) => write!(f, "a={}{}", ICE_OPTIONS_PREFIX, options),
+            IceAttribute::Candidate(candidate) => write!(f, "a={}{}", CANDIDATE_PREFIX, candidate),
+            IceAttribute::EndOfCandidates => write!(f, "a={}", END_OF_CANDIDATES),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ice_options_round_trip() {
+        let options = IceOptions::parse("a=ice-options:trickle renomination google-ice");
+        assert!(options.trickle);
+        assert!(options.renomination);
+        assert_eq!(options.other, vec![String::from("google-ice")]);
+        assert_eq!(
+            options.to_sdp_line().as_deref(),
+            Some("a=ice-options:trickle renomination google-ice")
+        );
+
+        assert_eq!(IceOptions::parse("trickle"), IceOptions { trickle: true, ..Default::default() });
+        assert_eq!(IceOptions::default().to_sdp_line(), None);
+    }
+
+    #[test]
+    fn ice_attribute_parse() {
+        assert!(matches!(IceAttribute::parse("a=end-of-candidates"), Some(IceAttribute::EndOfCandidates)));
+        assert!(matches!(IceAttribute::parse("ice-options:trickle"), Some(IceAttribute::Options(_))));
+        assert!(IceAttribute::parse("a=ice-ufrag:abcd").is_none());
+
+        let line = "a=candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host";
+        match IceAttribute::parse(line) {
+            Some(IceAttribute::Candidate(candidate)) => {
+                assert_eq!(candidate.component, 1);
+                assert_eq!(candidate.port, 50000);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(IceAttribute::EndOfCandidates.to_string(), "a=end-of-candidates");
+    }
+}
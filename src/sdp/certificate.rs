@@ -0,0 +1,73 @@
+//! Self-signed certificates for DTLS, see [Certificate].
+use crate::sdp::Fingerprint;
+use std::error::Error;
+use std::fmt;
+
+/// A self-signed certificate and its private key, both DER encoded, to be passed to the DTLS
+/// implementation in use.
+///
+/// WebRTC peers do not rely on a certificate authority but on the fingerprint advertised in the
+/// session description (see [Certificate::fingerprint]), so a new certificate is usually generated
+/// per agent or even per session.
+#[derive(Clone)]
+pub struct Certificate {
+    /// The X.509 certificate.
+    pub der: Vec<u8>,
+    /// The ECDSA P-256 private key in PKCS #8 form.
+    pub private_key_der: Vec<u8>,
+}
+
+impl Certificate {
+    /// Generates a certificate with a new ECDSA P-256 key, the algorithm every WebRTC
+    /// implementation supports.
+    pub fn generate_self_signed() -> Result<Self, CertificateError> {
+        // Only the fingerprint is verified, so the name does not matter
+        let certificate = rcgen::generate_simple_self_signed(vec!["WebRTC".to_owned()]).map_err(CertificateError)?;
+        Ok(Certificate {
+            der: certificate.serialize_der().map_err(CertificateError)?,
+            private_key_der: certificate.serialize_private_key_der(),
+        })
+    }
+
+    /// Returns the SHA-256 fingerprint to advertise via `a=fingerprint`.
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::sha256(&self.der)
+    }
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the private key
+        f.debug_struct("Certificate").field("fingerprint", &self.fingerprint()).finish()
+    }
+}
+
+/// Generating a [Certificate] failed.
+#[derive(Debug)]
+pub struct CertificateError(rcgen::RcgenError);
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to generate certificate: {}", self.0)
+    }
+}
+
+impl Error for CertificateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generates_certificates() {
+        let certificate = Certificate::generate_self_signed().unwrap();
+        let fingerprint = certificate.fingerprint();
+        assert_eq!(fingerprint.verify(&certificate.der), Some(true));
+        let other = Certificate::generate_self_signed().unwrap();
+        assert_eq!(fingerprint.verify(&other.der), Some(false));
+    }
+}
@@ -0,0 +1,115 @@
+//! Deriving the DTLS role from the `a=setup` attributes ([RFC 8842]) or the ICE role.
+//!
+//! This crate does not implement DTLS itself, the derived [DtlsRole] is meant to be passed to the
+//! DTLS implementation running over a connected component. Both sides deriving the same role
+//! (and thus no handshake ever completing) is the most common interop failure.
+//!
+//! [RFC 8842]: https://tools.ietf.org/html/rfc8842
+use std::fmt;
+
+/// The value of an `a=setup` attribute.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SetupRole {
+    /// The endpoint initiates the DTLS handshake.
+    Active,
+    /// The endpoint waits for the DTLS handshake.
+    Passive,
+    /// The endpoint can do either, only allowed in offers.
+    ActPass,
+    /// The endpoint does not want to establish a connection for now.
+    HoldConn,
+}
+
+impl SetupRole {
+    /// Parses the value of an `a=setup` attribute, e.g. `actpass`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "active" => Some(SetupRole::Active),
+            "passive" => Some(SetupRole::Passive),
+            "actpass" => Some(SetupRole::ActPass),
+            "holdconn" => Some(SetupRole::HoldConn),
+            _ => None,
+        }
+    }
+}
+
+/// Formats the value of the attribute, without the leading `a=setup:`.
+impl fmt::Display for SetupRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SetupRole::Active => "active",
+            SetupRole::Passive => "passive",
+            SetupRole::ActPass => "actpass",
+            SetupRole::HoldConn => "holdconn",
+        })
+    }
+}
+
+/// The role of an endpoint in the DTLS handshake.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DtlsRole {
+    /// Sends the ClientHello.
+    Client,
+    /// Waits for the ClientHello.
+    Server,
+}
+
+impl DtlsRole {
+    /// Derives the local role from the local and the remote `a=setup` attribute.
+    ///
+    /// Returns `None` if the attributes do not determine a role (e.g. both are `actpass`, both
+    /// are `active` or one is `holdconn`).
+    pub fn from_setup(local: SetupRole, remote: SetupRole) -> Option<Self> {
+        match (local, remote) {
+            (SetupRole::Active, SetupRole::Passive)
+            | (SetupRole::Active, SetupRole::ActPass)
+            | (SetupRole::ActPass, SetupRole::Passive) => Some(DtlsRole::Client),
+            (SetupRole::Passive, SetupRole::Active)
+            | (SetupRole::Passive, SetupRole::ActPass)
+            | (SetupRole::ActPass, SetupRole::Active) => Some(DtlsRole::Server),
+            _ => None,
+        }
+    }
+
+    /// Derives the local role from the ICE role if no `a=setup` attributes are exchanged, like
+    /// ORTC does: the controlling agent is the DTLS server, the controlled agent the client.
+    ///
+    /// The ICE role may still change due to a role conflict until the first pair has been
+    /// selected, see [Stream::role_changes](crate::ice::Stream::role_changes).
+    pub fn from_ice_role(controlling: bool) -> Self {
+        if controlling {
+            DtlsRole::Server
+        } else {
+            DtlsRole::Client
+        }
+    }
+
+    /// Returns the `a=setup` attribute to answer an offer with, `offered` being the remote one.
+    ///
+    /// Returns `None` if the offer does not allow any role.
+    pub fn answer_setup(offered: SetupRole) -> Option<SetupRole> {
+        match offered {
+            // RFC 8842, 5.3: the answerer should be active
+            SetupRole::ActPass | SetupRole::Passive => Some(SetupRole::Active),
+            SetupRole::Active => Some(SetupRole::Passive),
+            SetupRole::HoldConn => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derives_roles() {
+        assert_eq!(SetupRole::parse("actpass"), Some(SetupRole::ActPass));
+        assert_eq!(SetupRole::Passive.to_string(), "passive");
+
+        let answer = DtlsRole::answer_setup(SetupRole::ActPass).unwrap();
+        assert_eq!(DtlsRole::from_setup(answer, SetupRole::ActPass), Some(DtlsRole::Client));
+        assert_eq!(DtlsRole::from_setup(SetupRole::ActPass, answer), Some(DtlsRole::Server));
+        assert_eq!(DtlsRole::from_setup(SetupRole::Active, SetupRole::Active), None);
+        assert_ne!(DtlsRole::from_ice_role(true), DtlsRole::from_ice_role(false));
+    }
+}
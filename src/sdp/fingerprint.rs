@@ -0,0 +1,98 @@
+//! Certificate fingerprints as advertised via the `a=fingerprint` attribute ([RFC 8122]).
+//!
+//! With the `certificates` feature, SHA-256 fingerprints (the one hash function every WebRTC
+//! implementation supports) can be computed and verified. This crate does not implement DTLS, so
+//! certificates are passed in DER form as exported by the DTLS implementation in use, or
+//! generated via [Certificate](crate::sdp::Certificate).
+//!
+//! [RFC 8122]: https://tools.ietf.org/html/rfc8122
+#[cfg(feature = "certificates")]
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+#[cfg(feature = "certificates")]
+const SHA_256: &str = "sha-256";
+
+/// A certificate fingerprint, advertised via the `a=fingerprint` attribute ([RFC 8122]).
+///
+/// [RFC 8122]: https://tools.ietf.org/html/rfc8122
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// The hash function, e.g. `sha-256`.
+    pub hash_function: String,
+    /// The fingerprint as upper case hex bytes separated by colons.
+    pub value: String,
+}
+
+impl Fingerprint {
+    /// Creates a fingerprint from the digest of a certificate computed with `hash_function`.
+    pub fn from_digest(hash_function: &str, digest: &[u8]) -> Self {
+        let mut value = String::with_capacity(digest.len() * 3);
+        for (i, byte) in digest.iter().enumerate() {
+            if i > 0 {
+                value.push(':');
+            }
+            // Writing to a String cannot fail
+            let _ = write!(value, "{:02X}", byte);
+        }
+        Fingerprint {
+            hash_function: hash_function.to_owned(),
+            value,
+        }
+    }
+
+    /// Computes the SHA-256 fingerprint of a DER encoded certificate.
+    #[cfg(feature = "certificates")]
+    pub fn sha256(der_certificate: &[u8]) -> Self {
+        Self::from_digest(SHA_256, &Sha256::digest(der_certificate))
+    }
+
+    /// Returns the digest, or `None` if the value is not made up of hex bytes separated by colons.
+    pub fn digest(&self) -> Option<Vec<u8>> {
+        self.value
+            .split(':')
+            .map(|byte| match byte.len() {
+                2 => u8::from_str_radix(byte, 16).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks whether a DER encoded certificate, e.g. the one presented by the remote peer in the
+    /// DTLS handshake, matches this fingerprint.
+    ///
+    /// Returns `None` if the hash function is not supported (only `sha-256` is).
+    #[cfg(feature = "certificates")]
+    pub fn verify(&self, der_certificate: &[u8]) -> Option<bool> {
+        if !self.hash_function.eq_ignore_ascii_case(SHA_256) {
+            return None;
+        }
+        let expected = match self.digest() {
+            Some(expected) => expected,
+            None => return Some(false),
+        };
+        let actual = Sha256::digest(der_certificate);
+        // Compare all bytes, the fingerprint is public but there is no reason to leak timing
+        let diff = expected.iter().zip(actual.iter()).fold(0, |diff, (a, b)| diff | (a ^ b));
+        Some(expected.len() == actual.len() && diff == 0)
+    }
+}
+
+#[cfg(all(test, feature = "certificates"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sha256_fingerprints() {
+        let fingerprint = Fingerprint::sha256(b"abc");
+        assert_eq!(
+            fingerprint.value,
+            "BA:78:16:BF:8F:01:CF:EA:41:41:40:DE:5D:AE:22:23:B0:03:61:A3:96:17:7A:9C:B4:10:FF:61:F2:00:15:AD"
+        );
+        assert_eq!(Fingerprint::sha256(&[0u8; 100]).digest().unwrap().len(), 32);
+        assert_eq!(fingerprint.verify(b"abc"), Some(true));
+        assert_eq!(fingerprint.verify(b"abd"), Some(false));
+        let sha1 = Fingerprint::from_digest("sha-1", &[0xab; 20]);
+        assert_eq!(sha1.verify(b"abc"), None);
+    }
+}
@@ -0,0 +1,241 @@
+//! Complete, minimal SDP offers and answers for data-only sessions.
+use super::{
+    DtlsRole, Fingerprint, IceAttribute, IceCredentials, IceOptions, SetupRole, CANDIDATE_PREFIX, END_OF_CANDIDATES,
+    ICE_OPTIONS_PREFIX,
+};
+use crate::ice::{BoolResult, Candidate, Stream};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ICE_UFRAG_PREFIX: &str = "ice-ufrag:";
+const ICE_PWD_PREFIX: &str = "ice-pwd:";
+const FINGERPRINT_PREFIX: &str = "fingerprint:";
+const ICE_LITE: &str = "ice-lite";
+const SETUP_PREFIX: &str = "setup:";
+const SCTP_PORT_PREFIX: &str = "sctp-port:";
+const MAX_MESSAGE_SIZE_PREFIX: &str = "max-message-size:";
+
+/// The SCTP port assumed if `a=sctp-port` is missing, see
+/// [RFC 8841, 5.1](https://tools.ietf.org/html/rfc8841#section-5.1).
+pub const DEFAULT_SCTP_PORT: u16 = 5000;
+/// The maximum message size assumed if `a=max-message-size` is missing, see
+/// [RFC 8841, 6.1](https://tools.ietf.org/html/rfc8841#section-6.1).
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 65536;
+
+/// Whether a [SessionDescription] is sent as an offer or as an answer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DescriptionKind {
+    /// The description of the offering side.
+    Offer,
+    /// The description of the answering side.
+    Answer,
+}
+
+/// The ICE (and optionally DTLS) parameters of a session with a single `application` media
+/// section, e.g. for file transfers or other non-media uses of ICE.
+#[derive(Clone, Debug)]
+pub struct SessionDescription {
+    /// The ICE credentials.
+    pub credentials: IceCredentials,
+    /// The ICE options.
+    pub options: IceOptions,
+    /// The fingerprint of the DTLS certificate, if DTLS is used on top of ICE.
+    pub fingerprint: Option<Fingerprint>,
+    /// The `a=setup` attribute, see [SessionDescription::dtls_role]. If not set, descriptions
+    /// with a fingerprint are generated with `actpass` for offers and `active` for answers.
+    pub setup: Option<SetupRole>,
+    /// The candidates gathered so far.
+    pub candidates: Vec<Candidate>,
+    /// Whether all candidates have been gathered, adding `a=end-of-candidates`.
+    pub end_of_candidates: bool,
+    /// Whether the agent is an ICE lite implementation (`a=ice-lite`, [RFC 8445, 2.5]), which
+    /// requires the other side to be controlling.
+    ///
+    /// [RFC 8445, 2.5]: https://tools.ietf.org/html/rfc8445#section-2.5
+    pub ice_lite: bool,
+    /// The SCTP port of the data channels (`a=sctp-port`), [DEFAULT_SCTP_PORT] by default.
+    pub sctp_port: u16,
+    /// The largest message the side of this description can receive (`a=max-message-size`),
+    /// `0` for no limit. [DEFAULT_MAX_MESSAGE_SIZE] by default.
+    pub max_message_size: u64,
+}
+
+impl SessionDescription {
+    /// Creates a description with the local credentials of `stream` and no candidates yet.
+    ///
+    /// Candidates emitted by the stream should be added to [SessionDescription::candidates]
+    /// (or trickled separately, see [IceAttribute]).
+    pub fn from_stream(stream: &Stream) -> Self {
+        Self {
+            credentials: stream.get_local_credentials(),
+            options: IceOptions::default(),
+            fingerprint: None,
+            setup: None,
+            candidates: Vec::new(),
+            end_of_candidates: false,
+            ice_lite: false,
+            sctp_port: DEFAULT_SCTP_PORT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Generates the SDP, each line terminated by `\r\n`.
+    pub fn to_sdp(&self, kind: DescriptionKind) -> String {
+        let session_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_micros() as u64);
+        let mut sdp = String::new();
+        // Writing to a String cannot fail
+        let _ = write!(sdp, "v=0\r\no=- {} 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n", session_id);
+        if self.ice_lite {
+            let _ = write!(sdp, "a={}\r\n", ICE_LITE);
+        }
+        if let Some(options) = self.options.to_sdp_line() {
+            let _ = write!(sdp, "{}\r\n", options);
+        }
+        let _ = write!(sdp, "m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\na=mid:0\r\n");
+        let _ = write!(sdp, "a={}{}\r\na={}{}\r\n", ICE_UFRAG_PREFIX, self.credentials.ufrag, ICE_PWD_PREFIX, self.credentials.pwd);
+        if let Some(fingerprint) = &self.fingerprint {
+            let _ = write!(sdp, "a={}{} {}\r\n", FINGERPRINT_PREFIX, fingerprint.hash_function, fingerprint.value);
+            let setup = self.setup.unwrap_or(match kind {
+                DescriptionKind::Offer => SetupRole::ActPass,
+                DescriptionKind::Answer => SetupRole::Active,
+            });
+            let _ = write!(sdp, "a={}{}\r\n", SETUP_PREFIX, setup);
+        }
+        let _ = write!(sdp, "a={}{}\r\n", SCTP_PORT_PREFIX, self.sctp_port);
+        let _ = write!(sdp, "a={}{}\r\n", MAX_MESSAGE_SIZE_PREFIX, self.max_message_size);
+        for candidate in &self.candidates {
+            let _ = write!(sdp, "a={}{}\r\n", CANDIDATE_PREFIX, candidate);
+        }
+        if self.end_of_candidates {
+            let _ = write!(sdp, "a={}\r\n", END_OF_CANDIDATES);
+        }
+        sdp
+    }
+
+    /// Parses the ICE parameters of the first media section of a remote description.
+    ///
+    /// Attributes of the media section take precedence over session level ones. Unknown lines and
+    /// candidates which cannot be parsed are skipped. Returns `None` if the credentials are
+    /// missing.
+    pub fn parse(sdp: &str) -> Option<Self> {
+        let mut ufrag = None;
+        let mut pwd = None;
+        let mut description = SessionDescription {
+            credentials: IceCredentials { ufrag: String::new(), pwd: String::new() },
+            options: IceOptions::default(),
+            fingerprint: None,
+            setup: None,
+            candidates: Vec::new(),
+            end_of_candidates: false,
+            ice_lite: false,
+            sctp_port: DEFAULT_SCTP_PORT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        };
+        let mut media_sections = 0;
+        for line in sdp.lines().map(str::trim) {
+            if line.starts_with("m=") {
+                media_sections += 1;
+            }
+            if media_sections > 1 {
+                break;
+            }
+            let attribute = match line.strip_prefix("a=") {
+                Some(attribute) => attribute,
+                None => continue,
+            };
+            if let Some(value) = attribute.strip_prefix(ICE_UFRAG_PREFIX) {
+                ufrag = Some(value.to_owned());
+            } else if let Some(value) = attribute.strip_prefix(ICE_PWD_PREFIX) {
+                pwd = Some(value.to_owned());
+            } else if let Some(value) = attribute.strip_prefix(FINGERPRINT_PREFIX) {
+                let mut tokens = value.split_whitespace();
+                if let (Some(hash_function), Some(value)) = (tokens.next(), tokens.next()) {
+                    description.fingerprint = Some(Fingerprint {
+                        hash_function: hash_function.to_owned(),
+                        value: value.to_owned(),
+                    });
+                }
+            } else if let Some(value) = attribute.strip_prefix(SETUP_PREFIX) {
+                description.setup = SetupRole::parse(value);
+            } else if let Some(value) = attribute.strip_prefix(SCTP_PORT_PREFIX) {
+                description.sctp_port = value.parse().unwrap_or(DEFAULT_SCTP_PORT);
+            } else if let Some(value) = attribute.strip_prefix(MAX_MESSAGE_SIZE_PREFIX) {
+                description.max_message_size = value.parse().unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+            } else if attribute == ICE_LITE {
+                description.ice_lite = true;
+            } else if attribute.starts_with(ICE_OPTIONS_PREFIX) {
+                description.options = IceOptions::parse(attribute);
+            } else {
+                match IceAttribute::parse(attribute) {
+                    Some(IceAttribute::Candidate(candidate)) => description.candidates.push(candidate),
+                    Some(IceAttribute::EndOfCandidates) => description.end_of_candidates = true,
+                    _ => {}
+                }
+            }
+        }
+        description.credentials = IceCredentials { ufrag: ufrag?, pwd: pwd? };
+        Some(description)
+    }
+
+    /// Derives the local DTLS role from the `a=setup` attributes of this (local) and the remote
+    /// description, see [DtlsRole::from_setup].
+    ///
+    /// A missing attribute is treated as the default used by [SessionDescription::to_sdp], with
+    /// `kind` being the kind of this description.
+    pub fn dtls_role(&self, kind: DescriptionKind, remote: &SessionDescription) -> Option<DtlsRole> {
+        let (local_default, remote_default) = match kind {
+            DescriptionKind::Offer => (SetupRole::ActPass, SetupRole::Active),
+            DescriptionKind::Answer => (SetupRole::Active, SetupRole::ActPass),
+        };
+        DtlsRole::from_setup(
+            self.setup.unwrap_or(local_default),
+            remote.setup.unwrap_or(remote_default),
+        )
+    }
+
+    /// Applies a remote description to `stream`: sets the remote credentials and ICE options and
+    /// adds all candidates, see [Stream::apply_answer].
+    pub fn apply_to(self, stream: &mut Stream) -> BoolResult<()> {
+        stream.apply_answer(&self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdp::parse_candidate;
+
+    #[test]
+    fn round_trip() {
+        let candidate = parse_candidate("a=candidate:1 1 UDP 2130706431 192.168.1.2 50000 typ host");
+        let description = SessionDescription {
+            credentials: IceCredentials { ufrag: "abcd".to_owned(), pwd: "0123456789abcdefghijkl".to_owned() },
+            options: IceOptions::parse("trickle"),
+            fingerprint: Some(Fingerprint { hash_function: "sha-256".to_owned(), value: "AB:CD".to_owned() }),
+            setup: None,
+            candidates: vec![candidate],
+            end_of_candidates: true,
+            ice_lite: true,
+            sctp_port: DEFAULT_SCTP_PORT,
+            max_message_size: 262_144,
+        };
+        let sdp = description.to_sdp(DescriptionKind::Offer);
+        assert!(sdp.contains("a=setup:actpass\r\n"));
+        assert!(sdp.contains("a=sctp-port:5000\r\na=max-message-size:262144\r\n"));
+
+        let parsed = SessionDescription::parse(&sdp).expect("valid description");
+        assert_eq!(parsed.credentials, description.credentials);
+        assert_eq!(parsed.options, description.options);
+        assert_eq!(parsed.fingerprint, description.fingerprint);
+        assert_eq!(parsed.candidates.len(), 1);
+        assert!(parsed.end_of_candidates);
+        assert!(parsed.ice_lite);
+        assert_eq!(parsed.setup, Some(SetupRole::ActPass));
+        assert_eq!((parsed.sctp_port, parsed.max_message_size), (DEFAULT_SCTP_PORT, 262_144));
+        assert_eq!(description.dtls_role(DescriptionKind::Answer, &parsed), Some(DtlsRole::Client));
+
+        assert!(SessionDescription::parse("v=0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n").is_none());
+    }
+}
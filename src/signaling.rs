@@ -0,0 +1,282 @@
+//! A minimal signaling protocol for trickle ICE between two instances of this crate.
+//!
+//! Messages are exchanged as single lines of text over any [SignalingTransport], with a reference
+//! implementation over WebSockets behind the `signaling-websocket` feature. [negotiate] performs
+//! the whole exchange for a [Stream].
+use crate::ice::Stream;
+use crate::sdp::{IceAttribute, IceOptions};
+use futures::future::{self, Either};
+use futures::{Sink, SinkExt, Stream as FuturesStream, StreamExt};
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+
+const OFFER: &str = "offer";
+const ANSWER: &str = "answer";
+
+/// The ICE parameters of one side of a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IceParameters {
+    /// The ICE username fragment.
+    pub ufrag: String,
+    /// The ICE password.
+    pub pwd: String,
+    /// The ICE options.
+    pub options: IceOptions,
+}
+
+/// A signaling message.
+#[derive(Clone, Debug)]
+pub enum SignalingMessage {
+    /// The parameters of the offering side.
+    Offer(IceParameters),
+    /// The parameters of the answering side.
+    Answer(IceParameters),
+    /// A candidate or the end of candidates.
+    Attribute(IceAttribute),
+}
+
+impl SignalingMessage {
+    /// Parses a message from its text form, see the [Display](fmt::Display) implementation.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let mut tokens = line.splitn(4, ' ');
+        let kind = tokens.next()?;
+        let is_offer = match kind {
+            OFFER => true,
+            ANSWER => false,
+            _ => return IceAttribute::parse(line).map(SignalingMessage::Attribute),
+        };
+        let parameters = IceParameters {
+            ufrag: tokens.next()?.to_owned(),
+            pwd: tokens.next()?.to_owned(),
+            options: IceOptions::parse(tokens.next().unwrap_or("")),
+        };
+        Some(if is_offer {
+            SignalingMessage::Offer(parameters)
+        } else {
+            SignalingMessage::Answer(parameters)
+        })
+    }
+}
+
+/// Formats the message as a single line, e.g. `offer <ufrag> <pwd> trickle` or
+/// `a=candidate:...`.
+impl fmt::Display for SignalingMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, parameters) = match self {
+            SignalingMessage::Offer(parameters) => (OFFER, parameters),
+            SignalingMessage::Answer(parameters) => (ANSWER, parameters),
+            SignalingMessage::Attribute(attribute) => return write!(f, "{}", attribute),
+        };
+        write!(f, "{} {} {}", kind, parameters.ufrag, parameters.pwd)?;
+        if !parameters.options.is_empty() {
+            write!(f, " {}", parameters.options)?;
+        }
+        Ok(())
+    }
+}
+
+/// A bidirectional channel for signaling messages.
+///
+/// Implemented for everything which is a [Sink] and [futures::Stream] of messages.
+pub trait SignalingTransport:
+    Sink<SignalingMessage, Error = io::Error> + FuturesStream<Item = io::Result<SignalingMessage>> + Unpin + Send
+{
+}
+
+impl<T> SignalingTransport for T where
+    T: Sink<SignalingMessage, Error = io::Error>
+        + FuturesStream<Item = io::Result<SignalingMessage>>
+        + Unpin
+        + Send
+{
+}
+
+fn to_io_error(err: glib::BoolError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+async fn recv<T: SignalingTransport>(transport: &mut T) -> io::Result<SignalingMessage> {
+    match transport.next().await {
+        Some(msg) => msg,
+        None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "signaling channel closed")),
+    }
+}
+
+/// Negotiates `stream` with a remote peer doing the same: exchanges the ICE parameters (the
+/// offering side sends first), then trickles the local candidates while applying the remote ones.
+///
+/// Returns once both sides have signaled the end of their candidates. The stream must not have
+/// been polled for candidates before.
+pub async fn negotiate<T: SignalingTransport>(
+    stream: &mut Stream,
+    transport: &mut T,
+    options: IceOptions,
+    offerer: bool,
+) -> io::Result<()> {
+    let local = IceParameters {
+        ufrag: stream.get_local_ufrag().to_owned(),
+        pwd: stream.get_local_pwd().to_owned(),
+        options,
+    };
+    let remote = if offerer {
+        transport.send(SignalingMessage::Offer(local)).await?;
+        match recv(transport).await? {
+            SignalingMessage::Answer(remote) => remote,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected answer")),
+        }
+    } else {
+        let remote = match recv(transport).await? {
+            SignalingMessage::Offer(remote) => remote,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected offer")),
+        };
+        transport.send(SignalingMessage::Answer(local)).await?;
+        remote
+    };
+
+    let ufrag = CString::new(remote.ufrag).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let pwd = CString::new(remote.pwd).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    stream.set_remote_credentials(ufrag, pwd).map_err(to_io_error)?;
+    stream.set_remote_ice_options(remote.options);
+
+    let mut local_done = false;
+    let mut remote_done = false;
+    while !local_done || !remote_done {
+        let msg = if local_done {
+            recv(transport).await?
+        } else {
+            match future::select(stream.next(), transport.next()).await {
+                Either::Left((Some(candidate), _)) => {
+                    transport.send(SignalingMessage::Attribute(IceAttribute::Candidate(candidate))).await?;
+                    continue;
+                }
+                Either::Left((None, _)) => {
+                    local_done = true;
+                    transport.send(SignalingMessage::Attribute(IceAttribute::EndOfCandidates)).await?;
+                    continue;
+                }
+                Either::Right((Some(msg), _)) => msg?,
+                Either::Right((None, _)) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "signaling channel closed"))
+                }
+            }
+        };
+        match msg {
+            SignalingMessage::Attribute(attribute) => {
+                if let IceAttribute::EndOfCandidates = attribute {
+                    remote_done = true;
+                }
+                stream.apply_remote_attribute(attribute).map_err(to_io_error)?;
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected message")),
+        }
+    }
+    Ok(())
+}
+
+/// Signaling over a WebSocket, exchanging one message per text frame.
+#[cfg(feature = "signaling-websocket")]
+pub mod websocket {
+    use super::SignalingMessage;
+    use futures::{Sink, Stream as FuturesStream};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+    use tokio_tungstenite::WebSocketStream;
+
+    fn to_io_error(err: WsError) -> io::Error {
+        match err {
+            WsError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::Other, err.to_string()),
+        }
+    }
+
+    /// A [SignalingTransport](super::SignalingTransport) over an established WebSocket
+    /// connection, e.g. from [tokio_tungstenite::accept_async] or
+    /// [tokio_tungstenite::connect_async].
+    pub struct WebSocketTransport<S> {
+        inner: WebSocketStream<S>,
+    }
+
+    impl<S> WebSocketTransport<S> {
+        /// Wraps an established WebSocket connection.
+        pub fn new(inner: WebSocketStream<S>) -> Self {
+            Self { inner }
+        }
+
+        /// Returns the underlying WebSocket connection.
+        pub fn into_inner(self) -> WebSocketStream<S> {
+            self.inner
+        }
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> Sink<SignalingMessage> for WebSocketTransport<S> {
+        type Error = io::Error;
+
+        fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_ready(cx).map_err(to_io_error)
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: SignalingMessage) -> io::Result<()> {
+            Pin::new(&mut self.inner)
+                .start_send(Message::Text(item.to_string()))
+                .map_err(to_io_error)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx).map_err(to_io_error)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_close(cx).map_err(to_io_error)
+        }
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> FuturesStream for WebSocketTransport<S> {
+        type Item = io::Result<SignalingMessage>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+            loop {
+                let msg = match futures::ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => return Poll::Ready(Some(Err(to_io_error(err)))),
+                    None => return Poll::Ready(None),
+                };
+                let text = match msg {
+                    Message::Text(text) => text,
+                    Message::Close(_) => return Poll::Ready(None),
+                    _ => continue, // ping, pong and binary frames
+                };
+                return Poll::Ready(Some(SignalingMessage::parse(&text).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid signaling message")
+                })));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let offer = SignalingMessage::Offer(IceParameters {
+            ufrag: "abcd".to_owned(),
+            pwd: "0123456789abcdefghijkl".to_owned(),
+            options: IceOptions::parse("trickle"),
+        });
+        assert_eq!(offer.to_string(), "offer abcd 0123456789abcdefghijkl trickle");
+        match SignalingMessage::parse(&offer.to_string()) {
+            Some(SignalingMessage::Offer(parameters)) => assert!(parameters.options.trickle),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let end = SignalingMessage::Attribute(IceAttribute::EndOfCandidates);
+        assert_eq!(SignalingMessage::parse(&end.to_string()).unwrap().to_string(), end.to_string());
+        assert!(SignalingMessage::parse("answer abcd").is_none());
+    }
+}
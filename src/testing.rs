@@ -0,0 +1,180 @@
+//! Fixtures for testing applications against agents on the loopback interfaces, without a real
+//! network.
+//!
+//! A [LoopbackSetup] runs a [MainLoop] on its own thread and creates agents which only gather
+//! host candidates on the loopback addresses of the chosen [LoopbackFamilies]. With
+//! [LoopbackFamilies::DualStack], the effect of an
+//! [AddressFamilyPolicy](crate::ice::AddressFamilyPolicy) on the emitted candidates can be checked
+//! via [CandidateFamilies].
+use crate::ice::{Agent, BoolResult, Candidate, Stream};
+use glib::{MainContext, MainLoop};
+use std::ffi::CString;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use webrtc_sdp::address::Address;
+
+/// The loopback addresses the agents of a [LoopbackSetup] gather candidates on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoopbackFamilies {
+    /// Only `127.0.0.1`.
+    Ipv4,
+    /// Only `::1`.
+    Ipv6,
+    /// Both `127.0.0.1` and `::1`.
+    DualStack,
+}
+
+impl LoopbackFamilies {
+    /// Returns the local addresses (with port `0`) of these families.
+    pub fn addresses(self) -> Vec<SocketAddr> {
+        let ipv4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        let ipv6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 0);
+        match self {
+            LoopbackFamilies::Ipv4 => vec![ipv4],
+            LoopbackFamilies::Ipv6 => vec![ipv6],
+            LoopbackFamilies::DualStack => vec![ipv4, ipv6],
+        }
+    }
+}
+
+/// A [MainLoop] running on its own thread, on which loopback agents are created.
+///
+/// The loop is stopped once the setup is dropped. The agents still need to be polled, e.g. by
+/// spawning them on an executor.
+pub struct LoopbackSetup {
+    main_loop: MainLoop,
+}
+
+impl LoopbackSetup {
+    /// Starts a new [MainContext] and runs it on a new thread.
+    pub fn new() -> Self {
+        let ctx = MainContext::new();
+        let main_loop = MainLoop::new(Some(&ctx), false);
+        let main_loop_clone = main_loop.clone();
+        std::thread::spawn(move || {
+            if !main_loop_clone.get_context().acquire() {
+                panic!("failed to acquire main loop");
+            }
+            main_loop_clone.run();
+        });
+        Self { main_loop }
+    }
+
+    /// Returns the context the agents are created on.
+    pub fn get_ctx(&self) -> MainContext {
+        self.main_loop.get_context()
+    }
+
+    /// Creates an RFC5245 agent which only gathers candidates on the loopback addresses of
+    /// `families`.
+    pub fn agent(&self, families: LoopbackFamilies) -> BoolResult<Agent> {
+        let agent = Agent::new_rfc5245(self.get_ctx());
+        for addr in families.addresses() {
+            agent.add_local_address(addr)?;
+        }
+        Ok(agent)
+    }
+
+    /// Creates a controlling and a controlled agent, see [LoopbackSetup::agent].
+    pub fn agent_pair(&self, families: LoopbackFamilies) -> BoolResult<(Agent, Agent)> {
+        let controlling = self.agent(families)?;
+        controlling.set_controlling_mode(true);
+        Ok((controlling, self.agent(families)?))
+    }
+}
+
+impl Default for LoopbackSetup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LoopbackSetup {
+    fn drop(&mut self) {
+        self.main_loop.quit();
+    }
+}
+
+/// Sets the local credentials of each stream as the remote credentials of the other one.
+pub fn exchange_credentials(a: &mut Stream, b: &mut Stream) -> BoolResult<()> {
+    let credentials = |stream: &Stream| {
+        (
+            CString::new(stream.get_local_ufrag()).expect("ufrag has no null bytes"),
+            CString::new(stream.get_local_pwd()).expect("pwd has no null bytes"),
+        )
+    };
+    let (a_ufrag, a_pwd) = credentials(a);
+    let (b_ufrag, b_pwd) = credentials(b);
+    a.set_remote_credentials(b_ufrag, b_pwd)?;
+    b.set_remote_credentials(a_ufrag, a_pwd)
+}
+
+/// The number of candidates per address family.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CandidateFamilies {
+    /// Candidates with an IPv4 address.
+    pub ipv4: usize,
+    /// Candidates with an IPv6 address.
+    pub ipv6: usize,
+    /// Candidates with an FQDN instead of an address.
+    pub fqdn: usize,
+}
+
+impl CandidateFamilies {
+    /// Counts the address families of `candidates`.
+    pub fn of<'a>(candidates: impl IntoIterator<Item = &'a Candidate>) -> Self {
+        let mut families = Self::default();
+        for candidate in candidates {
+            match candidate.address {
+                Address::Ip(IpAddr::V4(_)) => families.ipv4 += 1,
+                Address::Ip(IpAddr::V6(_)) => families.ipv6 += 1,
+                Address::Fqdn(_) => families.fqdn += 1,
+            }
+        }
+        families
+    }
+
+    /// Panics unless candidates of the families in `expected` and only those are present.
+    pub fn assert_only(&self, expected: LoopbackFamilies) {
+        let (ipv4, ipv6) = match expected {
+            LoopbackFamilies::Ipv4 => (true, false),
+            LoopbackFamilies::Ipv6 => (false, true),
+            LoopbackFamilies::DualStack => (true, true),
+        };
+        assert_eq!(
+            (self.ipv4 > 0, self.ipv6 > 0, self.fqdn),
+            (ipv4, ipv6, 0),
+            "unexpected candidate families {:?}",
+            self
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ice::AddressFamilyPolicy;
+    use futures::StreamExt;
+    use tokio::runtime;
+
+    #[test]
+    fn applies_family_policy_on_dual_stack() {
+        let mut executor = runtime::Builder::new().basic_scheduler().build().unwrap();
+        let setup = LoopbackSetup::new();
+        let (controlling, controlled) = setup.agent_pair(LoopbackFamilies::DualStack).unwrap();
+
+        let mut any_stream = controlling.stream_builder(1).build().unwrap();
+        let mut ipv6_stream = controlled
+            .stream_builder(1)
+            .set_address_family_policy(AddressFamilyPolicy::Ipv6Only)
+            .build()
+            .unwrap();
+        exchange_credentials(&mut any_stream, &mut ipv6_stream).unwrap();
+        executor.spawn(controlling);
+        executor.spawn(controlled);
+
+        let candidates = executor.block_on(any_stream.by_ref().collect::<Vec<Candidate>>());
+        CandidateFamilies::of(&candidates).assert_only(LoopbackFamilies::DualStack);
+        let candidates = executor.block_on(ipv6_stream.by_ref().collect::<Vec<Candidate>>());
+        CandidateFamilies::of(&candidates).assert_only(LoopbackFamilies::Ipv6);
+    }
+}
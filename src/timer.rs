@@ -0,0 +1,141 @@
+//! Timers for the internal features of the [Agent](crate::ice::Agent), such as timeouts,
+//! keepalives and retransmissions.
+//!
+//! By default, timers are GLib timeout sources on the agent's [MainContext], so they fire as
+//! long as that context is running. With the `tokio` feature, [TokioTimerSource] runs them on a
+//! tokio runtime instead. Other runtimes can be supported by implementing [TimerSource].
+use glib::MainContext;
+use std::time::Duration;
+
+/// The callback of a timer, returning whether the timer should fire again.
+pub type TimerCallback = Box<dyn FnMut() -> bool + Send + 'static>;
+
+/// A factory for periodic timers.
+pub trait TimerSource: Send + Sync {
+    /// Starts a timer which calls `callback` every `period` (the first time after one `period`)
+    /// until it returns `false` or the returned [Timer] is cancelled.
+    ///
+    /// A zero `period` must be supported, the timer then fires as soon as possible.
+    fn start(&self, period: Duration, callback: TimerCallback) -> Timer;
+}
+
+/// A running timer, which is cancelled once dropped (unless [detached](Timer::detach)).
+pub struct Timer {
+    cancel: Option<Box<dyn FnOnce() + Send + 'static>>,
+}
+
+impl Timer {
+    /// Creates a timer handle which calls `cancel` to cancel the timer.
+    pub fn new(cancel: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            cancel: Some(Box::new(cancel)),
+        }
+    }
+
+    /// Cancels the timer, its callback will not be called anymore.
+    pub fn cancel(mut self) {
+        self.cancel_inner();
+    }
+
+    /// Lets the timer run until its callback returns `false`.
+    pub fn detach(mut self) {
+        self.cancel = None;
+    }
+
+    fn cancel_inner(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancel_inner();
+    }
+}
+
+/// Runs timers as GLib timeout sources on a [MainContext].
+#[derive(Clone, Debug)]
+pub struct GlibTimerSource {
+    ctx: MainContext,
+}
+
+impl GlibTimerSource {
+    /// Creates a timer source attaching its timers to `ctx`.
+    pub fn new(ctx: MainContext) -> Self {
+        Self { ctx }
+    }
+}
+
+impl TimerSource for GlibTimerSource {
+    fn start(&self, period: Duration, mut callback: TimerCallback) -> Timer {
+        let period_ms = period.as_millis().min(u128::from(u32::MAX)) as u32;
+        let source = glib::source::timeout_source_new(period_ms, None, glib::PRIORITY_DEFAULT, move || {
+            glib::Continue(callback())
+        });
+        source.attach(Some(&self.ctx));
+        Timer::new(move || source.destroy())
+    }
+}
+
+/// Runs timers as tasks on a tokio runtime.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub struct TokioTimerSource {
+    handle: tokio::runtime::Handle,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioTimerSource {
+    /// Creates a timer source spawning its timers on the runtime of `handle`.
+    pub fn new(handle: tokio::runtime::Handle) -> Self {
+        Self { handle }
+    }
+
+    /// Creates a timer source for the runtime of the current task.
+    ///
+    /// # Panics
+    /// Panics if not called from within a tokio runtime.
+    pub fn current() -> Self {
+        Self::new(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl TimerSource for TokioTimerSource {
+    fn start(&self, period: Duration, mut callback: TimerCallback) -> Timer {
+        // tokio does not support zero periods
+        let period = period.max(Duration::from_nanos(1));
+        let (task, abort) = futures::future::abortable(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                if !callback() {
+                    break;
+                }
+            }
+        });
+        self.handle.spawn(task);
+        Timer::new(move || abort.abort())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn dropping_timer_cancels() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = cancelled.clone();
+        Timer::new(move || flag.store(true, Ordering::SeqCst)).detach();
+        assert!(!cancelled.load(Ordering::SeqCst));
+
+        let flag = cancelled.clone();
+        drop(Timer::new(move || flag.store(true, Ordering::SeqCst)));
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,166 @@
+//! An abstraction over datagram transports, so upper layers (DTLS, SCTP, QUIC, ...) can be written
+//! and tested independently of ICE.
+//!
+//! [StreamComponent](crate::ice::StreamComponent) implements [DatagramTransport], an in-memory
+//! implementation for tests is provided by [MemoryTransport].
+use futures::channel::mpsc;
+use futures::task::AtomicWaker;
+use futures::Stream as FuturesStream;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// A change of the connectivity of a [DatagramTransport].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TransportEvent {
+    /// Datagrams can be exchanged with the remote peer.
+    Connected,
+    /// Connectivity to the remote peer has been lost, it may be regained later.
+    Disconnected,
+    /// Connectivity to the remote peer has been lost permanently.
+    Failed,
+}
+
+/// A transport of discrete, unreliable datagrams.
+pub trait DatagramTransport: Send {
+    /// Returns the maximum size of a single datagram.
+    fn max_datagram_size(&self) -> usize;
+
+    /// Sends a datagram. Delivery is not guaranteed even if this succeeds.
+    ///
+    /// Fails if the transport has been closed or `datagram` exceeds
+    /// [DatagramTransport::max_datagram_size].
+    fn send(&mut self, datagram: Vec<u8>) -> io::Result<()>;
+
+    /// Receives the next datagram, returning `None` once the transport has been closed.
+    fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Vec<u8>>>;
+
+    /// Receives the next connectivity change, returning `None` once the transport has been closed.
+    fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<TransportEvent>>;
+}
+
+pub(crate) fn check_datagram_size(transport: &impl DatagramTransport, datagram: &[u8]) -> io::Result<()> {
+    if datagram.len() > transport.max_datagram_size() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "datagram too large"));
+    }
+    Ok(())
+}
+
+/// Tells one end of a [MemoryTransport] pair that the other end has been dropped.
+#[derive(Default)]
+struct PeerDropped {
+    dropped: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// One end of a pair of connected in-memory transports, see [MemoryTransport::pair].
+pub struct MemoryTransport {
+    max_datagram_size: usize,
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+    receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    connected_reported: bool,
+    /// Set once the peer has been dropped.
+    peer_dropped: Arc<PeerDropped>,
+    /// Shared with the peer, set once this transport is dropped.
+    dropped: Arc<PeerDropped>,
+}
+
+impl MemoryTransport {
+    /// Creates two transports connected to each other.
+    ///
+    /// Both report [TransportEvent::Connected] right away, a transport is closed once its peer
+    /// has been dropped.
+    pub fn pair(max_datagram_size: usize) -> (MemoryTransport, MemoryTransport) {
+        let (a_sender, b_receiver) = mpsc::unbounded();
+        let (b_sender, a_receiver) = mpsc::unbounded();
+        let a_dropped = Arc::new(PeerDropped::default());
+        let b_dropped = Arc::new(PeerDropped::default());
+        let new = |sender, receiver, peer_dropped, dropped| MemoryTransport {
+            max_datagram_size,
+            sender,
+            receiver,
+            connected_reported: false,
+            peer_dropped,
+            dropped,
+        };
+        (
+            new(a_sender, a_receiver, Arc::clone(&b_dropped), Arc::clone(&a_dropped)),
+            new(b_sender, b_receiver, a_dropped, b_dropped),
+        )
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.dropped.dropped.store(true, Ordering::Release);
+        self.dropped.waker.wake();
+    }
+}
+
+impl DatagramTransport for MemoryTransport {
+    fn max_datagram_size(&self) -> usize {
+        self.max_datagram_size
+    }
+
+    fn send(&mut self, datagram: Vec<u8>) -> io::Result<()> {
+        check_datagram_size(self, &datagram)?;
+        self.sender
+            .unbounded_send(datagram)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "peer has been dropped"))
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Vec<u8>>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+
+    fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<TransportEvent>> {
+        if !self.connected_reported {
+            self.connected_reported = true;
+            return Poll::Ready(Some(TransportEvent::Connected));
+        }
+        // A memory transport never changes its connectivity, it only closes once the peer has
+        // been dropped
+        self.peer_dropped.waker.register(cx.waker());
+        if self.peer_dropped.dropped.load(Ordering::Acquire) || self.sender.is_closed() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future::poll_fn;
+
+    #[test]
+    fn memory_transport_pair() {
+        let (mut a, mut b) = MemoryTransport::pair(4);
+        assert_eq!(block_on(poll_fn(|cx| a.poll_event(cx))), Some(TransportEvent::Connected));
+
+        a.send(vec![1, 2, 3]).unwrap();
+        assert!(a.send(vec![0; 5]).is_err());
+        assert_eq!(block_on(poll_fn(|cx| b.poll_recv(cx))), Some(vec![1, 2, 3]));
+
+        drop(a);
+        assert_eq!(block_on(poll_fn(|cx| b.poll_recv(cx))), None);
+        assert!(b.send(vec![1]).is_err());
+    }
+
+    #[test]
+    fn memory_transport_wakes_on_peer_drop() {
+        let (a, mut b) = MemoryTransport::pair(4);
+        assert_eq!(block_on(poll_fn(|cx| b.poll_event(cx))), Some(TransportEvent::Connected));
+        let dropper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            drop(a);
+        });
+        // Blocks forever unless dropping the peer wakes the task
+        assert_eq!(block_on(poll_fn(|cx| b.poll_event(cx))), None);
+        dropper.join().unwrap();
+    }
+}